@@ -0,0 +1,195 @@
+//! This module describes the events emitted by chargebacks, so that withdrawals reversed to cover
+//! the shortfall a chargeback leaves behind can be clawed back.
+
+use crate::report::format_amount;
+use serde::Serialize;
+use std::io::Write;
+use thiserror::Error;
+
+/// An error type for the chargeback module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ChargebackError {
+    /// Triggered if a reversal cannot be written
+    #[error("Failed to write reversal report: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// A withdrawal that was reversed to cover the shortfall left by a chargeback.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReversedWithdrawal {
+    /// The ID of the withdrawal
+    pub tx_id: u32,
+    /// The amount of the withdrawal
+    pub amount: f64,
+}
+
+impl ReversedWithdrawal {
+    /// Creates a new `ReversedWithdrawal`.
+    #[inline]
+    #[must_use]
+    pub const fn new(tx_id: u32, amount: f64) -> Self {
+        Self { tx_id, amount }
+    }
+}
+
+/// The event emitted by a chargeback, listing the withdrawals reversed to cover the shortfall it
+/// left behind.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChargebackEvent {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the transaction that was charged back
+    pub tx_id: u32,
+    /// How far the chargeback took the total balance below zero, before any reversals
+    pub shortfall: f64,
+    /// The withdrawals reversed, in the order the shortfall policy chose them
+    pub reversed: Vec<ReversedWithdrawal>,
+    /// How far the total balance is still below zero after the reversals, to be recovered by
+    /// collections
+    pub uncovered: f64,
+}
+
+impl ChargebackEvent {
+    /// Creates a new `ChargebackEvent` with no reversed withdrawals, and the whole shortfall
+    /// uncovered.
+    #[inline]
+    #[must_use]
+    pub const fn new(client_id: u16, tx_id: u32, shortfall: f64) -> Self {
+        Self {
+            client_id,
+            tx_id,
+            shortfall,
+            reversed: Vec::new(),
+            uncovered: shortfall,
+        }
+    }
+
+    /// The sum of the reversed withdrawals.
+    #[inline]
+    #[must_use]
+    pub fn reversed_total(&self) -> f64 {
+        self.reversed.iter().fold(0_f64, |sum, r| sum + r.amount)
+    }
+}
+
+/// A row of the reversal report.
+#[derive(Debug, Serialize)]
+struct ReversalRow {
+    /// The ID of the client
+    client: u16,
+    /// The ID of the transaction that was charged back
+    chargeback_tx: u32,
+    /// The shortfall left by the chargeback
+    shortfall: String,
+    /// The ID of the reversed withdrawal
+    tx: u32,
+    /// The amount of the reversed withdrawal
+    amount: String,
+}
+
+/// A row of the collections report.
+#[derive(Debug, Serialize)]
+struct CollectionsRow {
+    /// The ID of the client
+    client: u16,
+    /// The ID of the transaction that was charged back
+    chargeback_tx: u32,
+    /// The shortfall left by the chargeback
+    shortfall: String,
+    /// The part of the shortfall not covered by reversals
+    uncovered: String,
+}
+
+/// Writes one CSV row for every withdrawal reversed by the given chargebacks to `writer`.
+/// # Errors
+/// Returns an error if the rows cannot be serialized or written.
+#[inline]
+pub fn write_reversals<W: Write>(
+    events: &[ChargebackEvent],
+    writer: W,
+) -> Result<(), ChargebackError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for event in events {
+        for reversed in &event.reversed {
+            csv_writer.serialize(ReversalRow {
+                client: event.client_id,
+                chargeback_tx: event.tx_id,
+                shortfall: format_amount(event.shortfall),
+                tx: reversed.tx_id,
+                amount: format_amount(reversed.amount),
+            })?;
+        }
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+/// Writes one CSV row for every chargeback that left part of its shortfall uncovered to `writer`.
+/// # Errors
+/// Returns an error if the rows cannot be serialized or written.
+#[inline]
+pub fn write_collections<W: Write>(
+    events: &[ChargebackEvent],
+    writer: W,
+) -> Result<(), ChargebackError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for event in events.iter().filter(|e| e.uncovered > 0_f64) {
+        csv_writer.serialize(CollectionsRow {
+            client: event.client_id,
+            chargeback_tx: event.tx_id,
+            shortfall: format_amount(event.shortfall),
+            uncovered: format_amount(event.uncovered),
+        })?;
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_writes_a_row_per_reversed_withdrawal() -> Result<()> {
+        let mut event = ChargebackEvent::new(1, 1, 750.0);
+        event.reversed.push(ReversedWithdrawal::new(3, 500.0));
+        event.reversed.push(ReversedWithdrawal::new(2, 250.0));
+        let events = vec![event, ChargebackEvent::new(2, 7, 0.0)];
+        assert!((events[0].reversed_total() - 750.0).abs() < f64::EPSILON);
+
+        let mut output = vec![];
+        write_reversals(&events, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,chargeback_tx,shortfall,tx,amount
+1,1,750.0,3,500.0
+1,1,750.0,2,250.0
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_a_row_per_uncovered_chargeback() -> Result<()> {
+        let mut covered = ChargebackEvent::new(1, 1, 750.0);
+        covered.reversed.push(ReversedWithdrawal::new(3, 750.0));
+        covered.uncovered = 0.0_f64;
+        let events = vec![covered, ChargebackEvent::new(2, 7, 120.5)];
+
+        let mut output = vec![];
+        write_collections(&events, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,chargeback_tx,shortfall,uncovered
+2,7,120.5,120.5
+"
+        );
+        Ok(())
+    }
+}
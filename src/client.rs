@@ -1,13 +1,36 @@
 //! This module holds the logic regarding Client accounts, such as their balances, held balances, ids,
 //! and whether or not they are locked.
 
+use crate::chargeback::{ChargebackEvent, ReversedWithdrawal};
+use crate::config::EngineConfig;
+use crate::currency::Currency;
+use crate::debt::DebtLedger;
+use crate::dispute_fee::DisputeFee;
+use crate::duplicate::AmountIndex;
+use crate::funds::FundsAvailability;
+use crate::fx::{Conversion, RateTable, Rounding};
+use crate::interest::{AverageBalance, InterestTiers};
+use crate::invariant::{Violation, ViolationKind};
+use crate::ledger::{JournalLine, Ledger, LedgerAccount};
+use crate::outcome::{Note, Reason, TransactionOutcome};
+use crate::period::PeriodClose;
+use crate::profile::ClientProfile;
 use crate::reader::RawTransactionStream;
+use crate::reserve::{ReservePolicy, RESERVE};
+use crate::risk::{RiskLog, RiskWeights};
+use crate::rules::{ClientView, PendingTransaction, Rule, Verdict};
+use crate::settlement::SettlementBatch;
+use crate::shortfall::ShortfallPolicy;
+use crate::statement::{BalanceSnapshot, ClientStatement, Direction, EntryKind, StatementEntry};
 use crate::transaction::{
-    truncate_to_decimal_places, Chargeback, Deposit, Dispute, RawTransaction,
-    RawTransactionVariant, Resolve, Transaction, Withdrawal,
+    truncate_to_decimal_places, Authorization, Capture, Chargeback, Convert, Deposit, Dispute,
+    InternalVariant, RawTransaction, RawTransactionVariant, Refund, Resolve, Transaction,
+    TransactionError, TransactionKind, Void, Withdrawal,
 };
+use crate::window::{Activity, ActivityLog, Window};
 use serde::{Serialize, Serializer};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
 use tokio_stream::StreamExt;
 
 /// The window size of transactions that can be disputed
@@ -18,6 +41,64 @@ fn truncate_to_4_decimals<S: Serializer>(value: &f64, s: S) -> Result<S::Ok, S::
     s.serialize_f64(truncate_to_decimal_places(*value, 4))
 }
 
+/// Records an entry on a statement, if a statement is being kept.
+fn record_entry(
+    statement: &mut Option<ClientStatement>,
+    tx_id: u32,
+    kind: EntryKind,
+    direction: Direction,
+    amount: f64,
+) {
+    if let Some(ref mut s) = *statement {
+        s.entries
+            .push(StatementEntry::new(tx_id, kind, direction, amount));
+    }
+}
+
+/// Whether a withdrawal can be reversed to cover the shortfall left by a chargeback.
+const fn is_reversible(withdrawal: &Withdrawal) -> bool {
+    !withdrawal.failed && !withdrawal.disputed || withdrawal.resolved
+}
+
+/// The state of a client that is shared by its accounts in every currency.
+///
+/// This is its lock, and the activity its rules and risk score are evaluated on.  It is owned by
+/// whoever processes the client's transactions, and lent to the account each transaction is
+/// applied to.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SharedState {
+    /// Whether or not the client is locked.
+    locked: bool,
+    /// The recent deposits and withdrawals of the client.
+    activity: ActivityLog,
+    /// The number of transactions processed for the client so far.
+    sequence: u64,
+    /// The time of the latest timestamped transaction processed for the client.
+    clock: Option<u64>,
+    /// Whether a rule has flagged the client for review.
+    flagged: bool,
+    /// The recent signals of the client's activity that its risk score is computed from.
+    risk: RiskLog,
+    /// The number of outcomes recorded for the client so far, across its accounts.
+    recorded: u64,
+}
+
+impl Default for SharedState {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            locked: false,
+            activity: ActivityLog::new(WINDOW_SIZE),
+            sequence: 0,
+            clock: None,
+            flagged: false,
+            risk: RiskLog::new(WINDOW_SIZE),
+            recorded: 0,
+        }
+    }
+}
+
 /// A `Client` represents an account that can hold funds.
 #[derive(Debug, Clone, Serialize)]
 #[non_exhaustive]
@@ -39,6 +120,9 @@ pub struct Client {
     pub total_balance: f64,
     /// Whether or not the client is locked.
     pub locked: bool,
+    /// The currency of the account, or `None` for transactions that do not name a currency.
+    #[serde(skip)]
+    pub currency: Option<Currency>,
     /// The processed transactions of the client.
     #[serde(skip)]
     processed_transactions: HashMap<u32, Transaction>,
@@ -47,6 +131,132 @@ pub struct Client {
     /// by default be resolved and then removed.
     #[serde(skip)]
     dispute_window: VecDeque<u32>,
+    /// The statement of the client's activity, if statements are being recorded.
+    #[serde(skip)]
+    statement: Option<ClientStatement>,
+    /// The outcome of every transaction processed, if outcomes are being recorded.
+    #[serde(skip)]
+    outcomes: Option<Vec<TransactionOutcome>>,
+    /// The events emitted by chargebacks on the client's account.
+    #[serde(skip)]
+    chargeback_events: Vec<ChargebackEvent>,
+    /// How the shortfall left by a chargeback is covered.
+    #[serde(skip)]
+    shortfall_policy: Arc<dyn ShortfallPolicy>,
+    /// The debts left by chargebacks, if the shortfall policy carries debt.
+    #[serde(skip)]
+    debt: Option<DebtLedger>,
+    /// Which funds a withdrawal may draw on.
+    #[serde(skip)]
+    funds_availability: FundsAvailability,
+    /// The client's limits, if it has a profile.
+    #[serde(skip)]
+    profile: Option<ClientProfile>,
+    /// The deposits of the client still in the dispute window, indexed by amount.
+    #[serde(skip)]
+    deposits_by_amount: AmountIndex,
+    /// The rules evaluated before the client's deposits and withdrawals.
+    #[serde(skip)]
+    rules: Arc<[Box<dyn Rule>]>,
+    /// Whether a rule had flagged the client for review by the end of its activity.
+    #[serde(skip)]
+    flagged: bool,
+    /// The rule that decided the verdict for the transaction being processed, if any.
+    #[serde(skip)]
+    matched_rule: Option<&'static str>,
+    /// The weights of the client's risk score, if it should be reported.
+    #[serde(skip)]
+    risk_weights: Option<RiskWeights>,
+    /// The client's risk score as of the end of its activity, if it is reported.
+    #[serde(skip)]
+    risk_score: Option<f64>,
+    /// The authorizations of the client that are still open, oldest first.
+    #[serde(skip)]
+    open_authorizations: VecDeque<Activity>,
+    /// How long an authorization may stay open before it expires.
+    #[serde(skip)]
+    authorization_expiry: Option<Window>,
+    /// The rates that converts move funds between currencies at.
+    #[serde(skip)]
+    rates: Arc<RateTable>,
+    /// How the amount credited by a conversion is rounded.
+    #[serde(skip)]
+    fx_rounding: Rounding,
+    /// The conversions made out of the client's account.
+    #[serde(skip)]
+    conversions: Vec<Conversion>,
+    /// The last convert out of the client's account, until it is taken to credit the account of the
+    /// currency converted into.
+    #[serde(skip)]
+    conversion_credit: Option<Conversion>,
+    /// The double-entry ledger the client's balances are derived from.
+    #[serde(skip)]
+    ledger: Ledger,
+    /// The total balance as of the last outcome recorded, which the money moved through settlement
+    /// by the next one is measured from.
+    #[serde(skip)]
+    settled_total: f64,
+    /// The fee earned on the transaction being processed, recorded with its settlement.
+    #[serde(skip)]
+    fee_income: f64,
+    /// The opening balance plus what every transaction applied moved, as given by its record, which
+    /// the total balance must equal.
+    #[serde(skip)]
+    recorded_total: f64,
+    /// Whether the held balance is checked against every transaction still in the dispute window
+    /// after every transaction, rather than against `open_holds`.
+    #[serde(skip)]
+    verify_each_transaction: bool,
+    /// The holds backed by open disputes, rule holds and authorizations, kept as a running sum so
+    /// that the held balance can be checked after every transaction.
+    #[serde(skip)]
+    open_holds: f64,
+    /// The ID of the last transaction processed.
+    #[serde(skip)]
+    last_tx_id: Option<u32>,
+    /// The first violation of each invariant found in the client's account.
+    #[serde(skip)]
+    violations: Vec<Violation>,
+    /// The snapshot frozen at the close of each period.
+    #[serde(skip)]
+    period_closes: Vec<PeriodClose>,
+    /// The statements of the periods closed, if statements are being recorded.
+    #[serde(skip)]
+    closed_statements: Vec<ClientStatement>,
+    /// The settlement partner of each transaction in the dispute window that named one.
+    #[serde(skip)]
+    tx_partners: HashMap<u32, String>,
+    /// The money moved through each settlement partner in the open period.
+    #[serde(skip)]
+    settlement: BTreeMap<Option<String>, SettlementBatch>,
+    /// The settlement batches of the periods closed.
+    #[serde(skip)]
+    settled: Vec<SettlementBatch>,
+    /// The tiers of the interest credited by an accrue.
+    #[serde(skip)]
+    interest: Arc<InterestTiers>,
+    /// The average available balance since the last accrue.
+    #[serde(skip)]
+    average_available: AverageBalance,
+    /// The fee charged for every chargeback, if any.
+    #[serde(skip)]
+    dispute_fee: Option<DisputeFee>,
+    /// The rolling reserve kept back from every deposit, if any.
+    #[serde(skip)]
+    reserve: Option<ReservePolicy>,
+    /// The shares of deposits kept in the rolling reserve, oldest first.
+    #[serde(skip)]
+    reserve_tranches: VecDeque<Activity>,
+    /// The shares of disputed deposits drawn from the rolling reserve into their dispute hold, by
+    /// the ID of the deposit.
+    #[serde(skip)]
+    disputed_reserve: HashMap<u32, Activity>,
+    /// The balance of each of the client's sub-accounts, which roll up into its held balance.
+    #[serde(skip)]
+    sub_accounts: BTreeMap<String, f64>,
+    /// Whether the report lists the client's sub-accounts.
+    #[serde(skip)]
+    list_sub_accounts: bool,
 }
 
 impl Client {
@@ -54,484 +264,2776 @@ impl Client {
     #[inline]
     #[must_use]
     pub fn new(id: u16) -> Self {
+        Self::with_config(id, &EngineConfig::default())
+    }
+
+    /// Creates a new `Client` that follows the given engine configuration.
+    #[inline]
+    #[must_use]
+    pub fn with_config(id: u16, config: &EngineConfig) -> Self {
+        let statement = config
+            .record_statements
+            .then(|| ClientStatement::new(id, BalanceSnapshot::default()));
+        let outcomes = config.record_outcomes.then(Vec::new);
+        let profile = config.profiles.get(&id).copied();
+        let funds_availability = profile
+            .and_then(|p| p.overdraft_limit)
+            .map_or(config.funds_availability, FundsAvailability::Overdraft);
         Self {
             id,
             available_balance: 0.0,
             held_balance: 0.0,
             total_balance: 0.0,
             locked: false,
+            currency: None,
             processed_transactions: HashMap::new(),
             dispute_window: VecDeque::with_capacity(WINDOW_SIZE),
+            statement,
+            outcomes,
+            chargeback_events: Vec::new(),
+            shortfall_policy: Arc::clone(&config.shortfall_policy),
+            debt: config
+                .shortfall_policy
+                .carries_debt()
+                .then(DebtLedger::default),
+            funds_availability,
+            profile,
+            deposits_by_amount: AmountIndex::default(),
+            rules: Arc::clone(&config.rules),
+            flagged: false,
+            matched_rule: None,
+            risk_weights: config.risk_weights,
+            risk_score: config.risk_weights.map(|_| 0.0_f64),
+            open_authorizations: VecDeque::new(),
+            authorization_expiry: config.authorization_expiry,
+            rates: Arc::clone(&config.rates),
+            fx_rounding: config.fx_rounding,
+            conversions: Vec::new(),
+            conversion_credit: None,
+            ledger: Ledger::new(config.record_journal),
+            settled_total: 0.0,
+            fee_income: 0.0,
+            recorded_total: 0.0,
+            verify_each_transaction: config.verify_each_transaction,
+            open_holds: 0.0,
+            last_tx_id: None,
+            violations: Vec::new(),
+            period_closes: Vec::new(),
+            closed_statements: Vec::new(),
+            tx_partners: HashMap::new(),
+            settlement: BTreeMap::new(),
+            settled: Vec::new(),
+            interest: Arc::clone(&config.interest),
+            average_available: AverageBalance::default(),
+            dispute_fee: config.dispute_fee,
+            reserve: config.reserve,
+            reserve_tranches: VecDeque::new(),
+            disputed_reserve: HashMap::new(),
+            sub_accounts: BTreeMap::new(),
+            list_sub_accounts: config.list_sub_accounts,
         }
     }
 
-    /// Processes all the activity of the client, and computes the final balances and status of the client.
+    /// Creates a new `Client` that holds the client's balances in the given currency, and follows
+    /// the given engine configuration.
     #[inline]
-    pub async fn process_activity(&mut self, mut activity_stream: RawTransactionStream) {
-        let mut pending_total_balance = self.total_balance;
-        let mut pending_held_balance = self.held_balance;
-        let mut pending_available_balance = self.available_balance;
-        while let Some(transaction) = activity_stream.next().await {
-            if self.locked {
-                break;
+    #[must_use]
+    pub fn with_currency(id: u16, currency: Option<Currency>, config: &EngineConfig) -> Self {
+        let mut client = Self::with_config(id, config);
+        client.currency = currency;
+        if let Some(ref mut statement) = client.statement {
+            statement.currency = currency;
+        }
+        client
+    }
+
+    /// Takes the statement of the client's activity, if statements are being recorded.  The
+    /// statement is closed with the client's current balances.
+    #[inline]
+    pub fn take_statement(&mut self) -> Option<ClientStatement> {
+        let mut statement = self.statement.take()?;
+        statement.closing = self.balances();
+        Some(statement)
+    }
+
+    /// Takes the statements of the periods closed so far, if statements are being recorded.
+    #[inline]
+    pub fn take_closed_statements(&mut self) -> Vec<ClientStatement> {
+        std::mem::take(&mut self.closed_statements)
+    }
+
+    /// Takes the snapshots frozen at the close of each period so far.
+    #[inline]
+    pub fn take_period_closes(&mut self) -> Vec<PeriodClose> {
+        std::mem::take(&mut self.period_closes)
+    }
+
+    /// Takes the settlement batches of every period so far, with the open period's last.
+    #[inline]
+    pub fn take_settlement(&mut self) -> Vec<SettlementBatch> {
+        let mut batches = std::mem::take(&mut self.settled);
+        batches.extend(std::mem::take(&mut self.settlement).into_values());
+        batches
+    }
+
+    /// Remembers the settlement partner of a transaction that names one.  Transactions that refer
+    /// to an earlier one belong to the partner of the transaction they refer to.
+    fn record_partner(&mut self, transaction: &RawTransaction) {
+        let Some(partner) = transaction.partner.as_ref() else {
+            return;
+        };
+        match transaction.variant {
+            RawTransactionVariant::Deposit
+            | RawTransactionVariant::Withdrawal
+            | RawTransactionVariant::Authorize
+            | RawTransactionVariant::Convert
+            | RawTransactionVariant::Refund => {
+                self.tx_partners
+                    .entry(transaction.tx_id)
+                    .or_insert_with(|| partner.clone());
             }
-            self.process_transaction(
-                &mut pending_total_balance,
-                &mut pending_held_balance,
-                &mut pending_available_balance,
-                transaction,
-            );
+            RawTransactionVariant::Dispute
+            | RawTransactionVariant::Resolve
+            | RawTransactionVariant::Chargeback
+            | RawTransactionVariant::Capture
+            | RawTransactionVariant::Void
+            | RawTransactionVariant::Close
+            | RawTransactionVariant::Accrue => {}
         }
-        self.available_balance = pending_available_balance;
-        self.held_balance = pending_held_balance;
-        self.total_balance = pending_total_balance;
-        self.dispute_window.clear();
-        self.processed_transactions.clear();
     }
 
-    /// Processes a transaction and updates the client's pending balances.
-    fn process_transaction(
-        &mut self,
-        pending_total_balance: &mut f64,
-        pending_held_balance: &mut f64,
-        pending_available_balance: &mut f64,
-        transaction: RawTransaction,
-    ) {
-        if self.dispute_window.len() >= WINDOW_SIZE {
-            self.finalize_transaction(pending_held_balance, pending_available_balance);
+    /// Credits the interest earned on the average available balance since the last accrue, rounded
+    /// down to the minor unit and leaving the residue to be posted with it.  The interest is not
+    /// kept as a processed transaction, so it cannot be disputed.
+    fn accrue_interest(&mut self, accrue: &RawTransaction, shared: &mut SharedState) {
+        let average = self
+            .average_available
+            .close(accrue.timestamp.or(shared.clock));
+        let accrued = self.interest.interest(average);
+        if accrued <= 0.0_f64 {
+            return;
+        }
+        let places = self.currency.map_or(4, Currency::minor_units);
+        let credited = Rounding::Down.round(accrued, places);
+        self.post(
+            accrue.tx_id,
+            InternalVariant::Interest.into(),
+            &[
+                (LedgerAccount::InterestExpense, accrued),
+                (LedgerAccount::ClientAvailable, -credited),
+                (LedgerAccount::InterestResidue, credited - accrued),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            accrue.tx_id,
+            EntryKind::Interest,
+            Direction::Credit,
+            credited,
+        );
+        self.record_internal(accrue.tx_id, InternalVariant::Interest, credited, shared);
+    }
+
+    /// Closes the current period with the given balances: freezes a snapshot of the account,
+    /// closes the statement, and opens the statement of the next period.  Open disputes and
+    /// authorizations carry over to the next period.
+    fn close_period(&mut self, close: &RawTransaction, balances: BalanceSnapshot, locked: bool) {
+        self.period_closes.push(PeriodClose::new(
+            close.tx_id,
+            close.timestamp,
+            self.id,
+            self.currency,
+            balances,
+            locked,
+        ));
+        if let Some(ref mut statement) = self.statement {
+            let mut next = ClientStatement::new(self.id, balances);
+            next.currency = self.currency;
+            let mut closed = std::mem::replace(statement, next);
+            closed.closing = balances;
+            closed.period = Some(close.tx_id);
+            closed.closed_at = close.timestamp;
+            self.closed_statements.push(closed);
+        }
+        for (_, mut batch) in std::mem::take(&mut self.settlement) {
+            batch.period = Some(close.tx_id);
+            self.settled.push(batch);
+        }
+    }
+
+    /// Takes the outcomes of the transactions processed so far, if outcomes are being recorded.
+    #[inline]
+    pub fn take_outcomes(&mut self) -> Vec<TransactionOutcome> {
+        self.outcomes
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Takes the events emitted by chargebacks so far.
+    #[inline]
+    pub fn take_chargeback_events(&mut self) -> Vec<ChargebackEvent> {
+        std::mem::take(&mut self.chargeback_events)
+    }
+
+    /// Takes the conversions made out of the client's account so far.
+    #[inline]
+    pub fn take_conversions(&mut self) -> Vec<Conversion> {
+        std::mem::take(&mut self.conversions)
+    }
+
+    /// Takes the convert just processed, so that the amount it converts to can be credited to the
+    /// account of the currency converted into, or `None` if the last transaction was not a convert
+    /// that succeeded.
+    #[inline]
+    pub const fn take_conversion_credit(&mut self) -> Option<Conversion> {
+        self.conversion_credit.take()
+    }
+
+    /// Whether a transaction is still in the client's dispute window, or held by a rule past it,
+    /// so that transactions referring to it can find it.
+    #[inline]
+    #[must_use]
+    pub fn holds_transaction(&self, tx_id: u32) -> bool {
+        self.processed_transactions.contains_key(&tx_id)
+    }
+
+    /// Locks the client, logging it once.
+    fn lock(&self, shared: &mut SharedState) {
+        if !shared.locked {
+            eprintln!("Account is locked: {}", self.id);
         }
+        shared.locked = true;
+    }
+
+    /// Whether a transaction is applied even though the client is locked: deposits that repay
+    /// debt, up to the debt outstanding.
+    fn applies_while_locked(&self, transaction: &RawTransaction) -> bool {
         match transaction.variant {
-            RawTransactionVariant::Deposit => {
-                if let Ok(deposit) = transaction.try_into() {
-                    self.process_deposit(pending_total_balance, pending_available_balance, deposit);
-                } else {
-                    eprintln!("Failed to parse deposit transaction");
-                }
-            }
-            RawTransactionVariant::Withdrawal => {
-                if let Ok(withdrawal) = transaction.try_into() {
-                    self.process_withdrawal(
-                        pending_total_balance,
-                        pending_available_balance,
-                        withdrawal,
-                    );
-                } else {
-                    eprintln!("Failed to parse withdrawal transaction");
-                }
-            }
-            RawTransactionVariant::Dispute => {
-                if let Ok(dispute) = transaction.try_into() {
-                    self.process_dispute(pending_held_balance, pending_available_balance, &dispute);
-                } else {
-                    eprintln!("Failed to parse dispute transaction");
-                }
-            }
-            RawTransactionVariant::Resolve => {
-                if let Ok(resolve) = transaction.try_into() {
-                    self.process_resolve(
-                        pending_held_balance,
-                        pending_available_balance,
-                        &resolve,
-                        None,
-                    );
-                } else {
-                    eprintln!("Failed to parse resolve transaction");
-                }
-            }
-            RawTransactionVariant::Chargeback => {
-                if let Ok(chargeback) = transaction.try_into() {
-                    self.process_chargeback(
-                        pending_held_balance,
-                        pending_total_balance,
-                        &chargeback,
-                    );
-                    if let Some(window_start) = self
-                        .dispute_window
-                        .iter()
-                        .position(|&id| id == chargeback.tx_id)
-                    {
-                        let ids_to_check = self
-                            .dispute_window
-                            .clone()
-                            .into_iter()
-                            .skip(window_start)
-                            .rev()
-                            .collect::<Vec<_>>();
-                        if *pending_total_balance < 0_f64 {
-                            // Reverse withdrawals until the total balance is positive.
-                            for id in ids_to_check {
-                                if *pending_total_balance >= 0_f64 {
-                                    break;
-                                }
-                                self.reverse_withdrawal(
-                                    pending_available_balance,
-                                    pending_total_balance,
-                                    id,
-                                );
-                            }
-                        }
-                    }
-                } else {
-                    eprintln!("Failed to parse chargeback transaction");
-                }
-            }
+            RawTransactionVariant::Deposit => self.owes_debt(),
+            RawTransactionVariant::Close | RawTransactionVariant::Accrue => true,
+            RawTransactionVariant::Withdrawal
+            | RawTransactionVariant::Dispute
+            | RawTransactionVariant::Resolve
+            | RawTransactionVariant::Chargeback
+            | RawTransactionVariant::Authorize
+            | RawTransactionVariant::Capture
+            | RawTransactionVariant::Void
+            | RawTransactionVariant::Convert
+            | RawTransactionVariant::Refund => false,
         }
     }
 
-    /// Finalizes a transaction by removing it from the dispute window.
-    fn finalize_transaction(
-        &mut self,
-        pending_held_balance: &mut f64,
-        pending_available_balance: &mut f64,
-    ) {
-        if let Some(id) = self.dispute_window.pop_front() {
-            if let Some(mut old_tx) = self.processed_transactions.remove(&id) {
-                match old_tx {
-                    Transaction::Deposit(deposit) => {
-                        if deposit.disputed && !deposit.resolved {
-                            self.process_resolve(
-                                pending_held_balance,
-                                pending_available_balance,
-                                &Resolve {
-                                    client_id: deposit.client_id,
-                                    tx_id: deposit.tx_id,
-                                },
-                                Some(&mut old_tx),
-                            );
-                        }
-                    }
-                    Transaction::Withdrawal(withdrawal) => {
-                        if withdrawal.disputed && !withdrawal.resolved {
-                            self.process_resolve(
-                                pending_held_balance,
-                                pending_available_balance,
-                                &Resolve {
-                                    client_id: withdrawal.client_id,
-                                    tx_id: withdrawal.tx_id,
-                                },
-                                Some(&mut old_tx),
-                            );
-                        }
-                    }
-                    Transaction::Dispute(_)
-                    | Transaction::Resolve(_)
-                    | Transaction::Chargeback(_) => {
-                        eprintln!("Failed to finalize transaction");
-                    }
-                }
-            }
+    /// Returns the client's debt ledger, if the shortfall policy carries debt.
+    #[inline]
+    #[must_use]
+    pub const fn debt(&self) -> Option<&DebtLedger> {
+        self.debt.as_ref()
+    }
+
+    /// Whether the client owes debt that a deposit would repay.
+    fn owes_debt(&self) -> bool {
+        self.debt
+            .as_ref()
+            .is_some_and(|debt| debt.outstanding() > 0_f64)
+    }
+
+    /// Returns the double-entry ledger of the client's account.
+    #[inline]
+    #[must_use]
+    pub const fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    /// Takes the journal lines posted to the client's ledger so far, if the journal is being
+    /// recorded.
+    #[inline]
+    pub fn take_journal(&mut self) -> Vec<JournalLine> {
+        self.ledger.take_journal()
+    }
+
+    /// Posts a journal entry for a transaction to the client's ledger.  Each line is an account
+    /// with a debit-positive amount, so funds credited to the client are negative.
+    fn post(&mut self, tx_id: u32, kind: TransactionKind, lines: &[(LedgerAccount, f64)]) {
+        self.ledger.post(tx_id, self.id, self.currency, kind, lines);
+    }
+
+    /// The client's balances as posted to its ledger so far.
+    fn pending(&self) -> BalanceSnapshot {
+        let available = self.ledger.credit_balance(LedgerAccount::ClientAvailable);
+        let held = self.ledger.credit_balance(LedgerAccount::ClientHeld);
+        BalanceSnapshot::new(available, held, available + held)
+    }
+
+    /// Records the money a transaction moved through its settlement partner, and the fee earned on
+    /// it, in the batch of the open period.
+    fn record_settlement(&mut self, tx_id: u32, kind: TransactionKind, total: f64) {
+        let fee = std::mem::take(&mut self.fee_income);
+        let moved = total - self.settled_total + fee;
+        self.settled_total = total;
+        if moved != 0.0_f64 || fee != 0.0_f64 {
+            let partner = self.tx_partners.get(&tx_id).cloned();
+            let currency = self.currency;
+            self.settlement
+                .entry(partner.clone())
+                .or_insert_with(|| SettlementBatch::new(partner, currency))
+                .record(kind, moved, fee);
         }
     }
 
-    /// Processes a deposit transaction.
-    fn process_deposit(
+    /// Records the settlement of a transaction, checks the client's balances after it, counts its
+    /// outcome towards the risk score, and records it if outcomes are being recorded.  The result
+    /// holds the notes on the transaction if it was applied, or the reason it was not.
+    fn record_outcome(
         &mut self,
-        pending_total_balance: &mut f64,
-        pending_available_balance: &mut f64,
-        deposit: Deposit,
+        tx_id: u32,
+        kind: TransactionKind,
+        result: Result<Vec<Note>, Reason>,
+        shared: &mut SharedState,
     ) {
-        if deposit.client_id != self.id {
-            eprintln!("Received deposit from wrong client: {}", deposit.client_id);
-            return;
+        self.recorded_total += self.recorded_movement(tx_id, kind, &result);
+        let after = self.pending();
+        self.record_settlement(tx_id, kind, after.total);
+        self.average_available.record(shared.clock, after.available);
+        self.last_tx_id = Some(tx_id);
+        let backed = if self.verify_each_transaction {
+            self.backed_hold()
+        } else {
+            self.sub_accounts.values().sum::<f64>() + self.open_holds
+        };
+        self.check_invariants(Some(tx_id), after, backed);
+        shared
+            .risk
+            .record(kind, &result, shared.sequence, shared.clock);
+        shared.recorded = shared.recorded.saturating_add(1);
+        if let Some(ref mut outcomes) = self.outcomes {
+            let mut outcome = TransactionOutcome::new(tx_id, self.id, kind, result, after)
+                .with_rule(self.matched_rule.take());
+            outcome.currency = self.currency.map(|currency| currency.to_string());
+            outcome.seq = shared.recorded;
+            outcomes.push(outcome);
         }
-        *pending_total_balance += deposit.amount;
-        *pending_available_balance += deposit.amount;
-        self.dispute_window.push_back(deposit.tx_id);
-        self.processed_transactions
-            .insert(deposit.tx_id, Transaction::Deposit(deposit));
     }
 
-    /// Processes a withdrawal transaction.
-    fn process_withdrawal(
+    /// Records the outcome of an entry the client posted on its own, which moved its total balance
+    /// by `moved`.
+    fn record_internal(
         &mut self,
-        pending_total_balance: &mut f64,
-        pending_available_balance: &mut f64,
-        mut withdrawal: Withdrawal,
+        tx_id: u32,
+        variant: InternalVariant,
+        moved: f64,
+        shared: &mut SharedState,
     ) {
-        if withdrawal.client_id != self.id {
-            eprintln!(
-                "Received withdrawal from wrong client: {}",
-                withdrawal.client_id
-            );
-            return;
+        self.recorded_total += moved;
+        self.record_outcome(tx_id, variant.into(), Ok(Vec::new()), shared);
+    }
+
+    /// What a transaction moved the total balance by, as given by its record rather than by what
+    /// was posted: the amount of a deposit, withdrawal, capture or refund applied, the amount a
+    /// chargeback takes back, a withdrawal reversed by a chargeback, or the amount a convert took
+    /// out.
+    fn recorded_movement(
+        &self,
+        tx_id: u32,
+        kind: TransactionKind,
+        result: &Result<Vec<Note>, Reason>,
+    ) -> f64 {
+        let record = self.processed_transactions.get(&tx_id);
+        match (kind, result, record) {
+            (
+                TransactionKind::Raw(RawTransactionVariant::Deposit),
+                &Ok(_),
+                Some(&Transaction::Deposit(deposit)),
+            ) => deposit.amount,
+            (
+                TransactionKind::Raw(
+                    RawTransactionVariant::Withdrawal | RawTransactionVariant::Capture,
+                ),
+                &Ok(_),
+                Some(&Transaction::Withdrawal(withdrawal)),
+            ) => -withdrawal.amount,
+            (
+                TransactionKind::Raw(RawTransactionVariant::Withdrawal),
+                &Err(Reason::ReversedByChargeback),
+                Some(&Transaction::Withdrawal(withdrawal)),
+            ) => withdrawal.amount,
+            (
+                TransactionKind::Raw(RawTransactionVariant::Refund),
+                &Ok(_),
+                Some(&Transaction::Refund(refund)),
+            ) => -refund.amount,
+            (
+                TransactionKind::Raw(RawTransactionVariant::Chargeback),
+                &Ok(_),
+                Some(&Transaction::Deposit(deposit)),
+            ) => -deposit.disputable(),
+            (
+                TransactionKind::Raw(RawTransactionVariant::Chargeback),
+                &Ok(_),
+                Some(&Transaction::Withdrawal(withdrawal)),
+            ) if !withdrawal.failed => withdrawal.amount,
+            (TransactionKind::Raw(RawTransactionVariant::Convert), &Ok(_), _) => self
+                .conversions
+                .last()
+                .filter(|conversion| conversion.tx == tx_id)
+                .map_or(0.0, |conversion| -conversion.amount),
+            _ => 0.0,
         }
-        if *pending_total_balance < withdrawal.amount {
-            // No resolution of disputed transactions will enable this withdrawal to be processed.
-            eprintln!("Insufficient funds to process withdrawal");
-            withdrawal.failed = true;
-            self.processed_transactions
-                .insert(withdrawal.tx_id, Transaction::Withdrawal(withdrawal));
-            return;
+    }
+
+    /// The opening balance of the client's account plus what every transaction applied to it has
+    /// moved since, as given by their records.
+    #[inline]
+    #[must_use]
+    pub const fn recorded_total(&self) -> f64 {
+        self.recorded_total
+    }
+
+    /// Returns the first violation of each invariant found in the client's account.
+    #[inline]
+    #[must_use]
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Checks that the client's balances keep their invariants after a transaction, recording the
+    /// first violation of each.  The held balance must equal the `backed` holds.
+    fn check_invariants(&mut self, tx_id: Option<u32>, after: BalanceSnapshot, backed: f64) {
+        let checks = [
+            (
+                ViolationKind::UnbalancedTotal,
+                after.total,
+                after.available + after.held,
+            ),
+            (
+                ViolationKind::FundsNotConserved,
+                self.recorded_total,
+                after.total,
+            ),
+            (ViolationKind::UnbackedHold, backed, after.held),
+        ];
+        for (kind, expected, actual) in checks {
+            if self.violations.iter().any(|v| v.kind == kind) {
+                continue;
+            }
+            if let Some(violation) =
+                Violation::compare(kind, Some(self.id), self.currency, tx_id, expected, actual)
+            {
+                eprintln!("Invariant violated: {violation}");
+                self.violations.push(violation);
+            }
         }
-        *pending_total_balance -= withdrawal.amount;
-        *pending_available_balance -= withdrawal.amount;
-        self.dispute_window.push_back(withdrawal.tx_id);
-        self.processed_transactions
-            .insert(withdrawal.tx_id, Transaction::Withdrawal(withdrawal));
     }
 
-    /// Process a dispute
-    fn process_dispute(
-        &mut self,
-        pending_held_balance: &mut f64,
-        pending_available_balance: &mut f64,
-        dispute: &Dispute,
-    ) {
-        if let Some(tx) = self.processed_transactions.get_mut(&dispute.tx_id) {
-            match tx {
-                Transaction::Deposit(deposit) => {
-                    if deposit.disputed
-                        || deposit.resolved
-                        || dispute.client_id != deposit.client_id
+    /// The amount the held balance should be: the deposits under open dispute or held by a rule,
+    /// less the withdrawals under open dispute, plus the authorizations not yet released and the
+    /// balances of the sub-accounts.  This looks at every transaction still in the dispute window.
+    fn backed_hold(&self) -> f64 {
+        self.sub_accounts.values().sum::<f64>()
+            + self
+                .processed_transactions
+                .values()
+                .map(|tx| match *tx {
+                    Transaction::Deposit(deposit)
+                        if deposit.held_by_rule || deposit.disputed && !deposit.resolved =>
                     {
-                        // Transaction has already been disputed
-                        eprintln!("Transaction has already been disputed: {}", dispute.tx_id);
-                        return;
+                        deposit.disputable()
                     }
-                    deposit.disputed = true;
-                    *pending_held_balance += deposit.amount;
-                    *pending_available_balance -= deposit.amount;
-                }
-                Transaction::Withdrawal(withdrawal) => {
-                    if withdrawal.disputed
-                        || withdrawal.resolved
-                        || dispute.client_id != withdrawal.client_id
+                    Transaction::Withdrawal(withdrawal)
+                        if withdrawal.disputed && !withdrawal.resolved && !withdrawal.failed =>
                     {
-                        // Transaction has already been disputed
-                        eprintln!("Transaction has already been disputed: {}", dispute.tx_id);
-                        return;
+                        -withdrawal.amount
                     }
-                    withdrawal.disputed = true;
-                    if !withdrawal.failed {
-                        *pending_held_balance -= withdrawal.amount;
-                        *pending_available_balance += withdrawal.amount;
+                    Transaction::Authorization(authorization) if !authorization.released => {
+                        authorization.amount
                     }
-                }
-                Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_) => {
-                    // The transaction referenced is not a deposit or withdrawal
-                    eprintln!(
-                        "Transaction is not a deposit or withdrawal: {}",
-                        dispute.tx_id
-                    );
-                }
-            }
-        }
+                    Transaction::Deposit(_)
+                    | Transaction::Withdrawal(_)
+                    | Transaction::Authorization(_)
+                    | Transaction::Dispute(_)
+                    | Transaction::Resolve(_)
+                    | Transaction::Chargeback(_)
+                    | Transaction::Capture(_)
+                    | Transaction::Void(_)
+                    | Transaction::Refund(_) => 0.0_f64,
+                })
+                .sum::<f64>()
     }
 
-    /// Processes a `Resolve` transaction
-    fn process_resolve(
-        &mut self,
-        pending_held_balance: &mut f64,
-        pending_available_balance: &mut f64,
-        resolve: &Resolve,
-        tx: Option<&mut Transaction>,
-    ) {
-        let transaction = if tx.is_some() {
-            tx
-        } else {
-            self.processed_transactions.get_mut(&resolve.tx_id)
-        };
-        if let Some(t) = transaction {
-            match t {
-                Transaction::Deposit(deposit) => {
-                    if !deposit.disputed
-                        || deposit.resolved
-                        || resolve.client_id != deposit.client_id
-                    {
-                        // Transaction has not been disputed or has already been resolved
-                        eprintln!(
-                            "Transaction has not been disputed or has already been resolved: {}",
+    /// Whether a rule has flagged the client for review, or `None` if there are no rules.
+    #[inline]
+    #[must_use]
+    pub fn flagged(&self) -> Option<bool> {
+        (!self.rules.is_empty()).then_some(self.flagged)
+    }
+
+    /// The balance of each of the client's sub-accounts.
+    #[inline]
+    #[must_use]
+    pub const fn sub_accounts(&self) -> &BTreeMap<String, f64> {
+        &self.sub_accounts
+    }
+
+    /// The balance of each of the client's sub-accounts, or `None` if the report does not list
+    /// them.
+    #[inline]
+    #[must_use]
+    pub fn listed_sub_accounts(&self) -> Option<&BTreeMap<String, f64>> {
+        self.list_sub_accounts.then_some(&self.sub_accounts)
+    }
+
+    /// The client's risk score, or `None` if it is not reported.
+    #[inline]
+    #[must_use]
+    pub const fn risk_score(&self) -> Option<f64> {
+        self.risk_score
+    }
+
+    /// Returns a snapshot of the client's current balances.
+    #[inline]
+    #[must_use]
+    pub const fn balances(&self) -> BalanceSnapshot {
+        BalanceSnapshot::new(
+            self.available_balance,
+            self.held_balance,
+            self.total_balance,
+        )
+    }
+
+    /// Processes all the activity of the client, and computes the final balances and status of the client.
+    #[inline]
+    pub async fn process_activity(&mut self, mut activity_stream: RawTransactionStream) {
+        let mut shared = SharedState::default();
+        self.begin_activity();
+        while let Some(transaction) = activity_stream.next().await {
+            self.process(transaction, &mut shared);
+        }
+        self.end_activity(&shared);
+    }
+
+    /// Starts processing the client's activity from its current balances.  Balances that are not
+    /// posted to the ledger yet, such as those the client was created with, are posted against
+    /// settlement.
+    #[inline]
+    pub fn begin_activity(&mut self) {
+        self.recorded_total = self.total_balance;
+        self.open_holds = self.backed_hold() - self.sub_accounts.values().sum::<f64>();
+        let posted = self.pending();
+        let available = self.available_balance - posted.available;
+        let held = self.held_balance - posted.held;
+        self.post(
+            self.last_tx_id.unwrap_or_default(),
+            InternalVariant::Opening.into(),
+            &[
+                (LedgerAccount::Settlement, available + held),
+                (LedgerAccount::ClientAvailable, -available),
+                (LedgerAccount::ClientHeld, -held),
+            ],
+        );
+    }
+
+    /// Processes a single transaction of the client's account, posting what it moves to the
+    /// client's ledger, with the state that the client's accounts in every currency share.
+    #[inline]
+    pub fn process(&mut self, mut transaction: RawTransaction, shared: &mut SharedState) {
+        if transaction.variant == RawTransactionVariant::Close {
+            self.close_period(&transaction, self.pending(), shared.locked);
+        } else if transaction.variant == RawTransactionVariant::Accrue {
+            self.accrue_interest(&transaction, shared);
+        } else {
+            if transaction.currency.is_none() {
+                transaction.currency = self.currency;
+            }
+            if let Some(currency) = self.currency {
+                transaction.amount = transaction.amount.map(|amount| currency.truncate(amount));
+            }
+            self.record_partner(&transaction);
+            if shared.locked && !self.applies_while_locked(&transaction) {
+                // Keep draining the stream so the processor can still send to this client.  The
+                // lock was logged when it happened, and the outcome records each transaction.
+                self.record_outcome(
+                    transaction.tx_id,
+                    transaction.variant.into(),
+                    Err(Reason::AccountLocked),
+                    shared,
+                );
+            } else {
+                if shared.locked {
+                    self.limit_to_debt(&mut transaction);
+                }
+                self.process_transaction(transaction, shared);
+            }
+        }
+    }
+
+    /// Limits a deposit made while the client is locked to the debt it still owes.  The rest of
+    /// the deposit is rejected, since the account is locked.
+    fn limit_to_debt(&self, deposit: &mut RawTransaction) {
+        let outstanding = self.debt.as_ref().map_or(0.0_f64, DebtLedger::outstanding);
+        if let Some(amount) = deposit.amount.filter(|&amount| amount > outstanding) {
+            eprintln!(
+                "Account is locked, rejected {} of deposit {} beyond the debt owed",
+                amount - outstanding,
+                deposit.tx_id
+            );
+            deposit.amount = Some(outstanding);
+        }
+    }
+
+    /// Computes the final balances of the client from its ledger, and checks them once more against
+    /// every transaction still in the dispute window, taking the lock, flag and risk score from the
+    /// state shared by the client's accounts.  A violation found only here names no transaction, as
+    /// it cannot tell which one caused it.
+    #[inline]
+    pub fn end_activity(&mut self, shared: &SharedState) {
+        self.locked = shared.locked;
+        self.flagged = shared.flagged;
+        self.risk_score = self.risk_weights.map(|weights| {
+            shared
+                .risk
+                .counters(weights.window, shared.sequence, shared.clock)
+                .score(&weights)
+        });
+        self.available_balance = self.ledger.credit_balance(LedgerAccount::ClientAvailable);
+        self.held_balance = self.ledger.credit_balance(LedgerAccount::ClientHeld);
+        self.total_balance = self.available_balance + self.held_balance;
+        self.check_invariants(None, self.balances(), self.backed_hold());
+        self.dispute_window.clear();
+        self.processed_transactions.clear();
+        self.disputed_reserve.clear();
+        self.deposits_by_amount.clear();
+        self.tx_partners.clear();
+    }
+
+    /// Advances the client's transaction count and clock for a new transaction, settles the oldest
+    /// transaction in the dispute window if it is full, expires authorizations past their
+    /// deadline, and releases the reserve kept back from deposits that have left its window.
+    fn advance(&mut self, timestamp: Option<u64>, shared: &mut SharedState) {
+        shared.sequence = shared.sequence.saturating_add(1);
+        if timestamp.is_some() {
+            shared.clock = timestamp;
+        }
+        if self.dispute_window.len() >= WINDOW_SIZE {
+            self.finalize_transaction(shared);
+        }
+        self.expire_authorizations(shared);
+        self.release_reserve(shared);
+    }
+
+    /// Moves the policy's share of a deposit credited to the available balance into the rolling
+    /// reserve, rounded down to the minor unit.
+    fn reserve_deposit(&mut self, tx_id: u32, amount: f64, shared: &SharedState) {
+        let Some(reserve) = self.reserve else {
+            return;
+        };
+        let places = self.currency.map_or(4, Currency::minor_units);
+        let reserved = Rounding::Down.round(reserve.reserved(amount), places);
+        if reserved <= 0.0_f64 {
+            return;
+        }
+        self.post(
+            tx_id,
+            RawTransactionVariant::Deposit.into(),
+            &[
+                (LedgerAccount::ClientAvailable, reserved),
+                (LedgerAccount::ClientHeld, -reserved),
+            ],
+        );
+        *self.sub_accounts.entry(RESERVE.to_owned()).or_default() += reserved;
+        record_entry(
+            &mut self.statement,
+            tx_id,
+            EntryKind::Reserve,
+            Direction::Debit,
+            reserved,
+        );
+        self.reserve_tranches.push_back(Activity {
+            seq: shared.sequence,
+            at: shared.clock,
+            tx_id,
+            variant: RawTransactionVariant::Deposit,
+            amount: reserved,
+        });
+    }
+
+    /// Releases the reserve kept back from every deposit that has left the reserve's window back
+    /// to the available balance, and records each release.
+    fn release_reserve(&mut self, shared: &mut SharedState) {
+        let Some(reserve) = self.reserve else {
+            return;
+        };
+        while let Some(oldest) = self.reserve_tranches.front().copied() {
+            if reserve
+                .hold_for
+                .contains(shared.sequence, shared.clock, oldest.seq, oldest.at)
+            {
+                break;
+            }
+            self.reserve_tranches.pop_front();
+            self.post(
+                oldest.tx_id,
+                InternalVariant::ReserveRelease.into(),
+                &[
+                    (LedgerAccount::ClientHeld, oldest.amount),
+                    (LedgerAccount::ClientAvailable, -oldest.amount),
+                ],
+            );
+            *self.sub_accounts.entry(RESERVE.to_owned()).or_default() -= oldest.amount;
+            record_entry(
+                &mut self.statement,
+                oldest.tx_id,
+                EntryKind::ReserveRelease,
+                Direction::Credit,
+                oldest.amount,
+            );
+            self.record_internal(oldest.tx_id, InternalVariant::ReserveRelease, 0.0, shared);
+        }
+    }
+
+    /// Draws the share of a deposit kept in the rolling reserve, up to `disputable`, out of the
+    /// reserve so that it becomes part of the deposit's dispute hold.  Returns the amount drawn.
+    fn draw_reserve(&mut self, tx_id: u32, disputable: f64) -> f64 {
+        let Some(index) = self.reserve_tranches.iter().position(|t| t.tx_id == tx_id) else {
+            return 0.0;
+        };
+        let Some(tranche) = self.reserve_tranches.get_mut(index) else {
+            return 0.0;
+        };
+        let drawn = Activity {
+            amount: tranche.amount.min(disputable),
+            ..*tranche
+        };
+        tranche.amount -= drawn.amount;
+        if tranche.amount <= 0.0_f64 {
+            self.reserve_tranches.remove(index);
+        }
+        *self.sub_accounts.entry(RESERVE.to_owned()).or_default() -= drawn.amount;
+        self.disputed_reserve.insert(tx_id, drawn);
+        drawn.amount
+    }
+
+    /// Returns the share of a deposit drawn into its dispute hold to the rolling reserve once the
+    /// dispute is resolved, if the deposit is still inside the reserve's window.  Returns the amount
+    /// returned; the rest of the dispute hold is released to the available balance.
+    fn restore_reserve(&mut self, tx_id: u32, shared: &SharedState) -> f64 {
+        let (Some(reserve), Some(tranche)) = (self.reserve, self.disputed_reserve.remove(&tx_id))
+        else {
+            return 0.0;
+        };
+        if !reserve
+            .hold_for
+            .contains(shared.sequence, shared.clock, tranche.seq, tranche.at)
+        {
+            return 0.0;
+        }
+        *self.sub_accounts.entry(RESERVE.to_owned()).or_default() += tranche.amount;
+        let index = self
+            .reserve_tranches
+            .iter()
+            .position(|t| t.seq > tranche.seq)
+            .unwrap_or(self.reserve_tranches.len());
+        self.reserve_tranches.insert(index, tranche);
+        tranche.amount
+    }
+
+    /// Processes a transaction and posts what it moves to the client's ledger.
+    fn process_transaction(&mut self, transaction: RawTransaction, shared: &mut SharedState) {
+        self.advance(transaction.timestamp, shared);
+        let tx_id = transaction.tx_id;
+        let variant = transaction.variant;
+        let result = match transaction.variant {
+            RawTransactionVariant::Deposit => self.process_deposit_transaction(transaction, shared),
+            RawTransactionVariant::Withdrawal => transaction.try_into().map_or_else(
+                |_| {
+                    eprintln!("Failed to parse withdrawal transaction");
+                    Err(Reason::InvalidTransaction)
+                },
+                |withdrawal| self.process_withdrawal(withdrawal, shared),
+            ),
+            RawTransactionVariant::Dispute => transaction.try_into().map_or_else(
+                |_| {
+                    eprintln!("Failed to parse dispute transaction");
+                    Err(Reason::InvalidTransaction)
+                },
+                |dispute| self.process_dispute(&dispute).map(|()| Vec::new()),
+            ),
+            RawTransactionVariant::Resolve => transaction.try_into().map_or_else(
+                |_| {
+                    eprintln!("Failed to parse resolve transaction");
+                    Err(Reason::InvalidTransaction)
+                },
+                |resolve| {
+                    self.process_resolve(&resolve, None, shared)
+                        .map(|()| Vec::new())
+                },
+            ),
+            RawTransactionVariant::Refund => self
+                .process_refund_transaction(transaction)
+                .map(|()| Vec::new()),
+            // Closes and accrues do not reach here, since they are handled before being processed.
+            RawTransactionVariant::Close | RawTransactionVariant::Accrue => {
+                Err(Reason::InvalidTransaction)
+            }
+            RawTransactionVariant::Authorize
+            | RawTransactionVariant::Capture
+            | RawTransactionVariant::Void => self
+                .process_authorization_transaction(transaction, shared)
+                .map(|()| Vec::new()),
+            RawTransactionVariant::Convert => self
+                .process_conversion_transaction(transaction, shared)
+                .map(|()| Vec::new()),
+            RawTransactionVariant::Chargeback => {
+                return self.process_chargeback_transaction(transaction, shared);
+            }
+        };
+        self.record_outcome(tx_id, variant.into(), result, shared);
+    }
+
+    /// Reverses withdrawals made since a charged back transaction until the total balance is no
+    /// longer negative, and emits a `ChargebackEvent` listing the reversed withdrawals.
+    fn cover_chargeback_shortfall(&mut self, chargeback: &Chargeback, shared: &mut SharedState) {
+        let mut event =
+            ChargebackEvent::new(self.id, chargeback.tx_id, (-self.pending().total).max(0.0));
+        if let Some(window_start) = self
+            .dispute_window
+            .iter()
+            .position(|&id| id == chargeback.tx_id)
+        {
+            if self.pending().total < 0_f64 {
+                let candidates = self
+                    .dispute_window
+                    .iter()
+                    .skip(window_start)
+                    .filter_map(|id| match self.processed_transactions.get(id) {
+                        Some(Transaction::Withdrawal(withdrawal)) if is_reversible(withdrawal) => {
+                            Some(ReversedWithdrawal::new(withdrawal.tx_id, withdrawal.amount))
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let ids_to_reverse = self.shortfall_policy.select(event.shortfall, &candidates);
+                for id in ids_to_reverse {
+                    if let Some(amount) = self.reverse_withdrawal(id) {
+                        event.reversed.push(ReversedWithdrawal::new(id, amount));
+                        self.record_outcome(
+                            id,
+                            RawTransactionVariant::Withdrawal.into(),
+                            Err(Reason::ReversedByChargeback),
+                            shared,
+                        );
+                    }
+                }
+            }
+        }
+        event.uncovered = (-self.pending().total).max(0.0);
+        if event.uncovered > 0_f64 {
+            // The client's negative balance is kept for collections, with an allowance for the
+            // loss until it is repaid.
+            self.post(
+                chargeback.tx_id,
+                RawTransactionVariant::Chargeback.into(),
+                &[
+                    (LedgerAccount::ChargebackLosses, event.uncovered),
+                    (LedgerAccount::ShortfallAllowance, -event.uncovered),
+                ],
+            );
+            if let Some(ref mut debt) = self.debt {
+                debt.incur(chargeback.tx_id, event.uncovered);
+            }
+        }
+        self.chargeback_events.push(event);
+    }
+
+    /// Finalizes a transaction by removing it from the dispute window.  An open dispute on the
+    /// transaction is resolved, and an open authorization expires, and either is recorded as such.
+    /// A deposit held by a rule is kept until a resolve releases it or a dispute takes it over.
+    fn finalize_transaction(&mut self, shared: &mut SharedState) {
+        if let Some(id) = self.dispute_window.pop_front() {
+            if let Some(mut old_tx) = self.processed_transactions.remove(&id) {
+                if let Transaction::Deposit(deposit) = old_tx {
+                    self.deposits_by_amount
+                        .remove(deposit.tx_id, deposit.amount);
+                    if deposit.held_by_rule {
+                        self.processed_transactions.insert(id, old_tx);
+                        return;
+                    }
+                }
+                let resolved = match old_tx {
+                    Transaction::Deposit(deposit) => {
+                        deposit.disputed
+                            && !deposit.resolved
+                            && self
+                                .process_resolve(
+                                    &Resolve {
+                                        client_id: deposit.client_id,
+                                        tx_id: deposit.tx_id,
+                                    },
+                                    Some(&mut old_tx),
+                                    shared,
+                                )
+                                .is_ok()
+                    }
+                    Transaction::Withdrawal(withdrawal) => {
+                        withdrawal.disputed
+                            && !withdrawal.resolved
+                            && self
+                                .process_resolve(
+                                    &Resolve {
+                                        client_id: withdrawal.client_id,
+                                        tx_id: withdrawal.tx_id,
+                                    },
+                                    Some(&mut old_tx),
+                                    shared,
+                                )
+                                .is_ok()
+                    }
+                    Transaction::Authorization(authorization) => {
+                        if !authorization.released {
+                            self.expire_authorization(&authorization, shared);
+                        }
+                        false
+                    }
+                    Transaction::Dispute(_)
+                    | Transaction::Resolve(_)
+                    | Transaction::Chargeback(_)
+                    | Transaction::Capture(_)
+                    | Transaction::Void(_) => {
+                        eprintln!("Failed to finalize transaction");
+                        false
+                    }
+                    Transaction::Refund(_) => false,
+                };
+                if resolved {
+                    self.record_outcome(
+                        id,
+                        RawTransactionVariant::Resolve.into(),
+                        Err(Reason::DisputeWindowExpired),
+                        shared,
+                    );
+                }
+            }
+            self.tx_partners.remove(&id);
+        }
+    }
+
+    /// Parses and processes a chargeback transaction, recording its outcome before any withdrawals
+    /// reversed to cover the shortfall it leaves.
+    fn process_chargeback_transaction(
+        &mut self,
+        transaction: RawTransaction,
+        shared: &mut SharedState,
+    ) {
+        let tx_id = transaction.tx_id;
+        let variant = transaction.variant;
+        let before = self.pending().total;
+        let chargeback: Result<Chargeback, Reason> = transaction.try_into().map_err(|error| {
+            eprintln!("Failed to parse chargeback transaction: {error}");
+            Reason::InvalidTransaction
+        });
+        let result = chargeback.as_ref().map_or_else(
+            |reason| Err(*reason),
+            |chargeback| self.process_chargeback(chargeback, shared),
+        );
+        self.record_outcome(tx_id, variant.into(), result.map(|()| Vec::new()), shared);
+        if let (Ok(chargeback), Ok(())) = (chargeback, result) {
+            self.charge_dispute_fee(tx_id, before - self.pending().total, shared);
+            self.cover_chargeback_shortfall(&chargeback, shared);
+        }
+    }
+
+    /// Charges the dispute fee, if any, for a chargeback of `charged_back`, earning it as fee
+    /// income.  The fee is rounded to the minor unit, and may take the balance below zero for the
+    /// shortfall policy to cover.
+    fn charge_dispute_fee(&mut self, tx_id: u32, charged_back: f64, shared: &mut SharedState) {
+        let Some(dispute_fee) = self.dispute_fee else {
+            return;
+        };
+        let places = self.currency.map_or(4, Currency::minor_units);
+        let fee = Rounding::HalfUp.round(dispute_fee.amount(charged_back), places);
+        if fee <= 0.0_f64 {
+            return;
+        }
+        self.fee_income = fee;
+        self.post(
+            tx_id,
+            InternalVariant::DisputeFee.into(),
+            &[
+                (LedgerAccount::ClientAvailable, fee),
+                (LedgerAccount::FeeIncome, -fee),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            tx_id,
+            EntryKind::DisputeFee,
+            Direction::Debit,
+            fee,
+        );
+        self.record_internal(tx_id, InternalVariant::DisputeFee, -fee, shared);
+    }
+
+    /// Parses and processes a convert out of the client's account, keeping its conversion for the
+    /// account of the currency converted into to be credited with.
+    fn process_conversion_transaction(
+        &mut self,
+        transaction: RawTransaction,
+        shared: &SharedState,
+    ) -> Result<(), Reason> {
+        let result = Convert::try_from(transaction)
+            .map_err(|error| {
+                eprintln!("Failed to parse convert transaction: {error}");
+                Reason::InvalidTransaction
+            })
+            .and_then(|convert| self.process_convert(&convert, shared));
+        self.conversion_credit = result.as_ref().ok().cloned();
+        result.map(|_| ())
+    }
+
+    /// Credits the client's account with the amount a convert out of another of the client's
+    /// accounts converted into its currency, and records the credit.
+    #[inline]
+    pub fn credit_conversion(&mut self, conversion: &Conversion, shared: &mut SharedState) {
+        self.post(
+            conversion.tx,
+            InternalVariant::ConversionCredit.into(),
+            &[
+                (LedgerAccount::Settlement, conversion.credited),
+                (LedgerAccount::ClientAvailable, -conversion.credited),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            conversion.tx,
+            EntryKind::Conversion,
+            Direction::Credit,
+            conversion.credited,
+        );
+        self.record_internal(
+            conversion.tx,
+            InternalVariant::ConversionCredit,
+            conversion.credited,
+            shared,
+        );
+    }
+
+    /// Processes a convert, taking the amount out of the client's account and returning the
+    /// conversion, which the account of the currency it converts into is credited with.
+    fn process_convert(
+        &mut self,
+        convert: &Convert,
+        shared: &SharedState,
+    ) -> Result<Conversion, Reason> {
+        if convert.client_id != self.id {
+            eprintln!("Received convert from wrong client: {}", convert.client_id);
+            return Err(Reason::WrongClient);
+        }
+        let rate = self
+            .currency
+            .filter(|&from| from != convert.to_currency)
+            .and_then(|from| self.rates.rate(from, convert.to_currency, shared.clock))
+            .ok_or_else(|| {
+                eprintln!("No rate to convert into {}", convert.to_currency);
+                Reason::NoExchangeRate
+            })?;
+        let pending = self.pending();
+        if let Err(reason) =
+            self.funds_availability
+                .check(convert.amount, pending.available, pending.total)
+        {
+            eprintln!("Convert refused: {reason:?}");
+            return Err(reason);
+        }
+        let conversion = Conversion::new(
+            self.id,
+            convert.tx_id,
+            convert.amount,
+            rate,
+            self.fx_rounding,
+        );
+        // The spread and rounding remainder are kept as fee income, and the rest is sold at the rate.
+        self.fee_income = convert.amount - conversion.credited / rate.rate;
+        self.post(
+            convert.tx_id,
+            RawTransactionVariant::Convert.into(),
+            &[
+                (LedgerAccount::ClientAvailable, convert.amount),
+                (LedgerAccount::FeeIncome, -self.fee_income),
+                (LedgerAccount::Settlement, self.fee_income - convert.amount),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            convert.tx_id,
+            EntryKind::Conversion,
+            Direction::Debit,
+            convert.amount,
+        );
+        self.conversions.push(conversion.clone());
+        Ok(conversion)
+    }
+
+    /// Parses and processes an authorize, capture or void transaction.
+    fn process_authorization_transaction(
+        &mut self,
+        transaction: RawTransaction,
+        shared: &mut SharedState,
+    ) -> Result<(), Reason> {
+        let parsed = match transaction.variant {
+            RawTransactionVariant::Authorize => transaction
+                .try_into()
+                .map(|authorization| self.process_authorization(authorization, shared)),
+            RawTransactionVariant::Capture => transaction
+                .try_into()
+                .map(|capture| self.process_capture(&capture, shared)),
+            RawTransactionVariant::Void => {
+                transaction.try_into().map(|void| self.process_void(&void))
+            }
+            RawTransactionVariant::Deposit
+            | RawTransactionVariant::Withdrawal
+            | RawTransactionVariant::Dispute
+            | RawTransactionVariant::Resolve
+            | RawTransactionVariant::Chargeback
+            | RawTransactionVariant::Convert
+            | RawTransactionVariant::Close
+            | RawTransactionVariant::Accrue
+            | RawTransactionVariant::Refund => Err(TransactionError::InvalidAuthorization),
+        };
+        parsed.unwrap_or_else(|error| {
+            eprintln!("Failed to parse authorization transaction: {error}");
+            Err(Reason::InvalidTransaction)
+        })
+    }
+
+    /// Processes an authorization, holding the amount authorized.
+    fn process_authorization(
+        &mut self,
+        authorization: Authorization,
+        shared: &SharedState,
+    ) -> Result<(), Reason> {
+        if authorization.client_id != self.id {
+            eprintln!(
+                "Received authorization from wrong client: {}",
+                authorization.client_id
+            );
+            return Err(Reason::WrongClient);
+        }
+        let pending = self.pending();
+        if let Err(reason) =
+            self.funds_availability
+                .check(authorization.amount, pending.available, pending.total)
+        {
+            eprintln!("Authorization refused: {reason:?}");
+            return Err(reason);
+        }
+        self.post(
+            authorization.tx_id,
+            RawTransactionVariant::Authorize.into(),
+            &[
+                (LedgerAccount::ClientAvailable, authorization.amount),
+                (LedgerAccount::ClientHeld, -authorization.amount),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            authorization.tx_id,
+            EntryKind::Authorization,
+            Direction::Debit,
+            authorization.amount,
+        );
+        self.open_authorizations.push_back(Activity {
+            seq: shared.sequence,
+            at: shared.clock,
+            tx_id: authorization.tx_id,
+            variant: RawTransactionVariant::Authorize,
+            amount: authorization.amount,
+        });
+        self.dispute_window.push_back(authorization.tx_id);
+        self.open_holds += authorization.amount;
+        self.processed_transactions.insert(
+            authorization.tx_id,
+            Transaction::Authorization(authorization),
+        );
+        Ok(())
+    }
+
+    /// Looks up the open authorization a capture or void refers to.
+    fn open_authorization(&self, client_id: u16, tx_id: u32) -> Result<Authorization, Reason> {
+        match self.processed_transactions.get(&tx_id) {
+            Some(&Transaction::Authorization(authorization)) => {
+                if client_id != authorization.client_id {
+                    eprintln!("Received capture or void from wrong client: {client_id}");
+                    Err(Reason::WrongClient)
+                } else if authorization.released {
+                    eprintln!("Authorization has already been released: {tx_id}");
+                    Err(Reason::AuthorizationReleased)
+                } else {
+                    Ok(authorization)
+                }
+            }
+            Some(_) => {
+                eprintln!("Transaction is not an authorization: {tx_id}");
+                Err(Reason::NotAnAuthorization)
+            }
+            None => {
+                eprintln!("Authorization not found: {tx_id}");
+                Err(Reason::UnknownTransaction)
+            }
+        }
+    }
+
+    /// Processes a capture, turning the authorization into a withdrawal of the amount captured.
+    fn process_capture(
+        &mut self,
+        capture: &Capture,
+        shared: &mut SharedState,
+    ) -> Result<(), Reason> {
+        let authorization = self.open_authorization(capture.client_id, capture.tx_id)?;
+        let amount = capture.amount.unwrap_or(authorization.amount);
+        if amount > authorization.amount {
+            eprintln!("Capture exceeds the authorization: {}", capture.tx_id);
+            return Err(Reason::CaptureExceedsAuthorization);
+        }
+        self.post(
+            capture.tx_id,
+            RawTransactionVariant::Capture.into(),
+            &[
+                (LedgerAccount::ClientHeld, amount),
+                (LedgerAccount::Settlement, -amount),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            capture.tx_id,
+            EntryKind::Withdrawal,
+            Direction::Debit,
+            amount,
+        );
+        self.release_authorization(
+            capture.tx_id,
+            RawTransactionVariant::Capture,
+            authorization.amount - amount,
+        );
+        self.record_activity(
+            capture.tx_id,
+            RawTransactionVariant::Withdrawal,
+            amount,
+            shared,
+        );
+        self.open_holds -= authorization.amount;
+        self.processed_transactions.insert(
+            capture.tx_id,
+            Transaction::Withdrawal(Withdrawal {
+                client_id: authorization.client_id,
+                tx_id: authorization.tx_id,
+                amount,
+                disputed: false,
+                resolved: false,
+                failed: false,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Processes a void, releasing the authorization.
+    fn process_void(&mut self, void: &Void) -> Result<(), Reason> {
+        let mut authorization = self.open_authorization(void.client_id, void.tx_id)?;
+        authorization.released = true;
+        self.open_holds -= authorization.amount;
+        self.processed_transactions
+            .insert(void.tx_id, Transaction::Authorization(authorization));
+        self.release_authorization(
+            void.tx_id,
+            RawTransactionVariant::Void,
+            authorization.amount,
+        );
+        Ok(())
+    }
+
+    /// Closes an authorization, releasing `amount` of its hold to the available balance for the
+    /// capture, void or expiry named by `variant`.
+    fn release_authorization(&mut self, tx_id: u32, variant: RawTransactionVariant, amount: f64) {
+        self.open_authorizations.retain(|a| a.tx_id != tx_id);
+        if amount > 0_f64 {
+            self.post(
+                tx_id,
+                variant.into(),
+                &[
+                    (LedgerAccount::ClientHeld, amount),
+                    (LedgerAccount::ClientAvailable, -amount),
+                ],
+            );
+            record_entry(
+                &mut self.statement,
+                tx_id,
+                EntryKind::Release,
+                Direction::Credit,
+                amount,
+            );
+        }
+    }
+
+    /// Expires the open authorizations that are past their deadline.
+    fn expire_authorizations(&mut self, shared: &mut SharedState) {
+        let Some(expiry) = self.authorization_expiry else {
+            return;
+        };
+        while let Some(oldest) = self.open_authorizations.front().copied() {
+            if expiry.contains(shared.sequence, shared.clock, oldest.seq, oldest.at) {
+                break;
+            }
+            let Some(Transaction::Authorization(authorization)) =
+                self.processed_transactions.get_mut(&oldest.tx_id)
+            else {
+                self.open_authorizations.pop_front();
+                continue;
+            };
+            authorization.released = true;
+            let authorization = *authorization;
+            self.expire_authorization(&authorization, shared);
+        }
+    }
+
+    /// Releases an authorization that expired, and records it as such.
+    fn expire_authorization(&mut self, authorization: &Authorization, shared: &mut SharedState) {
+        eprintln!("Authorization expired: {}", authorization.tx_id);
+        self.open_holds -= authorization.amount;
+        self.release_authorization(
+            authorization.tx_id,
+            RawTransactionVariant::Authorize,
+            authorization.amount,
+        );
+        self.record_outcome(
+            authorization.tx_id,
+            RawTransactionVariant::Authorize.into(),
+            Err(Reason::AuthorizationExpired),
+            shared,
+        );
+    }
+
+    /// Parses and processes a deposit transaction.
+    fn process_deposit_transaction(
+        &mut self,
+        transaction: RawTransaction,
+        shared: &mut SharedState,
+    ) -> Result<Vec<Note>, Reason> {
+        transaction.try_into().map_or_else(
+            |_| {
+                eprintln!("Failed to parse deposit transaction");
+                Err(Reason::InvalidTransaction)
+            },
+            |deposit| self.process_deposit(deposit, shared),
+        )
+    }
+
+    /// Processes a deposit transaction.
+    fn process_deposit(
+        &mut self,
+        mut deposit: Deposit,
+        shared: &mut SharedState,
+    ) -> Result<Vec<Note>, Reason> {
+        if deposit.client_id != self.id {
+            eprintln!("Received deposit from wrong client: {}", deposit.client_id);
+            return Err(Reason::WrongClient);
+        }
+        let verdict = self.evaluate_rules(
+            PendingTransaction::new(
+                deposit.tx_id,
+                RawTransactionVariant::Deposit,
+                deposit.amount,
+            ),
+            self.pending(),
+            shared,
+        );
+        if verdict == Verdict::Reject {
+            return Err(Reason::RejectedByRule);
+        }
+        // Held deposits stay in the held balance until they are resolved or disputed.
+        deposit.held_by_rule = verdict == Verdict::Hold;
+        let credited = if deposit.held_by_rule {
+            self.open_holds += deposit.amount;
+            LedgerAccount::ClientHeld
+        } else {
+            LedgerAccount::ClientAvailable
+        };
+        self.post(
+            deposit.tx_id,
+            RawTransactionVariant::Deposit.into(),
+            &[
+                (LedgerAccount::Settlement, deposit.amount),
+                (credited, -deposit.amount),
+            ],
+        );
+        if let Some(ref mut debt) = self.debt {
+            let repaid = debt.repay(deposit.amount);
+            self.post(
+                deposit.tx_id,
+                RawTransactionVariant::Deposit.into(),
+                &[
+                    (LedgerAccount::ShortfallAllowance, repaid),
+                    (LedgerAccount::ChargebackLosses, -repaid),
+                ],
+            );
+        }
+        record_entry(
+            &mut self.statement,
+            deposit.tx_id,
+            EntryKind::Deposit,
+            Direction::Credit,
+            deposit.amount,
+        );
+        self.record_activity(
+            deposit.tx_id,
+            RawTransactionVariant::Deposit,
+            deposit.amount,
+            shared,
+        );
+        if verdict == Verdict::Hold {
+            record_entry(
+                &mut self.statement,
+                deposit.tx_id,
+                EntryKind::Hold,
+                Direction::Debit,
+                deposit.amount,
+            );
+        }
+        if verdict != Verdict::Hold {
+            self.reserve_deposit(deposit.tx_id, deposit.amount, shared);
+        }
+        self.dispute_window.push_back(deposit.tx_id);
+        self.processed_transactions
+            .insert(deposit.tx_id, Transaction::Deposit(deposit));
+        Ok(match verdict {
+            Verdict::Hold => vec![Note::HeldByRule],
+            Verdict::Flag => vec![Note::FlaggedByRule],
+            Verdict::Allow | Verdict::Reject => Vec::new(),
+        })
+    }
+
+    /// Evaluates the rules for a deposit or withdrawal, flagging the client if any rule matches,
+    /// and returns the strictest verdict.
+    fn evaluate_rules(
+        &mut self,
+        transaction: PendingTransaction,
+        balances: BalanceSnapshot,
+        shared: &mut SharedState,
+    ) -> Verdict {
+        let view = ClientView {
+            client_id: self.id,
+            balances,
+            processed_transactions: &self.processed_transactions,
+            activity: &shared.activity,
+            deposits_by_amount: &self.deposits_by_amount,
+            sequence: shared.sequence,
+            clock: shared.clock,
+        };
+        let mut verdict = Verdict::Allow;
+        let mut matched_rule = None;
+        for rule in self.rules.iter() {
+            let matched = rule.evaluate(&view, &transaction);
+            if matched != Verdict::Allow {
+                eprintln!(
+                    "Rule {} matched transaction {} of client {}: {:?}",
+                    rule.name(),
+                    transaction.tx_id,
+                    self.id,
+                    matched
+                );
+            }
+            if matched > verdict {
+                verdict = matched;
+                matched_rule = Some(rule.name());
+            }
+        }
+        if verdict != Verdict::Allow {
+            shared.flagged = true;
+        }
+        self.matched_rule = matched_rule;
+        verdict
+    }
+
+    /// Records an accepted deposit or withdrawal in the activity log, and indexes deposits by
+    /// amount.
+    fn record_activity(
+        &mut self,
+        tx_id: u32,
+        variant: RawTransactionVariant,
+        amount: f64,
+        shared: &mut SharedState,
+    ) {
+        let activity = Activity {
+            seq: shared.sequence,
+            at: shared.clock,
+            tx_id,
+            variant,
+            amount,
+        };
+        if variant == RawTransactionVariant::Deposit {
+            self.deposits_by_amount.insert(activity);
+        }
+        shared.activity.push(activity);
+    }
+
+    /// Checks a withdrawal against the limits of the client's profile.
+    fn check_withdrawal_limits(&self, amount: f64, shared: &SharedState) -> Result<(), Reason> {
+        let Some(profile) = self.profile else {
+            return Ok(());
+        };
+        if profile.max_withdrawal.is_some_and(|max| amount > max) {
+            return Err(Reason::WithdrawalLimitExceeded);
+        }
+        if let Some(cap) = profile.withdrawal_cap {
+            let withdrawn = shared.activity.total_in_window(
+                profile.cap_window(),
+                shared.sequence,
+                shared.clock,
+                RawTransactionVariant::Withdrawal,
+            );
+            if withdrawn + amount > cap {
+                return Err(Reason::WithdrawalCapExceeded);
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes a withdrawal transaction.
+    fn process_withdrawal(
+        &mut self,
+        mut withdrawal: Withdrawal,
+        shared: &mut SharedState,
+    ) -> Result<Vec<Note>, Reason> {
+        if withdrawal.client_id != self.id {
+            eprintln!(
+                "Received withdrawal from wrong client: {}",
+                withdrawal.client_id
+            );
+            return Err(Reason::WrongClient);
+        }
+        let pending = self.pending();
+        let checked = self
+            .check_withdrawal_limits(withdrawal.amount, shared)
+            .and_then(|()| {
+                let verdict = self.evaluate_rules(
+                    PendingTransaction::new(
+                        withdrawal.tx_id,
+                        RawTransactionVariant::Withdrawal,
+                        withdrawal.amount,
+                    ),
+                    pending,
+                    shared,
+                );
+                match verdict {
+                    Verdict::Reject => Err(Reason::RejectedByRule),
+                    Verdict::Hold => Err(Reason::HeldByRule),
+                    Verdict::Allow | Verdict::Flag => self
+                        .funds_availability
+                        .check(withdrawal.amount, pending.available, pending.total)
+                        .map(|()| verdict),
+                }
+            });
+        let verdict = match checked {
+            Ok(verdict) => verdict,
+            Err(reason) => {
+                eprintln!("Withdrawal refused: {reason:?}");
+                withdrawal.failed = true;
+                self.processed_transactions
+                    .insert(withdrawal.tx_id, Transaction::Withdrawal(withdrawal));
+                return Err(reason);
+            }
+        };
+        self.post(
+            withdrawal.tx_id,
+            RawTransactionVariant::Withdrawal.into(),
+            &[
+                (LedgerAccount::ClientAvailable, withdrawal.amount),
+                (LedgerAccount::Settlement, -withdrawal.amount),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            withdrawal.tx_id,
+            EntryKind::Withdrawal,
+            Direction::Debit,
+            withdrawal.amount,
+        );
+        self.record_activity(
+            withdrawal.tx_id,
+            RawTransactionVariant::Withdrawal,
+            withdrawal.amount,
+            shared,
+        );
+        self.dispute_window.push_back(withdrawal.tx_id);
+        self.processed_transactions
+            .insert(withdrawal.tx_id, Transaction::Withdrawal(withdrawal));
+        let mut notes = Vec::new();
+        if verdict == Verdict::Flag {
+            notes.push(Note::FlaggedByRule);
+        }
+        if self.pending().available < 0.0_f64 {
+            notes.push(Note::AvailableBelowZero);
+        }
+        Ok(notes)
+    }
+
+    /// Parses and processes a refund transaction.
+    fn process_refund_transaction(&mut self, transaction: RawTransaction) -> Result<(), Reason> {
+        transaction.try_into().map_or_else(
+            |_| {
+                eprintln!("Failed to parse refund transaction");
+                Err(Reason::InvalidTransaction)
+            },
+            |refund| self.process_refund(refund),
+        )
+    }
+
+    /// Processes a refund of part or all of an earlier deposit.  The refunds of a deposit may not
+    /// add up to more than its amount, and a deposit that has been disputed cannot be refunded.  A
+    /// refund of a deposit held by a rule is paid out of the hold.
+    fn process_refund(&mut self, refund: Refund) -> Result<(), Reason> {
+        if refund.client_id != self.id {
+            eprintln!("Received refund from wrong client: {}", refund.client_id);
+            return Err(Reason::WrongClient);
+        }
+        let deposit = match self.processed_transactions.get(&refund.original_tx) {
+            Some(&Transaction::Deposit(deposit)) => deposit,
+            Some(_) => {
+                eprintln!(
+                    "Refunded transaction is not a deposit: {}",
+                    refund.original_tx
+                );
+                return Err(Reason::NotADeposit);
+            }
+            None => {
+                eprintln!("Refunded transaction not found: {}", refund.original_tx);
+                return Err(Reason::UnknownTransaction);
+            }
+        };
+        if deposit.disputed {
+            eprintln!("Refunded deposit has been disputed: {}", refund.original_tx);
+            return Err(Reason::AlreadyDisputed);
+        }
+        if refund.amount > deposit.disputable() {
+            eprintln!("Refund exceeds deposit: {}", refund.original_tx);
+            return Err(Reason::RefundExceedsDeposit);
+        }
+        if !deposit.held_by_rule {
+            let pending = self.pending();
+            if let Err(reason) =
+                self.funds_availability
+                    .check(refund.amount, pending.available, pending.total)
+            {
+                eprintln!("Refund refused: {reason:?}");
+                return Err(reason);
+            }
+        }
+        if let Some(Transaction::Deposit(deposit)) =
+            self.processed_transactions.get_mut(&refund.original_tx)
+        {
+            deposit.refunded += refund.amount;
+        }
+        // A refund is paid out by the partner that collected the deposit, unless it names its own.
+        if let Some(partner) = self.tx_partners.get(&refund.original_tx).cloned() {
+            self.tx_partners.entry(refund.tx_id).or_insert(partner);
+        }
+        let debited = if deposit.held_by_rule {
+            self.open_holds -= refund.amount;
+            LedgerAccount::ClientHeld
+        } else {
+            LedgerAccount::ClientAvailable
+        };
+        self.post(
+            refund.tx_id,
+            RawTransactionVariant::Refund.into(),
+            &[
+                (debited, refund.amount),
+                (LedgerAccount::Settlement, -refund.amount),
+            ],
+        );
+        record_entry(
+            &mut self.statement,
+            refund.tx_id,
+            EntryKind::Refund,
+            Direction::Debit,
+            refund.amount,
+        );
+        self.dispute_window.push_back(refund.tx_id);
+        self.processed_transactions
+            .insert(refund.tx_id, Transaction::Refund(refund));
+        Ok(())
+    }
+
+    /// Process a dispute
+    fn process_dispute(&mut self, dispute: &Dispute) -> Result<(), Reason> {
+        if let Some(tx) = self.processed_transactions.get_mut(&dispute.tx_id) {
+            match tx {
+                Transaction::Deposit(deposit) => {
+                    if dispute.client_id != deposit.client_id {
+                        eprintln!("Received dispute from wrong client: {}", dispute.client_id);
+                        return Err(Reason::WrongClient);
+                    }
+                    if deposit.disputed || deposit.resolved {
+                        // Transaction has already been disputed
+                        eprintln!("Transaction has already been disputed: {}", dispute.tx_id);
+                        return Err(Reason::AlreadyDisputed);
+                    }
+                    deposit.disputed = true;
+                    if deposit.held_by_rule {
+                        // The funds are already held: the dispute takes over the rule's hold.
+                        deposit.held_by_rule = false;
+                    } else {
+                        // The share kept in the reserve is already held, and joins the dispute.
+                        let (tx_id, disputable) = (deposit.tx_id, deposit.disputable());
+                        let held = disputable - self.draw_reserve(tx_id, disputable);
+                        self.open_holds += disputable;
+                        self.post(
+                            tx_id,
+                            RawTransactionVariant::Dispute.into(),
+                            &[
+                                (LedgerAccount::ClientAvailable, held),
+                                (LedgerAccount::ClientHeld, -held),
+                            ],
+                        );
+                        record_entry(
+                            &mut self.statement,
+                            tx_id,
+                            EntryKind::Dispute,
+                            Direction::Debit,
+                            held,
+                        );
+                    }
+                }
+                Transaction::Withdrawal(withdrawal) => {
+                    if dispute.client_id != withdrawal.client_id {
+                        eprintln!("Received dispute from wrong client: {}", dispute.client_id);
+                        return Err(Reason::WrongClient);
+                    }
+                    if withdrawal.disputed || withdrawal.resolved {
+                        // Transaction has already been disputed
+                        eprintln!("Transaction has already been disputed: {}", dispute.tx_id);
+                        return Err(Reason::AlreadyDisputed);
+                    }
+                    withdrawal.disputed = true;
+                    if !withdrawal.failed {
+                        self.open_holds -= withdrawal.amount;
+                        self.ledger.post(
+                            withdrawal.tx_id,
+                            self.id,
+                            self.currency,
+                            RawTransactionVariant::Dispute.into(),
+                            &[
+                                (LedgerAccount::ClientHeld, withdrawal.amount),
+                                (LedgerAccount::ClientAvailable, -withdrawal.amount),
+                            ],
+                        );
+                        record_entry(
+                            &mut self.statement,
+                            withdrawal.tx_id,
+                            EntryKind::Dispute,
+                            Direction::Credit,
+                            withdrawal.amount,
+                        );
+                    }
+                }
+                Transaction::Dispute(_)
+                | Transaction::Resolve(_)
+                | Transaction::Chargeback(_)
+                | Transaction::Authorization(_)
+                | Transaction::Capture(_)
+                | Transaction::Void(_)
+                | Transaction::Refund(_) => {
+                    // The transaction referenced is not a deposit or withdrawal
+                    eprintln!(
+                        "Transaction is not a deposit or withdrawal: {}",
+                        dispute.tx_id
+                    );
+                    return Err(Reason::NotDisputable);
+                }
+            }
+            return Ok(());
+        }
+        eprintln!("Disputed transaction not found: {}", dispute.tx_id);
+        Err(Reason::UnknownTransaction)
+    }
+
+    /// Processes a `Resolve` transaction
+    fn process_resolve(
+        &mut self,
+        resolve: &Resolve,
+        tx: Option<&mut Transaction>,
+        shared: &SharedState,
+    ) -> Result<(), Reason> {
+        let transaction = tx.or_else(|| self.processed_transactions.get_mut(&resolve.tx_id));
+        if let Some(t) = transaction {
+            match t {
+                Transaction::Deposit(deposit) => {
+                    if resolve.client_id != deposit.client_id {
+                        eprintln!("Received resolve from wrong client: {}", resolve.client_id);
+                        return Err(Reason::WrongClient);
+                    }
+                    let kind = if deposit.held_by_rule {
+                        // A resolve releases the hold a rule put on the deposit.
+                        deposit.held_by_rule = false;
+                        EntryKind::HoldRelease
+                    } else if !deposit.disputed || deposit.resolved {
+                        // Transaction has not been disputed or has already been resolved
+                        eprintln!(
+                            "Transaction has not been disputed or has already been resolved: {}",
                             resolve.tx_id
                         );
-                        return;
+                        return Err(Reason::NotDisputed);
+                    } else {
+                        deposit.resolved = true;
+                        EntryKind::Resolve
+                    };
+                    let (tx_id, disputable) = (deposit.tx_id, deposit.disputable());
+                    let released = disputable - self.restore_reserve(tx_id, shared);
+                    self.open_holds -= disputable;
+                    self.post(
+                        tx_id,
+                        RawTransactionVariant::Resolve.into(),
+                        &[
+                            (LedgerAccount::ClientHeld, released),
+                            (LedgerAccount::ClientAvailable, -released),
+                        ],
+                    );
+                    record_entry(
+                        &mut self.statement,
+                        tx_id,
+                        kind,
+                        Direction::Credit,
+                        released,
+                    );
+                }
+                Transaction::Withdrawal(withdrawal) => {
+                    if resolve.client_id != withdrawal.client_id {
+                        eprintln!("Received resolve from wrong client: {}", resolve.client_id);
+                        return Err(Reason::WrongClient);
+                    }
+                    if !withdrawal.disputed || withdrawal.resolved {
+                        // Transaction has not been disputed or has already been resolved
+                        eprintln!(
+                            "Transaction has not been disputed or has already been resolved: {}",
+                            resolve.tx_id
+                        );
+                        return Err(Reason::NotDisputed);
+                    }
+                    withdrawal.resolved = true;
+                    if !withdrawal.failed {
+                        self.open_holds += withdrawal.amount;
+                        self.ledger.post(
+                            withdrawal.tx_id,
+                            self.id,
+                            self.currency,
+                            RawTransactionVariant::Resolve.into(),
+                            &[
+                                (LedgerAccount::ClientAvailable, withdrawal.amount),
+                                (LedgerAccount::ClientHeld, -withdrawal.amount),
+                            ],
+                        );
+                        record_entry(
+                            &mut self.statement,
+                            withdrawal.tx_id,
+                            EntryKind::Resolve,
+                            Direction::Debit,
+                            withdrawal.amount,
+                        );
+                    }
+                }
+                Transaction::Dispute(_)
+                | Transaction::Resolve(_)
+                | Transaction::Chargeback(_)
+                | Transaction::Authorization(_)
+                | Transaction::Capture(_)
+                | Transaction::Void(_)
+                | Transaction::Refund(_) => {
+                    // The transaction is not a deposit or a withdrawal
+                    eprintln!(
+                        "Transaction is not a deposit or withdrawal: {}",
+                        resolve.tx_id
+                    );
+                    return Err(Reason::NotDisputable);
+                }
+            }
+            return Ok(());
+        }
+        eprintln!("Resolved transaction not found: {}", resolve.tx_id);
+        Err(Reason::UnknownTransaction)
+    }
+
+    /// Processes a `Chargeback` transaction
+    fn process_chargeback(
+        &mut self,
+        chargeback: &Chargeback,
+        shared: &mut SharedState,
+    ) -> Result<(), Reason> {
+        if let Some(tx) = self.processed_transactions.get_mut(&chargeback.tx_id) {
+            match tx {
+                Transaction::Deposit(deposit) => {
+                    if chargeback.client_id != deposit.client_id {
+                        eprintln!(
+                            "Received chargeback from wrong client: {}",
+                            chargeback.client_id
+                        );
+                        return Err(Reason::WrongClient);
+                    }
+                    if !deposit.disputed || deposit.resolved {
+                        // Transaction has not been disputed or has already been resolved
+                        eprintln!(
+                            "Transaction has not been disputed or has already been resolved: {}",
+                            chargeback.tx_id
+                        );
+                        return Err(Reason::NotDisputed);
                     }
                     deposit.resolved = true;
-                    *pending_held_balance -= deposit.amount;
-                    *pending_available_balance += deposit.amount;
+                    // The share drawn from the reserve is charged back with the rest of the hold.
+                    self.disputed_reserve.remove(&deposit.tx_id);
+                    self.open_holds -= deposit.disputable();
+                    self.ledger.post(
+                        deposit.tx_id,
+                        self.id,
+                        self.currency,
+                        RawTransactionVariant::Chargeback.into(),
+                        &[
+                            (LedgerAccount::ClientHeld, deposit.disputable()),
+                            (LedgerAccount::Settlement, -deposit.disputable()),
+                        ],
+                    );
+                    record_entry(
+                        &mut self.statement,
+                        deposit.tx_id,
+                        EntryKind::Chargeback,
+                        Direction::Debit,
+                        deposit.disputable(),
+                    );
+                    self.lock(shared);
                 }
                 Transaction::Withdrawal(withdrawal) => {
-                    if !withdrawal.disputed
-                        || withdrawal.resolved
-                        || resolve.client_id != withdrawal.client_id
-                    {
+                    if chargeback.client_id != withdrawal.client_id {
+                        eprintln!(
+                            "Received chargeback from wrong client: {}",
+                            chargeback.client_id
+                        );
+                        return Err(Reason::WrongClient);
+                    }
+                    if !withdrawal.disputed || withdrawal.resolved {
                         // Transaction has not been disputed or has already been resolved
                         eprintln!(
                             "Transaction has not been disputed or has already been resolved: {}",
-                            resolve.tx_id
+                            chargeback.tx_id
                         );
-                        return;
+                        return Err(Reason::NotDisputed);
                     }
                     withdrawal.resolved = true;
                     if !withdrawal.failed {
-                        *pending_held_balance += withdrawal.amount;
-                        *pending_available_balance -= withdrawal.amount;
+                        self.open_holds += withdrawal.amount;
+                        self.ledger.post(
+                            withdrawal.tx_id,
+                            self.id,
+                            self.currency,
+                            RawTransactionVariant::Chargeback.into(),
+                            &[
+                                (LedgerAccount::Settlement, withdrawal.amount),
+                                (LedgerAccount::ClientHeld, -withdrawal.amount),
+                            ],
+                        );
+                        record_entry(
+                            &mut self.statement,
+                            withdrawal.tx_id,
+                            EntryKind::Chargeback,
+                            Direction::Credit,
+                            withdrawal.amount,
+                        );
                     }
+
+                    self.lock(shared);
                 }
-                Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_) => {
+                Transaction::Dispute(_)
+                | Transaction::Resolve(_)
+                | Transaction::Chargeback(_)
+                | Transaction::Authorization(_)
+                | Transaction::Capture(_)
+                | Transaction::Void(_)
+                | Transaction::Refund(_) => {
                     // The transaction is not a deposit or a withdrawal
                     eprintln!(
                         "Transaction is not a deposit or withdrawal: {}",
-                        resolve.tx_id
+                        chargeback.tx_id
                     );
+                    return Err(Reason::NotDisputable);
                 }
             }
-        }
+            return Ok(());
+        }
+        eprintln!("Charged back transaction not found: {}", chargeback.tx_id);
+        Err(Reason::UnknownTransaction)
+    }
+
+    /// Reverses a withdrawal transaction, marking it as failed.  Returns the amount reversed, if the
+    /// withdrawal was reversed.
+    fn reverse_withdrawal(&mut self, id: u32) -> Option<f64> {
+        if let Some(Transaction::Withdrawal(withdrawal)) = self.processed_transactions.get_mut(&id)
+        {
+            if is_reversible(withdrawal) {
+                self.ledger.post(
+                    withdrawal.tx_id,
+                    self.id,
+                    self.currency,
+                    RawTransactionVariant::Withdrawal.into(),
+                    &[
+                        (LedgerAccount::Settlement, withdrawal.amount),
+                        (LedgerAccount::ClientAvailable, -withdrawal.amount),
+                    ],
+                );
+                withdrawal.failed = true;
+                record_entry(
+                    &mut self.statement,
+                    withdrawal.tx_id,
+                    EntryKind::ReversedWithdrawal,
+                    Direction::Credit,
+                    withdrawal.amount,
+                );
+                return Some(withdrawal.amount);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicate::DuplicateDeposit;
+    use crate::outcome::Outcome;
+    use crate::report::{write_report, OutputFormat};
+    use crate::rules::{read_rules, LargeAmount};
+    use crate::shortfall::{AllowNegative, OldestFirst};
+    use crate::velocity::VelocityRule;
+    use anyhow::{anyhow, Result};
+    use async_stream::stream;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn it_processes_deposits() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 2, Some(2_000.0_f64));
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 3_000.0_f64).abs() < f64::EPSILON);
+        assert!((client.total_balance - 3_000.0_f64).abs() < f64::EPSILON);
+        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_process_deposits_with_different_client_ids() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 2, 2, Some(2_000.0_f64));
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1_000.0_f64).abs() < f64::EPSILON);
+        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_processes_withdrawals() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
+        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_fails_to_process_withdrawals_with_different_client_ids() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 2, 2, Some(1_000.0_f64));
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1_500.0_f64).abs() < f64::EPSILON);
+        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_handles_disputes_of_deposits() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!(client.available_balance.abs() < f64::EPSILON);
+        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
+        assert!((client.held_balance - 1500.0).abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_handles_disputes_of_withdrawals() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
+        assert!((client.held_balance + 1000.0).abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_fails_withdrawals_with_insufficient_balance() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(2_000.0_f64));
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(!client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_fails_withdrawals_against_held_funds_when_checking_available_funds() -> Result<()> {
+        let transactions = vec![
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_000.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 2, Some(500.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None),
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(800.0_f64)),
+        ];
+
+        for (funds_availability, available, reason) in [
+            (FundsAvailability::Total, -300.0_f64, None),
+            (
+                FundsAvailability::Available,
+                500.0_f64,
+                Some(Reason::InsufficientAvailableFunds),
+            ),
+            (
+                FundsAvailability::Overdraft(200.0),
+                500.0_f64,
+                Some(Reason::OverdraftLimitExceeded),
+            ),
+            (FundsAvailability::Overdraft(300.0), -300.0_f64, None),
+        ] {
+            let config = EngineConfig {
+                record_outcomes: true,
+                funds_availability,
+                ..EngineConfig::default()
+            };
+            let mut client = Client::with_config(1, &config);
+            let activity = transactions.clone();
+            client
+                .process_activity(RawTransactionStream::new(stream! {
+                    for t in activity {
+                        yield t;
+                    }
+                }))
+                .await;
+            assert!((client.available_balance - available).abs() < f64::EPSILON);
+            assert!((client.held_balance - 1_000.0).abs() < f64::EPSILON);
+            let outcomes = client.take_outcomes();
+            assert_eq!(outcomes.last().and_then(|o| o.reason), reason);
+            if available < 0.0_f64 {
+                assert_eq!(outcomes.last().map(|o| o.outcome), Some(Outcome::Accepted));
+                assert_eq!(
+                    outcomes.last().map(|o| o.notes.clone()),
+                    Some(vec![Note::AvailableBelowZero])
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_enforces_the_withdrawal_limits_of_the_client_profile() -> Result<()> {
+        let profile = ClientProfile {
+            client_id: 1,
+            max_withdrawal: Some(500.0_f64),
+            withdrawal_cap: Some(1_000.0_f64),
+            ..ClientProfile::default()
+        };
+        let config = EngineConfig {
+            record_outcomes: true,
+            profiles: Arc::new(HashMap::from([(1, profile)])),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction {
+                timestamp: Some(0),
+                ..RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(5_000.0_f64))
+            };
+            for (tx_id, amount, timestamp) in [
+                (2, 600.0_f64, 100),
+                (3, 400.0_f64, 200),
+                (4, 400.0_f64, 300),
+                (5, 300.0_f64, 400),
+                (6, 300.0_f64, 86_400),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(timestamp),
+                    ..RawTransaction::new(RawTransactionVariant::Withdrawal, 1, tx_id, Some(amount))
+                };
+            }
+        });
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 3_900.0).abs() < f64::EPSILON);
+        let reasons: Vec<_> = client
+            .take_outcomes()
+            .iter()
+            .map(|o| (o.tx_id, o.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (1, None),
+                (2, Some(Reason::WithdrawalLimitExceeded)),
+                (3, None),
+                (4, None),
+                (5, Some(Reason::WithdrawalCapExceeded)),
+                (6, None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_flags_the_client_and_holds_transactions_matching_velocity_rules() -> Result<()> {
+        let config = EngineConfig {
+            record_outcomes: true,
+            rules: Arc::from([
+                Box::new("withdrawal:count:2:10:flag".parse::<VelocityRule>()?) as Box<dyn Rule>,
+                Box::new("deposit:total:1000:day:hold".parse::<VelocityRule>()?),
+            ]),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(500.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(600.0_f64), RawTransactionVariant::Deposit),
+                (3, Some(10.0_f64), RawTransactionVariant::Withdrawal),
+                (4, Some(10.0_f64), RawTransactionVariant::Withdrawal),
+                (5, Some(10.0_f64), RawTransactionVariant::Withdrawal),
+                (2, None, RawTransactionVariant::Resolve),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(u64::from(tx_id) * 10),
+                    ..RawTransaction::new(variant, 1, tx_id, amount)
+                };
+            }
+        });
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1_070.0).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert_eq!(client.flagged(), Some(true));
+        let notes: Vec<_> = client
+            .take_outcomes()
+            .into_iter()
+            .map(|o| (o.tx_id, o.notes))
+            .collect();
+        assert_eq!(
+            notes,
+            vec![
+                (1, vec![]),
+                (2, vec![Note::HeldByRule]),
+                (3, vec![]),
+                (4, vec![]),
+                (5, vec![Note::FlaggedByRule]),
+                (2, vec![]),
+            ]
+        );
+        assert_eq!(Client::new(2).flagged(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_keeps_deposits_held_by_a_rule_apart_from_disputes() -> Result<()> {
+        let config = EngineConfig {
+            record_outcomes: true,
+            rules: Arc::from([Box::new(LargeAmount {
+                variant: RawTransactionVariant::Deposit,
+                min_amount: 100.0,
+                verdict: Verdict::Hold,
+            }) as Box<dyn Rule>]),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(50.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(500.0_f64), RawTransactionVariant::Deposit),
+                (3, Some(200.0_f64), RawTransactionVariant::Deposit),
+                (2, None, RawTransactionVariant::Chargeback),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
+            yield RawTransaction {
+                original_tx: Some(3),
+                ..RawTransaction::new(RawTransactionVariant::Refund, 1, 4, Some(50.0_f64))
+            };
+            for (tx_id, variant) in [
+                (2, RawTransactionVariant::Dispute),
+                (3, RawTransactionVariant::Resolve),
+                (2, RawTransactionVariant::Chargeback),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, None);
+            }
+        });
+        client.process_activity(stream).await;
+
+        // A chargeback needs a real dispute, a refund is paid out of the hold, a dispute takes
+        // over the hold, and a resolve releases it.
+        assert!((client.available_balance - 200.0).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!((client.total_balance - 200.0).abs() < f64::EPSILON);
+        assert!(client.locked);
+        let reasons: Vec<_> = client
+            .take_outcomes()
+            .into_iter()
+            .map(|o| (o.tx_id, o.reason, o.notes))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (1, None, vec![]),
+                (2, None, vec![Note::HeldByRule]),
+                (3, None, vec![Note::HeldByRule]),
+                (2, Some(Reason::NotDisputed), vec![]),
+                (4, None, vec![]),
+                (2, None, vec![]),
+                (3, None, vec![]),
+                (2, None, vec![]),
+            ]
+        );
+        assert!(client.violations().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_does_not_apply_transactions_rejected_by_a_rule() -> Result<()> {
+        let config = EngineConfig {
+            record_outcomes: true,
+            rules: Arc::from(read_rules(Path::new("test_data/test_rules.json"))?),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(100.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(100.0_f64), RawTransactionVariant::Deposit),
+                (1, None, RawTransactionVariant::Dispute),
+                (2, None, RawTransactionVariant::Dispute),
+                (3, Some(10.0_f64), RawTransactionVariant::Withdrawal),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
+        });
+        client.process_activity(stream).await;
+
+        assert!((client.total_balance - 200.0).abs() < f64::EPSILON);
+        assert_eq!(client.flagged(), Some(true));
+        let outcomes = client.take_outcomes();
+        assert_eq!(
+            outcomes.last().map(|o| (o.tx_id, o.outcome, o.reason)),
+            Some((3, Outcome::Rejected, Some(Reason::RejectedByRule)))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_holds_likely_duplicate_deposits() -> Result<()> {
+        let config = EngineConfig {
+            record_outcomes: true,
+            rules: Arc::from([Box::new("day:hold".parse::<DuplicateDeposit>()?) as Box<dyn Rule>]),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, timestamp) in [
+                (1, 100.0_f64, 1_000),
+                (2, 250.0_f64, 2_000),
+                (3, 100.0_f64, 3_000),
+                (4, 100.0_f64, 90_000),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(timestamp),
+                    ..RawTransaction::new(RawTransactionVariant::Deposit, 1, tx_id, Some(amount))
+                };
+            }
+        });
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 450.0).abs() < f64::EPSILON);
+        assert!((client.held_balance - 100.0).abs() < f64::EPSILON);
+        let outcomes: Vec<_> = client
+            .take_outcomes()
+            .into_iter()
+            .map(|o| (o.tx_id, o.notes, o.rule))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, vec![], None),
+                (2, vec![], None),
+                (3, vec![Note::HeldByRule], Some("duplicate_deposit")),
+                (4, vec![], None),
+            ]
+        );
+
+        Ok(())
     }
 
-    /// Processes a `Chargeback` transaction
-    fn process_chargeback(
-        &mut self,
-        pending_held_balance: &mut f64,
-        pending_total_balance: &mut f64,
-        chargeback: &Chargeback,
-    ) {
-        if let Some(tx) = self.processed_transactions.get_mut(&chargeback.tx_id) {
-            match tx {
-                Transaction::Deposit(deposit) => {
-                    if !deposit.disputed
-                        || deposit.resolved
-                        || chargeback.client_id != deposit.client_id
-                    {
-                        // Transaction has not been disputed or has already been resolved
-                        eprintln!(
-                            "Transaction has not been disputed or has already been resolved: {}",
-                            chargeback.tx_id
-                        );
-                        return;
-                    }
-                    deposit.resolved = true;
-                    *pending_held_balance -= deposit.amount;
-                    *pending_total_balance -= deposit.amount;
-                    self.locked = true;
-                }
-                Transaction::Withdrawal(withdrawal) => {
-                    if !withdrawal.disputed
-                        || withdrawal.resolved
-                        || chargeback.client_id != withdrawal.client_id
-                    {
-                        // Transaction has not been disputed or has already been resolved
-                        eprintln!(
-                            "Transaction has not been disputed or has already been resolved: {}",
-                            chargeback.tx_id
-                        );
-                        return;
-                    }
-                    withdrawal.resolved = true;
-                    if !withdrawal.failed {
-                        *pending_held_balance += withdrawal.amount;
-                        *pending_total_balance += withdrawal.amount;
-                    }
+    #[tokio::test]
+    async fn it_scores_the_risk_of_the_client() -> Result<()> {
+        let config = EngineConfig {
+            risk_weights: Some("chargeback=30".parse()?),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(100.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(100.0_f64), RawTransactionVariant::Deposit),
+                (1, None, RawTransactionVariant::Dispute),
+                (1, None, RawTransactionVariant::Chargeback),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
+        });
+        client.process_activity(stream).await;
+
+        // Half of the deposits were disputed, and one was charged back.
+        let score = client
+            .risk_score()
+            .ok_or_else(|| anyhow!("No risk score"))?;
+        assert!((score - 55.0).abs() < f64::EPSILON);
+        assert_eq!(Client::new(2).risk_score(), None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_derives_its_balances_from_a_balanced_ledger() -> Result<()> {
+        let mut client = Client::with_config(
+            1,
+            &EngineConfig {
+                record_journal: true,
+                ..EngineConfig::default()
+            },
+        );
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(100.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(80.0_f64), RawTransactionVariant::Withdrawal),
+                (3, Some(50.0_f64), RawTransactionVariant::Deposit),
+                (3, None, RawTransactionVariant::Dispute),
+                (1, None, RawTransactionVariant::Dispute),
+                (1, None, RawTransactionVariant::Chargeback),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
+        });
+        client.process_activity(stream).await;
+
+        // The chargeback reversed the withdrawal to cover its shortfall.
+        let ledger = client.ledger();
+        assert!(ledger.verify(1).is_ok());
+        assert!((ledger.debits() - ledger.credits()).abs() < f64::EPSILON);
+        assert!((client.held_balance - 50.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 50.0).abs() < f64::EPSILON);
+        assert!((ledger.balance(LedgerAccount::Settlement) - 50.0).abs() < f64::EPSILON);
+        let journal = client.take_journal();
+        assert_eq!(journal.len(), 14);
+        assert!(journal.iter().all(|line| line.client == 1));
+
+        Ok(())
+    }
 
-                    self.locked = true;
+    #[tokio::test]
+    async fn it_finds_no_violations_in_a_consistent_account() -> Result<()> {
+        for verify_each_transaction in [true, false] {
+            let mut client = Client::with_config(
+                1,
+                &EngineConfig {
+                    verify_each_transaction,
+                    ..EngineConfig::default()
+                },
+            );
+            let stream = RawTransactionStream::new(stream! {
+                for (tx_id, amount, variant) in [
+                    (1, Some(100.0_f64), RawTransactionVariant::Deposit),
+                    (2, Some(30.0_f64), RawTransactionVariant::Withdrawal),
+                    (3, Some(20.0_f64), RawTransactionVariant::Authorize),
+                    (4, Some(10.0_f64), RawTransactionVariant::Authorize),
+                    (2, None, RawTransactionVariant::Dispute),
+                    (3, Some(15.0_f64), RawTransactionVariant::Capture),
+                    (4, None, RawTransactionVariant::Void),
+                    (1, None, RawTransactionVariant::Dispute),
+                    (2, None, RawTransactionVariant::Resolve),
+                    (1, None, RawTransactionVariant::Chargeback),
+                ] {
+                    yield RawTransaction::new(variant, 1, tx_id, amount);
                 }
-                Transaction::Dispute(_) | Transaction::Resolve(_) | Transaction::Chargeback(_) => {
-                    // The transaction is not a deposit or a withdrawal
-                    eprintln!(
-                        "Transaction is not a deposit or withdrawal: {}",
-                        chargeback.tx_id
-                    );
+            });
+            client.process_activity(stream).await;
+
+            assert!(client.locked);
+            assert!(client.violations().is_empty());
+            assert!((client.recorded_total() - client.total_balance).abs() < f64::EPSILON);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_names_the_transaction_after_which_an_invariant_was_violated() -> Result<()> {
+        // The held balance is checked after every transaction whether or not the holds are
+        // recounted from the dispute window each time.
+        for verify_each_transaction in [true, false] {
+            let mut client = Client::with_config(
+                1,
+                &EngineConfig {
+                    verify_each_transaction,
+                    ..EngineConfig::default()
+                },
+            );
+            // A hold that no dispute or authorization accounts for.
+            client.held_balance = 5.0_f64;
+            let stream = RawTransactionStream::new(stream! {
+                for tx_id in 1..=2 {
+                    yield RawTransaction::new(RawTransactionVariant::Deposit, 1, tx_id, Some(10.0_f64));
                 }
-            }
+            });
+            client.process_activity(stream).await;
+
+            let violations = client
+                .violations()
+                .iter()
+                .map(|v| (v.kind, v.tx))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                violations,
+                vec![
+                    // The stray hold is posted with the opening balances, so it is in the total
+                    // that the ledger derives but not in the opening total recorded.
+                    (ViolationKind::FundsNotConserved, Some(1)),
+                    (ViolationKind::UnbackedHold, Some(1)),
+                ]
+            );
         }
+
+        Ok(())
     }
 
-    /// Reverses a withdrawal transaction, marking it as failed.
-    fn reverse_withdrawal(
-        &mut self,
-        pending_available_balance: &mut f64,
-        pending_total_balance: &mut f64,
-        id: u32,
-    ) {
-        if let Some(Transaction::Withdrawal(withdrawal)) = self.processed_transactions.get_mut(&id)
-        {
-            if !withdrawal.failed && !withdrawal.disputed || withdrawal.resolved {
-                *pending_available_balance += withdrawal.amount;
-                *pending_total_balance += withdrawal.amount;
-                withdrawal.failed = true;
+    #[tokio::test]
+    async fn it_holds_authorized_funds_until_captured_or_voided() -> Result<()> {
+        let mut client = Client::with_config(
+            1,
+            &EngineConfig {
+                record_outcomes: true,
+                ..EngineConfig::default()
+            },
+        );
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant) in [
+                (1, Some(1_000.0_f64), RawTransactionVariant::Deposit),
+                (2, Some(300.0_f64), RawTransactionVariant::Authorize),
+                (3, Some(200.0_f64), RawTransactionVariant::Authorize),
+                (2, Some(250.0_f64), RawTransactionVariant::Capture),
+                (3, None, RawTransactionVariant::Void),
+                (3, None, RawTransactionVariant::Capture),
+                (1, None, RawTransactionVariant::Capture),
+                (4, Some(900.0_f64), RawTransactionVariant::Authorize),
+                (2, None, RawTransactionVariant::Dispute),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
             }
-        }
+        });
+        client.process_activity(stream).await;
+
+        // The capture of 250 is a final withdrawal, disputed like any other; the rest of the holds
+        // were released.
+        assert!((client.total_balance - 750.0).abs() < f64::EPSILON);
+        assert!((client.held_balance + 250.0).abs() < f64::EPSILON);
+        assert!((client.available_balance - 1_000.0).abs() < f64::EPSILON);
+        let reasons: Vec<_> = client
+            .take_outcomes()
+            .iter()
+            .map(|o| (o.tx_id, o.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (1, None),
+                (2, None),
+                (3, None),
+                (2, None),
+                (3, None),
+                (3, Some(Reason::AuthorizationReleased)),
+                (1, Some(Reason::NotAnAuthorization)),
+                (4, Some(Reason::InsufficientFunds)),
+                (2, None),
+            ]
+        );
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use async_stream::stream;
+    #[tokio::test]
+    async fn it_expires_authorizations_past_their_deadline() -> Result<()> {
+        let mut client = Client::with_config(
+            1,
+            &EngineConfig {
+                record_outcomes: true,
+                authorization_expiry: Some(Window::Seconds(3_600)),
+                ..EngineConfig::default()
+            },
+        );
+        let stream = RawTransactionStream::new(stream! {
+            for (tx_id, amount, variant, timestamp) in [
+                (1, Some(1_000.0_f64), RawTransactionVariant::Deposit, 0),
+                (2, Some(300.0_f64), RawTransactionVariant::Authorize, 100),
+                (3, Some(10.0_f64), RawTransactionVariant::Deposit, 3_700),
+                (2, None, RawTransactionVariant::Capture, 3_800),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(timestamp),
+                    ..RawTransaction::new(variant, 1, tx_id, amount)
+                };
+            }
+        });
+        client.process_activity(stream).await;
+
+        assert!((client.available_balance - 1_010.0).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        let outcomes: Vec<_> = client
+            .take_outcomes()
+            .iter()
+            .map(|o| (o.tx_id, o.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, Outcome::Accepted),
+                (2, Outcome::Accepted),
+                (2, Outcome::Expired),
+                (3, Outcome::Accepted),
+                (2, Outcome::Rejected),
+            ]
+        );
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn it_processes_deposits() -> Result<()> {
+    async fn it_handles_disputes_of_deposits_and_withdrawals() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1_000.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: Some(2_000.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 3_000.0_f64).abs() < f64::EPSILON);
-        assert!((client.total_balance - 3_000.0_f64).abs() < f64::EPSILON);
-        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(client.available_balance.abs() < f64::EPSILON);
+        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
+        assert!((client.held_balance - 500.0).abs() < f64::EPSILON);
         assert!(!client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_fails_to_process_deposits_with_different_client_ids() -> Result<()> {
+    async fn it_rejects_disputes_of_deposits_with_different_client_ids() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1_000.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 2,
-                amount: Some(2_000.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 2, 1, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1_000.0_f64).abs() < f64::EPSILON);
-        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
         assert!(!client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_processes_withdrawals() -> Result<()> {
+    async fn it_rejects_disputes_of_withdrawals_with_different_client_ids() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1_500.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: Some(1_000.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 2, 2, None);
         });
 
         let mut client = Client::new(1);
@@ -539,8 +3041,7 @@ mod tests {
         client.process_activity(stream).await;
 
         assert!((client.available_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
         assert!(client.held_balance.abs() < f64::EPSILON);
         assert!(!client.locked);
 
@@ -548,28 +3049,19 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn it_fails_to_process_withdrawals_with_different_client_ids() -> Result<()> {
+    async fn it_handles_resolves_of_deposit_disputes() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1_500.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 2,
-                amount: Some(1_000.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1_500.0_f64).abs() < f64::EPSILON);
-        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
+        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
         assert!(client.held_balance.abs() < f64::EPSILON);
         assert!(!client.locked);
 
@@ -577,84 +3069,53 @@ mod tests {
     }
 
     #[tokio::test]
-    async fn it_handles_disputes_of_deposits() -> Result<()> {
+    async fn it_handles_resolves_of_withdrawal_disputes() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
+        yield RawTransaction::new(RawTransactionVariant::Resolve, 1, 2, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!(client.available_balance.abs() < f64::EPSILON);
-        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
-        assert!((client.held_balance - 1500.0).abs() < f64::EPSILON);
+        assert!((client.available_balance - 500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
         assert!(!client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_disputes_of_withdrawals() -> Result<()> {
+    async fn it_rejects_resolves_of_deposit_disputes_with_different_client_ids() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Resolve, 2, 1, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.held_balance + 1000.0).abs() < f64::EPSILON);
+        assert!(client.available_balance.abs() < f64::EPSILON);
+        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
+        assert!((client.held_balance - 1500.0).abs() < f64::EPSILON);
         assert!(!client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_fails_withdrawals_with_insufficient_balance() -> Result<()> {
+    async fn it_rejects_resolves_of_withdrawal_disputes_with_different_client_ids() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1_500.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: Some(2_000.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
+        yield RawTransaction::new(RawTransactionVariant::Resolve, 2, 2, None);
         });
 
         let mut client = Client::new(1);
@@ -662,70 +3123,41 @@ mod tests {
         client.process_activity(stream).await;
 
         assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.available_balance - client.total_balance).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
+        assert!((client.held_balance + 1000.0).abs() < f64::EPSILON);
         assert!(!client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_disputes_of_deposits_and_withdrawals() -> Result<()> {
+    async fn it_handles_chargebacks_of_deposit_disputes() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
+        println!("{:?}", client);
         assert!(client.available_balance.abs() < f64::EPSILON);
-        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
-        assert!((client.held_balance - 500.0).abs() < f64::EPSILON);
-        assert!(!client.locked);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_rejects_disputes_of_deposits_with_different_client_ids() -> Result<()> {
+    async fn it_handles_chargebacks_of_withdrawal_disputes() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 2,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
+        yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 2, None);
         });
 
         let mut client = Client::new(1);
@@ -733,314 +3165,404 @@ mod tests {
         client.process_activity(stream).await;
 
         assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
+        assert!((client.total_balance - 1500.0).abs() < f64::EPSILON);
         assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(!client.locked);
+        assert!(client.locked);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_rejects_disputes_of_withdrawals_with_different_client_ids() -> Result<()> {
+    async fn it_reverses_withdrawals_after_a_chargeback() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 2,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+        });
+
+        let mut client = Client::new(1);
+
+        client.process_activity(stream).await;
+
+        assert!(client.available_balance.abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_emits_an_event_listing_the_withdrawals_reversed_by_a_chargeback() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(600.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(400.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 4, Some(300.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
         });
 
         let mut client = Client::new(1);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(!client.locked);
+        let events = client.take_chargeback_events();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.client_id, 1);
+        assert_eq!(event.tx_id, 1);
+        assert!((event.shortfall - 1_300.0).abs() < f64::EPSILON);
+        assert_eq!(
+            event.reversed,
+            vec![
+                ReversedWithdrawal::new(4, 300.0),
+                ReversedWithdrawal::new(3, 400.0),
+                ReversedWithdrawal::new(2, 600.0),
+            ]
+        );
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.take_chargeback_events().is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_resolves_of_deposit_disputes() -> Result<()> {
+    async fn it_covers_a_chargeback_shortfall_with_the_configured_policy() -> Result<()> {
+        let transactions = vec![
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(600.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(400.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 4, Some(500.0_f64)),
+            RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None),
+            RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None),
+        ];
+
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(OldestFirst),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        let activity = transactions.clone();
+        client
+            .process_activity(RawTransactionStream::new(stream! {
+                for t in activity {
+                    yield t;
+                }
+            }))
+            .await;
+        let events = client.take_chargeback_events();
+        assert_eq!(events[0].reversed, vec![ReversedWithdrawal::new(2, 600.0)]);
+        assert!(events[0].uncovered.abs() < f64::EPSILON);
+        assert!((client.total_balance - 100.0).abs() < f64::EPSILON);
+
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        client
+            .process_activity(RawTransactionStream::new(stream! {
+                for t in transactions {
+                    yield t;
+                }
+            }))
+            .await;
+        let events = client.take_chargeback_events();
+        assert!(events[0].reversed.is_empty());
+        assert!((events[0].uncovered - 500.0).abs() < f64::EPSILON);
+        assert!((client.total_balance + 500.0).abs() < f64::EPSILON);
+        assert!(client.locked);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_carries_an_uncovered_shortfall_as_debt_repaid_by_later_deposits() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Resolve,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 3, Some(200.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 4, Some(400.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 5, Some(50.0_f64));
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(!client.locked);
+        let debt = client.debt().ok_or_else(|| anyhow!("No debt ledger"))?;
+        assert_eq!(debt.entries.len(), 1);
+        assert_eq!(debt.entries[0].tx_id, 1);
+        assert!((debt.entries[0].amount - 500.0).abs() < f64::EPSILON);
+        assert!(debt.outstanding().abs() < f64::EPSILON);
+        assert!((debt.repaid() - 500.0).abs() < f64::EPSILON);
+        // What a deposit brings beyond the debt, and the deposit after the debt was repaid, are
+        // rejected, since the account is locked.
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.available_balance.abs() < f64::EPSILON);
+        assert!(client.locked);
+        assert!(Client::new(2).debt().is_none());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_resolves_of_withdrawal_disputes() -> Result<()> {
+    async fn it_applies_only_the_debt_owed_of_a_larger_deposit_while_locked() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Resolve,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(100.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(100.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 3, Some(250.0_f64));
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            record_statements: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - client.available_balance).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(!client.locked);
+        let debt = client.debt().ok_or_else(|| anyhow!("No debt ledger"))?;
+        assert!(debt.outstanding().abs() < f64::EPSILON);
+        assert!((debt.repaid() - 100.0).abs() < f64::EPSILON);
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.locked);
+        assert!(client.violations().is_empty());
+        let statement = client
+            .take_statement()
+            .ok_or_else(|| anyhow!("No statement"))?;
+        let deposit = statement
+            .entries
+            .iter()
+            .find(|e| e.tx_id == 3)
+            .ok_or_else(|| anyhow!("No entry for the deposit"))?;
+        assert!((deposit.amount - 100.0).abs() < f64::EPSILON);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_rejects_resolves_of_deposit_disputes_with_different_client_ids() -> Result<()> {
+    async fn it_provisions_an_uncovered_shortfall_until_it_is_repaid() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 2,
-            amount: None,
-            variant: RawTransactionVariant::Resolve,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 3, Some(200.0_f64));
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            record_journal: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!(client.available_balance.abs() < f64::EPSILON);
-        assert!((client.total_balance - client.held_balance).abs() < f64::EPSILON);
-        assert!((client.held_balance - 1500.0).abs() < f64::EPSILON);
-        assert!(!client.locked);
+        let ledger = client.ledger();
+        assert!(ledger.verify(1).is_ok());
+        assert!((ledger.balance(LedgerAccount::ChargebackLosses) - 300.0).abs() < f64::EPSILON);
+        assert!((ledger.balance(LedgerAccount::ShortfallAllowance) + 300.0).abs() < f64::EPSILON);
+        assert!((ledger.balance(LedgerAccount::Settlement) + 300.0).abs() < f64::EPSILON);
+        assert!((client.total_balance + 300.0).abs() < f64::EPSILON);
+        let repayment = client
+            .take_journal()
+            .into_iter()
+            .filter(|line| line.tx == 3)
+            .map(|line| (line.account, line.debit, line.credit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            repayment,
+            vec![
+                (LedgerAccount::Settlement, 200.0_f64, 0.0_f64),
+                (LedgerAccount::ClientAvailable, 0.0_f64, 200.0_f64),
+                (LedgerAccount::ShortfallAllowance, 200.0_f64, 0.0_f64),
+                (LedgerAccount::ChargebackLosses, 0.0_f64, 200.0_f64),
+            ]
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_rejects_resolves_of_withdrawal_disputes_with_different_client_ids() -> Result<()> {
+    async fn it_only_disputes_the_part_of_a_deposit_that_was_not_refunded() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 2,
-            amount: None,
-            variant: RawTransactionVariant::Resolve,
-            };
+            for (variant, tx_id, amount, original_tx) in [
+                (RawTransactionVariant::Deposit, 1, Some(100.0_f64), None),
+                (RawTransactionVariant::Refund, 2, Some(30.0_f64), Some(1)),
+                (RawTransactionVariant::Refund, 3, Some(80.0_f64), Some(1)),
+                (RawTransactionVariant::Refund, 4, Some(10.0_f64), Some(2)),
+                (RawTransactionVariant::Dispute, 2, None, None),
+                (RawTransactionVariant::Dispute, 1, None, None),
+                (RawTransactionVariant::Chargeback, 1, None, None),
+            ] {
+                yield RawTransaction {
+                    original_tx,
+                    ..RawTransaction::new(variant, 1, tx_id, amount)
+                };
+            }
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            record_outcomes: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - 500.0).abs() < f64::EPSILON);
-        assert!((client.held_balance + 1000.0).abs() < f64::EPSILON);
-        assert!(!client.locked);
+        let reasons = client
+            .take_outcomes()
+            .iter()
+            .map(|o| (o.tx_id, o.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            reasons,
+            vec![
+                (1, None),
+                (2, None),
+                (3, Some(Reason::RefundExceedsDeposit)),
+                (4, Some(Reason::NotADeposit)),
+                (2, Some(Reason::NotDisputable)),
+                (1, None),
+                (1, None),
+            ]
+        );
+        // Only the 70 left after the refund was charged back.
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        assert!(client.held_balance.abs() < f64::EPSILON);
+        assert!(client.violations().is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_chargebacks_of_deposit_disputes() -> Result<()> {
+    async fn it_charges_a_dispute_fee_that_the_shortfall_policy_covers() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Chargeback,
-            };
+            for (variant, tx_id, amount) in [
+                (RawTransactionVariant::Deposit, 1, Some(1_000.0_f64)),
+                (RawTransactionVariant::Withdrawal, 2, Some(990.0_f64)),
+                (RawTransactionVariant::Dispute, 1, None),
+                (RawTransactionVariant::Chargeback, 1, None),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            dispute_fee: Some(DisputeFee::Percent(1.5)),
+            record_statements: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        println!("{:?}", client);
-        assert!(client.available_balance.abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(client.total_balance.abs() < f64::EPSILON);
-        assert!(client.locked);
+        assert!((client.total_balance + 1_005.0).abs() < f64::EPSILON);
+        let debt = client.debt().ok_or_else(|| anyhow!("No debt ledger"))?;
+        assert!((debt.outstanding() - 1_005.0).abs() < f64::EPSILON);
+        assert!((client.ledger().balance(LedgerAccount::FeeIncome) + 15.0).abs() < f64::EPSILON);
+        let statement = client
+            .take_statement()
+            .ok_or_else(|| anyhow!("No statement"))?;
+        let last = statement
+            .entries
+            .last()
+            .map(|e| (e.tx_id, e.kind, e.amount));
+        assert_eq!(last, Some((1, EntryKind::DisputeFee, 15.0_f64)));
+        assert!(client.violations().is_empty());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_handles_chargebacks_of_withdrawal_disputes() -> Result<()> {
+    async fn it_keeps_back_a_rolling_reserve_and_releases_it_after_its_window() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Chargeback,
-            };
+            for (variant, tx_id, amount, timestamp) in [
+                (RawTransactionVariant::Deposit, 1, 100.0_f64, 0),
+                (RawTransactionVariant::Deposit, 2, 50.0_f64, 150),
+                (RawTransactionVariant::Withdrawal, 3, 1.0_f64, 200),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(timestamp),
+                    ..RawTransaction::new(variant, 1, tx_id, Some(amount))
+                };
+            }
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            reserve: Some(ReservePolicy::new(10.0, Window::Seconds(100))),
+            list_sub_accounts: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!((client.available_balance - 1500.0).abs() < f64::EPSILON);
-        assert!((client.total_balance - 1500.0).abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(client.locked);
+        assert!((client.available_balance - 144.0).abs() < f64::EPSILON);
+        assert!((client.held_balance - 5.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 149.0).abs() < f64::EPSILON);
+        assert_eq!(client.sub_accounts().get(RESERVE), Some(&5.0_f64));
+        assert!(client.violations().is_empty());
+
+        let mut output = vec![];
+        write_report(&[client], OutputFormat::Csv, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,sub_account,available,held,total,locked
+1,,144.0,5.0,149.0,false
+1,reserve,0.0,5.0,5.0,false
+"
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn it_reverses_withdrawals_after_a_chargeback() -> Result<()> {
+    async fn it_draws_the_reserve_of_a_disputed_deposit_into_its_dispute() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 2,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Withdrawal,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Chargeback,
-            };
+            for (variant, tx_id, amount) in [
+                (RawTransactionVariant::Deposit, 1, Some(100.0_f64)),
+                (RawTransactionVariant::Deposit, 2, Some(50.0_f64)),
+                (RawTransactionVariant::Dispute, 1, None),
+                (RawTransactionVariant::Chargeback, 1, None),
+                (RawTransactionVariant::Dispute, 2, None),
+                (RawTransactionVariant::Resolve, 2, None),
+            ] {
+                yield RawTransaction::new(variant, 1, tx_id, amount);
+            }
         });
 
-        let mut client = Client::new(1);
+        let config = EngineConfig {
+            reserve: Some(ReservePolicy::new(10.0, Window::Day)),
+            list_sub_accounts: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
 
         client.process_activity(stream).await;
 
-        assert!(client.available_balance.abs() < f64::EPSILON);
-        assert!(client.held_balance.abs() < f64::EPSILON);
-        assert!(client.total_balance.abs() < f64::EPSILON);
-        assert!(client.locked);
+        // The reserve kept back from the charged back deposit went with it, while the reserve of
+        // the resolved deposit went back to the reserve.
+        assert!((client.available_balance - 45.0).abs() < f64::EPSILON);
+        assert!((client.held_balance - 5.0).abs() < f64::EPSILON);
+        assert!((client.total_balance - 50.0).abs() < f64::EPSILON);
+        assert_eq!(client.sub_accounts().get(RESERVE), Some(&5.0_f64));
+        assert!(client.violations().is_empty());
 
         Ok(())
     }
@@ -1048,30 +3570,10 @@ mod tests {
     #[tokio::test]
     async fn it_stops_processing_transactions_when_a_client_is_locked() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: Some(1_500.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Dispute,
-            };
-        yield RawTransaction {
-            tx_id: 1,
-            client_id: 1,
-            amount: None,
-            variant: RawTransactionVariant::Chargeback,
-            };
-        yield RawTransaction {
-            tx_id: 3,
-            client_id: 1,
-            amount: Some(1_000.0_f64),
-            variant: RawTransactionVariant::Deposit,
-            };
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+        yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+        yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 3, Some(1_000.0_f64));
         });
 
         let mut client = Client::new(1);
@@ -1090,12 +3592,7 @@ mod tests {
     async fn it_handles_a_large_volume_of_transactions() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
             for i in 0..2000 {
-                yield RawTransaction {
-                    tx_id: i,
-                    client_id: 1,
-                    amount: Some(1.0_f64),
-                    variant: RawTransactionVariant::Deposit,
-                }
+                yield RawTransaction::new(RawTransactionVariant::Deposit, 1, i, Some(1.0_f64))
             }
         });
 
@@ -1114,25 +3611,10 @@ mod tests {
     #[tokio::test]
     async fn it_handles_resolving_deposit_disputes_after_window_expired() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 0,
-                client_id: 1,
-                amount: Some(1.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 0, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
             for i in 0..1000 {
-                yield RawTransaction {
-                    tx_id: i + 1,
-                    client_id: 1,
-                    amount: Some(1.0_f64),
-                    variant: RawTransactionVariant::Deposit,
-                };
+                yield RawTransaction::new(RawTransactionVariant::Deposit, 1, i + 1, Some(1.0_f64));
             }
         });
 
@@ -1151,31 +3633,11 @@ mod tests {
     #[tokio::test]
     async fn it_handles_resolving_withdrawal_disputes_after_window_expired() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 0,
-                client_id: 1,
-                amount: Some(1.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 0, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
             for i in 0..1000 {
-                yield RawTransaction {
-                    tx_id: i + 2,
-                    client_id: 1,
-                    amount: Some(1.0_f64),
-                    variant: RawTransactionVariant::Deposit,
-                };
+                yield RawTransaction::new(RawTransactionVariant::Deposit, 1, i + 2, Some(1.0_f64));
             }
         });
 
@@ -1194,24 +3656,9 @@ mod tests {
     #[tokio::test]
     async fn it_handles_resolving_a_dispute_for_a_failed_withdrawal() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Resolve,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Resolve, 1, 2, None);
         });
 
         let mut client = Client::new(1);
@@ -1229,24 +3676,9 @@ mod tests {
     #[tokio::test]
     async fn it_handles_a_chargeback_for_a_failed_withdrawal() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Chargeback,
-            };
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
         });
 
         let mut client = Client::new(1);
@@ -1261,6 +3693,156 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn it_records_a_statement_of_booked_and_pending_entries() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(5_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+        });
+
+        let config = EngineConfig {
+            record_statements: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+
+        client.process_activity(stream).await;
+
+        let statement = client
+            .take_statement()
+            .ok_or_else(|| anyhow!("No statement"))?;
+        assert_eq!(
+            statement.entries,
+            vec![
+                StatementEntry::new(1, EntryKind::Deposit, Direction::Credit, 1_500.0),
+                StatementEntry::new(2, EntryKind::Withdrawal, Direction::Debit, 1_000.0),
+                StatementEntry::new(1, EntryKind::Dispute, Direction::Debit, 1_500.0),
+                StatementEntry::new(1, EntryKind::Chargeback, Direction::Debit, 1_500.0),
+                StatementEntry::new(2, EntryKind::ReversedWithdrawal, Direction::Credit, 1_000.0),
+            ]
+        );
+        let booked = statement
+            .entries
+            .iter()
+            .filter(|e| e.kind.is_booked())
+            .map(StatementEntry::signed_amount)
+            .sum::<f64>();
+        assert!((statement.opening.total + booked - statement.closing.total).abs() < f64::EPSILON);
+        assert_eq!(statement.closing, client.balances());
+        assert!(client.take_statement().is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_does_not_record_a_statement_by_default() -> Result<()> {
+        let mut client = Client::new(1);
+        client
+            .process_activity(RawTransactionStream::new(stream! {
+                yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.0_f64));
+            }))
+            .await;
+        assert!(client.take_statement().is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_records_the_outcome_of_each_transaction() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1_500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(1_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(5_000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 2, 4, Some(5.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 9, None);
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 5, Some(1.0_f64));
+        });
+
+        let config = EngineConfig {
+            record_outcomes: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+
+        client.process_activity(stream).await;
+
+        let outcomes = client
+            .take_outcomes()
+            .into_iter()
+            .map(|o| (o.tx_id, o.reason, o.total_after))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, None, 1_500.0_f64),
+                (2, None, 500.0_f64),
+                (3, Some(Reason::InsufficientFunds), 500.0_f64),
+                (4, Some(Reason::WrongClient), 500.0_f64),
+                (9, Some(Reason::UnknownTransaction), 500.0_f64),
+                (1, None, 500.0_f64),
+                (1, Some(Reason::AlreadyDisputed), 500.0_f64),
+                (1, None, -1_000.0_f64),
+                (2, Some(Reason::ReversedByChargeback), 0.0_f64),
+                (5, Some(Reason::AccountLocked), 0.0_f64),
+            ]
+        );
+        assert!(client.take_outcomes().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_records_disputes_resolved_when_the_window_expires() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 0, Some(1.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 0, None);
+            for i in 1..=WINDOW_SIZE {
+                yield RawTransaction::new(
+                    RawTransactionVariant::Deposit,
+                    1,
+                    u32::try_from(i).unwrap_or_default(),
+                    Some(1.0_f64),
+                );
+            }
+        });
+
+        let config = EngineConfig {
+            record_outcomes: true,
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+
+        client.process_activity(stream).await;
+
+        let outcomes = client.take_outcomes();
+        assert_eq!(outcomes.len(), WINDOW_SIZE + 3);
+        let expired = outcomes
+            .iter()
+            .find(|o| o.outcome == Outcome::AutoResolved)
+            .ok_or_else(|| anyhow!("No auto-resolved outcome"))?;
+        assert_eq!(expired.tx_id, 0);
+        assert_eq!(expired.variant, RawTransactionVariant::Resolve.into());
+        assert!(expired.held_after.abs() < f64::EPSILON);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_does_not_record_outcomes_by_default() -> Result<()> {
+        let stream = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.0_f64));
+        });
+        let mut client = Client::new(1);
+        client.process_activity(stream).await;
+        assert!(client.take_outcomes().is_empty());
+        Ok(())
+    }
+
     #[tokio::test]
     async fn it_serializes_client_to_csv() -> Result<()> {
         let mut client = Client::new(1);
@@ -1285,42 +3867,12 @@ client,available,held,total,locked
     #[tokio::test]
     async fn it_handles_deposits_withdrawals_disputes_and_resolves() -> Result<()> {
         let stream = RawTransactionStream::new(stream! {
-             yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: Some(1000.0_f64),
-                variant: RawTransactionVariant::Deposit,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: Some(500.0_f64),
-                variant: RawTransactionVariant::Withdrawal,
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute,
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Dispute
-            };
-            yield RawTransaction {
-                tx_id: 1,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Resolve
-            };
-            yield RawTransaction {
-                tx_id: 2,
-                client_id: 1,
-                amount: None,
-                variant: RawTransactionVariant::Resolve
-            };
+             yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None);
+            yield RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Resolve, 1, 2, None);
         });
 
         let mut client = Client::new(1);
@@ -0,0 +1,86 @@
+//! Holds the `EngineConfig`, which controls the optional behaviour of the `Processor` and the
+//! `Client`s it creates.
+
+use crate::dispute_fee::DisputeFee;
+use crate::funds::FundsAvailability;
+use crate::fx::{RateTable, Rounding};
+use crate::interest::InterestTiers;
+use crate::profile::ClientProfile;
+use crate::reserve::ReservePolicy;
+use crate::risk::RiskWeights;
+use crate::rules::Rule;
+use crate::shortfall::{NewestFirst, ShortfallPolicy};
+use crate::window::Window;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Configuration shared by every `Client` in a processing run.
+#[non_exhaustive]
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Whether each `Client` should keep a statement of the movements on its account.  This is off
+    /// by default, since the statement grows with every transaction processed.
+    pub record_statements: bool,
+    /// Whether each `Client` should keep the outcome of every transaction it processes.  This is
+    /// off by default for the same reason.
+    pub record_outcomes: bool,
+    /// Whether each `Client` should keep every line posted to its ledger.  This is off by default
+    /// for the same reason.
+    pub record_journal: bool,
+    /// How the shortfall left by a chargeback is covered.  Defaults to reversing the newest
+    /// withdrawals first.
+    pub shortfall_policy: Arc<dyn ShortfallPolicy>,
+    /// Which funds a withdrawal may draw on.  Defaults to the total balance.
+    pub funds_availability: FundsAvailability,
+    /// The limits of each client that has a profile, keyed by client id.
+    pub profiles: Arc<HashMap<u16, ClientProfile>>,
+    /// The rules evaluated before every client's deposits and withdrawals.
+    pub rules: Arc<[Box<dyn Rule>]>,
+    /// The weights of each client's risk score, if the score should be reported.
+    pub risk_weights: Option<RiskWeights>,
+    /// How long an authorization may stay open before it expires.  Authorizations that leave the
+    /// dispute window expire regardless.
+    pub authorization_expiry: Option<Window>,
+    /// The rates that convert transactions move funds between currencies at.
+    pub rates: Arc<RateTable>,
+    /// How the amount credited by a conversion is rounded.  Defaults to rounding down.
+    pub fx_rounding: Rounding,
+    /// Whether each `Client` should recount the holds backed by open disputes and authorizations
+    /// from every open transaction after every transaction, rather than only once its activity is
+    /// processed.  Otherwise the held balance is checked against a running sum of the holds.  This
+    /// is off by default, since the recount looks at every open transaction.
+    pub verify_each_transaction: bool,
+    /// The tiers of the interest credited by an accrue.  Defaults to no tiers, paying no interest.
+    pub interest: Arc<InterestTiers>,
+    /// The fee charged to a client for every chargeback, if any.
+    pub dispute_fee: Option<DisputeFee>,
+    /// The rolling reserve kept back from every deposit, if any.
+    pub reserve: Option<ReservePolicy>,
+    /// Whether the report lists the balance of each client's sub-accounts, as well as rolling
+    /// them up into the client's own balances.
+    pub list_sub_accounts: bool,
+}
+
+impl Default for EngineConfig {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            record_statements: false,
+            record_outcomes: false,
+            record_journal: false,
+            shortfall_policy: Arc::new(NewestFirst),
+            funds_availability: FundsAvailability::default(),
+            profiles: Arc::default(),
+            rules: Arc::default(),
+            risk_weights: None,
+            authorization_expiry: None,
+            rates: Arc::default(),
+            fx_rounding: Rounding::default(),
+            verify_each_transaction: false,
+            interest: Arc::default(),
+            dispute_fee: None,
+            reserve: None,
+            list_sub_accounts: false,
+        }
+    }
+}
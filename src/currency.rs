@@ -0,0 +1,169 @@
+//! This module holds the `Currency` of a client's account.
+//!
+//! Transactions may name an ISO 4217 currency, and each client keeps separate balances in every
+//! currency it transacts in, kept to the number of minor units (decimal places) of that currency.
+
+use crate::fx::Rounding;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The currencies whose minor unit is not a hundredth, with the number of decimal places they use.
+const MINOR_UNITS: [(&[u8; 3], u8); 20] = [
+    (b"BHD", 3),
+    (b"BIF", 0),
+    (b"CLP", 0),
+    (b"DJF", 0),
+    (b"GNF", 0),
+    (b"IQD", 3),
+    (b"ISK", 0),
+    (b"JOD", 3),
+    (b"JPY", 0),
+    (b"KMF", 0),
+    (b"KRW", 0),
+    (b"KWD", 3),
+    (b"LYD", 3),
+    (b"OMR", 3),
+    (b"PYG", 0),
+    (b"RWF", 0),
+    (b"TND", 3),
+    (b"UGX", 0),
+    (b"VND", 0),
+    (b"XAF", 0),
+];
+
+/// An error type for the currency module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CurrencyError {
+    /// Triggered if a currency code is not three letters
+    #[error("Invalid currency code: {0}")]
+    InvalidCode(String),
+}
+
+/// An ISO 4217 currency, identified by its three letter code.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency {
+    /// The upper case code of the currency
+    code: [u8; 3],
+}
+
+impl Currency {
+    /// The number of decimal places of the currency's minor unit.  Currencies not known to use
+    /// another minor unit use hundredths.
+    #[inline]
+    #[must_use]
+    pub fn minor_units(self) -> u8 {
+        MINOR_UNITS
+            .iter()
+            .find(|&&(code, _)| *code == self.code)
+            .map_or(2, |&(_, units)| units)
+    }
+
+    /// Truncates an amount to the minor unit of the currency.
+    #[inline]
+    #[must_use]
+    pub fn truncate(self, amount: f64) -> f64 {
+        Rounding::Down.round(amount, self.minor_units())
+    }
+
+    /// Formats an amount with exactly as many decimal places as the currency's minor unit.
+    #[inline]
+    #[must_use]
+    pub fn format(self, amount: f64) -> String {
+        format!(
+            "{:.*}",
+            usize::from(self.minor_units()),
+            self.truncate(amount)
+        )
+    }
+}
+
+impl Display for Currency {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.code
+            .iter()
+            .try_for_each(|&c| write!(f, "{}", char::from(c)))
+    }
+}
+
+impl Serialize for Currency {
+    /// Serializes the currency as its code.
+    #[inline]
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    /// Parses a three letter currency code, in any case.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let upper = s.trim().to_ascii_uppercase();
+        match *upper.as_bytes() {
+            [a, b, c] if upper.bytes().all(|byte| byte.is_ascii_uppercase()) => {
+                Ok(Self { code: [a, b, c] })
+            }
+            _ => Err(CurrencyError::InvalidCode(s.to_owned())),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    /// Deserializes a currency from its code.
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Deserializer for the optional currency of a transaction, which may be left empty.
+/// # Errors
+/// Returns an error if the currency is given but is not a valid currency code.
+#[inline]
+pub fn deserialize_currency<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Currency>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(code) if code.trim().is_empty() => Ok(None),
+        Some(code) => code.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_parses_currency_codes() -> Result<()> {
+        assert_eq!("eur".parse::<Currency>()?.to_string(), "EUR");
+        assert_eq!(
+            "EURO".parse::<Currency>(),
+            Err(CurrencyError::InvalidCode("EURO".to_owned()))
+        );
+        assert!("E1R".parse::<Currency>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_keeps_amounts_to_the_minor_unit_of_the_currency() -> Result<()> {
+        let usd: Currency = "USD".parse()?;
+        let jpy: Currency = "JPY".parse()?;
+        let kwd: Currency = "KWD".parse()?;
+        assert_eq!(usd.format(12.3456), "12.34");
+        assert_eq!(jpy.format(1200.9), "1200");
+        assert_eq!(kwd.format(1.5), "1.500");
+        assert!((usd.truncate(0.129) - 0.12).abs() < f64::EPSILON);
+        assert!((usd.truncate(0.29) - 0.29).abs() < f64::EPSILON);
+        Ok(())
+    }
+}
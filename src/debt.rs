@@ -0,0 +1,103 @@
+//! This module holds the `DebtLedger`, which tracks what a client owes after a chargeback left its
+//! account with a negative balance, and how much of it later deposits have repaid.
+
+/// A debt left by a single chargeback.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DebtEntry {
+    /// The ID of the transaction that was charged back
+    pub tx_id: u32,
+    /// The amount owed when the chargeback was processed
+    pub amount: f64,
+    /// The amount repaid so far
+    pub repaid: f64,
+}
+
+impl DebtEntry {
+    /// Creates a new `DebtEntry` with nothing repaid.
+    #[inline]
+    #[must_use]
+    pub const fn new(tx_id: u32, amount: f64) -> Self {
+        Self {
+            tx_id,
+            amount,
+            repaid: 0.0,
+        }
+    }
+
+    /// The amount still owed.
+    #[inline]
+    #[must_use]
+    pub fn outstanding(&self) -> f64 {
+        (self.amount - self.repaid).max(0.0)
+    }
+}
+
+/// The debts of a client, oldest first.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DebtLedger {
+    /// The debts, in the order they were incurred
+    pub entries: Vec<DebtEntry>,
+}
+
+impl DebtLedger {
+    /// Records a new debt.
+    #[inline]
+    pub fn incur(&mut self, tx_id: u32, amount: f64) {
+        self.entries.push(DebtEntry::new(tx_id, amount));
+    }
+
+    /// Applies `amount` to the outstanding debts, oldest first, and returns how much of it was
+    /// used.
+    #[inline]
+    pub fn repay(&mut self, amount: f64) -> f64 {
+        let mut remaining = amount;
+        for entry in &mut self.entries {
+            if remaining <= 0_f64 {
+                break;
+            }
+            let repayment = entry.outstanding().min(remaining);
+            entry.repaid += repayment;
+            remaining -= repayment;
+        }
+        amount - remaining
+    }
+
+    /// The total amount still owed.
+    #[inline]
+    #[must_use]
+    pub fn outstanding(&self) -> f64 {
+        self.entries
+            .iter()
+            .fold(0_f64, |sum, e| sum + e.outstanding())
+    }
+
+    /// The total amount repaid.
+    #[inline]
+    #[must_use]
+    pub fn repaid(&self) -> f64 {
+        self.entries.iter().fold(0_f64, |sum, e| sum + e.repaid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_repays_the_oldest_debt_first() {
+        let mut ledger = DebtLedger::default();
+        ledger.incur(1, 100.0);
+        ledger.incur(2, 50.0);
+
+        assert!((ledger.repay(120.0) - 120.0).abs() < f64::EPSILON);
+        assert!(ledger.entries[0].outstanding().abs() < f64::EPSILON);
+        assert!((ledger.entries[1].outstanding() - 30.0).abs() < f64::EPSILON);
+
+        assert!((ledger.repay(100.0) - 30.0).abs() < f64::EPSILON);
+        assert!(ledger.outstanding().abs() < f64::EPSILON);
+        assert!((ledger.repaid() - 150.0).abs() < f64::EPSILON);
+        assert!(ledger.repay(10.0).abs() < f64::EPSILON);
+    }
+}
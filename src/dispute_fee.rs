@@ -0,0 +1,80 @@
+//! This module holds the `DisputeFee` charged to a client when one of its transactions is charged
+//! back.
+//!
+//! The card network charges the platform for every chargeback, and the fee passes that cost on.
+//! It is posted as its own `dispute_fee` transaction right after the chargeback, earning fee
+//! income, and a balance it takes below zero is covered by the shortfall policy like the
+//! chargeback's own shortfall.
+
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the dispute fee module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DisputeFeeError {
+    /// Triggered if a dispute fee cannot be parsed
+    #[error("Unknown dispute fee: {0}")]
+    UnknownFee(String),
+}
+
+/// The fee charged to a client for a chargeback.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisputeFee {
+    /// A fixed amount, in the currency of the account.
+    Fixed(f64),
+    /// A percentage of the amount charged back.
+    Percent(f64),
+}
+
+impl DisputeFee {
+    /// The fee for a chargeback of `charged_back`, before rounding.
+    #[inline]
+    #[must_use]
+    pub fn amount(self, charged_back: f64) -> f64 {
+        match self {
+            Self::Fixed(fee) => fee,
+            Self::Percent(percent) => charged_back.abs() * percent / 100.0_f64,
+        }
+    }
+}
+
+impl FromStr for DisputeFee {
+    type Err = DisputeFeeError;
+
+    /// Parses `fixed:<amount>` or `percent:<percent>`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || DisputeFeeError::UnknownFee(s.to_owned());
+        let lower = s.to_ascii_lowercase();
+        let (kind, value) = lower.split_once(':').ok_or_else(unknown)?;
+        let value = match value.trim().parse::<f64>() {
+            Ok(v) if v.is_finite() && v >= 0_f64 => v,
+            _ => return Err(unknown()),
+        };
+        match kind {
+            "fixed" => Ok(Self::Fixed(value)),
+            "percent" => Ok(Self::Percent(value)),
+            _ => Err(unknown()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_fixed_and_percentage_fees() {
+        assert_eq!("fixed:15".parse(), Ok(DisputeFee::Fixed(15.0)));
+        assert_eq!("Percent:1.5".parse(), Ok(DisputeFee::Percent(1.5)));
+        assert!((DisputeFee::Percent(1.5).amount(200.0) - 3.0).abs() < f64::EPSILON);
+        assert!((DisputeFee::Fixed(15.0).amount(200.0) - 15.0).abs() < f64::EPSILON);
+        assert_eq!(
+            "percent:-1".parse::<DisputeFee>(),
+            Err(DisputeFeeError::UnknownFee("percent:-1".to_owned()))
+        );
+        assert!("15".parse::<DisputeFee>().is_err());
+    }
+}
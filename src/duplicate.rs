@@ -0,0 +1,203 @@
+//! This module detects likely duplicate payments, as partners produce when they resend a
+//! settlement file.
+//!
+//! A deposit is a likely duplicate when an earlier deposit of the same amount, under a different
+//! transaction id, is inside a window.
+//! The `Client` keeps an `AmountIndex` of its deposits alongside its processed transactions, so
+//! that the `DuplicateDeposit` rule can find earlier deposits of an amount without scanning them
+//! all.
+
+use crate::rules::{ClientView, PendingTransaction, Rule, Verdict};
+use crate::transaction::RawTransactionVariant;
+use crate::window::{Activity, Window};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the duplicate module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DuplicateError {
+    /// Triggered if a duplicate deposit rule cannot be parsed
+    #[error("Invalid duplicate deposit rule: {0}")]
+    InvalidRule(String),
+}
+
+/// The deposits still in a client's dispute window, indexed by amount.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct AmountIndex {
+    /// The deposits of each amount, keyed by the bits of the amount, oldest first
+    entries: HashMap<u64, Vec<Activity>>,
+}
+
+impl AmountIndex {
+    /// Adds a deposit to the index.
+    #[inline]
+    pub fn insert(&mut self, activity: Activity) {
+        self.entries
+            .entry(activity.amount.to_bits())
+            .or_default()
+            .push(activity);
+    }
+
+    /// Removes a deposit from the index.
+    #[inline]
+    pub fn remove(&mut self, tx_id: u32, amount: f64) {
+        let key = amount.to_bits();
+        if let Some(deposits) = self.entries.get_mut(&key) {
+            deposits.retain(|d| d.tx_id != tx_id);
+            if deposits.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Iterates over the deposits of exactly `amount`, oldest first.
+    #[inline]
+    pub fn with_amount(&self, amount: f64) -> impl Iterator<Item = &Activity> {
+        self.entries.get(&amount.to_bits()).into_iter().flatten()
+    }
+
+    /// Removes every deposit from the index.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// Matches deposits of the same amount as an earlier deposit with a different transaction id
+/// inside a window.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct DuplicateDeposit {
+    /// The window an earlier deposit must be inside
+    pub window: Window,
+    /// The verdict for a likely duplicate
+    pub verdict: Verdict,
+}
+
+impl DuplicateDeposit {
+    /// Creates a new `DuplicateDeposit` rule.
+    #[inline]
+    #[must_use]
+    pub const fn new(window: Window, verdict: Verdict) -> Self {
+        Self { window, verdict }
+    }
+}
+
+impl Rule for DuplicateDeposit {
+    #[inline]
+    fn name(&self) -> &'static str {
+        "duplicate_deposit"
+    }
+
+    #[inline]
+    fn evaluate(&self, client: &ClientView<'_>, transaction: &PendingTransaction) -> Verdict {
+        if transaction.variant != RawTransactionVariant::Deposit {
+            return Verdict::Allow;
+        }
+        let duplicate = client
+            .deposits_by_amount
+            .with_amount(transaction.amount)
+            .any(|d| {
+                d.tx_id != transaction.tx_id
+                    && self
+                        .window
+                        .contains(client.sequence, client.clock, d.seq, d.at)
+            });
+        if duplicate {
+            self.verdict
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+impl FromStr for DuplicateDeposit {
+    type Err = DuplicateError;
+
+    /// Parses `<window>:<flag|hold>`, or just a window to flag duplicates.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DuplicateError::InvalidRule(s.to_owned());
+        let lower = s.trim().to_ascii_lowercase();
+        let (window, verdict) = match lower.rsplit_once(':') {
+            Some((window, "flag")) => (window, Verdict::Flag),
+            Some((window, "hold")) => (window, Verdict::Hold),
+            Some(_) => return Err(invalid()),
+            None => (lower.as_str(), Verdict::Flag),
+        };
+        Ok(Self::new(window.parse().ok().ok_or_else(invalid)?, verdict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::BalanceSnapshot;
+    use crate::window::ActivityLog;
+
+    #[test]
+    fn it_matches_deposits_of_the_same_amount_inside_the_window() {
+        let mut index = AmountIndex::default();
+        for (seq, tx_id, amount) in [(1, 1, 100.0_f64), (2, 2, 250.0_f64), (3, 3, 100.0_f64)] {
+            index.insert(Activity {
+                seq,
+                at: None,
+                tx_id,
+                variant: RawTransactionVariant::Deposit,
+                amount,
+            });
+        }
+        index.remove(3, 100.0);
+        let processed_transactions = HashMap::new();
+        let activity = ActivityLog::new(10);
+        let client = ClientView {
+            client_id: 1,
+            balances: BalanceSnapshot::default(),
+            processed_transactions: &processed_transactions,
+            activity: &activity,
+            deposits_by_amount: &index,
+            sequence: 4,
+            clock: None,
+        };
+        let rule = DuplicateDeposit::new(Window::Transactions(3), Verdict::Hold);
+        let deposit =
+            |tx_id, amount| PendingTransaction::new(tx_id, RawTransactionVariant::Deposit, amount);
+        assert_eq!(
+            rule.evaluate(&client, &deposit(4, 250.0_f64)),
+            Verdict::Hold
+        );
+        assert_eq!(
+            rule.evaluate(&client, &deposit(2, 250.0_f64)),
+            Verdict::Allow
+        );
+        // The first deposit of 100 is outside the window, and the second has been removed.
+        assert_eq!(
+            rule.evaluate(&client, &deposit(4, 100.0_f64)),
+            Verdict::Allow
+        );
+        assert_eq!(
+            rule.evaluate(&client, &deposit(4, 250.5_f64)),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn it_parses_duplicate_deposit_rules() {
+        assert_eq!(
+            "day:hold".parse(),
+            Ok(DuplicateDeposit::new(Window::Day, Verdict::Hold))
+        );
+        assert_eq!(
+            "3600s".parse(),
+            Ok(DuplicateDeposit::new(Window::Seconds(3_600), Verdict::Flag))
+        );
+        assert_eq!(
+            "day:reject".parse::<DuplicateDeposit>(),
+            Err(DuplicateError::InvalidRule("day:reject".to_owned()))
+        );
+    }
+}
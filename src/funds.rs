@@ -0,0 +1,113 @@
+//! This module holds the `FundsAvailability` policy, which decides which funds a withdrawal may
+//! draw on.
+
+use crate::outcome::Reason;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the funds module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FundsError {
+    /// Triggered if a funds availability policy cannot be parsed
+    #[error("Unknown funds availability policy: {0}")]
+    UnknownPolicy(String),
+}
+
+/// Which funds a withdrawal may draw on.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FundsAvailability {
+    /// The withdrawal must be covered by the total balance, so held funds may be drawn on and the
+    /// available balance may go negative.
+    #[default]
+    Total,
+    /// The withdrawal must be covered by the available balance.
+    Available,
+    /// The withdrawal must be covered by the available balance plus an overdraft limit.
+    Overdraft(f64),
+}
+
+impl FundsAvailability {
+    /// Checks whether a withdrawal of `amount` may be made from the given balances, returning the
+    /// reason it may not otherwise.
+    /// # Errors
+    /// Returns the reason the withdrawal is refused.
+    #[inline]
+    pub fn check(self, amount: f64, available: f64, total: f64) -> Result<(), Reason> {
+        match self {
+            Self::Total | Self::Available if total < amount => Err(Reason::InsufficientFunds),
+            Self::Available if available < amount => Err(Reason::InsufficientAvailableFunds),
+            Self::Overdraft(limit) if available + limit < amount => {
+                Err(Reason::OverdraftLimitExceeded)
+            }
+            Self::Total | Self::Available | Self::Overdraft(_) => Ok(()),
+        }
+    }
+}
+
+impl FromStr for FundsAvailability {
+    type Err = FundsError;
+
+    /// Parses `total`, `available` or `overdraft:<limit>`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || FundsError::UnknownPolicy(s.to_owned());
+        let lower = s.to_ascii_lowercase();
+        match lower.split_once(':') {
+            None if lower == "total" => Ok(Self::Total),
+            None if lower == "available" => Ok(Self::Available),
+            Some(("overdraft", limit)) => match limit.trim().parse::<f64>() {
+                Ok(l) if l.is_finite() && l >= 0_f64 => Ok(Self::Overdraft(l)),
+                _ => Err(unknown()),
+            },
+            _ => Err(unknown()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_checks_withdrawals_against_the_selected_funds() {
+        // 100 available, 50 held.
+        assert_eq!(FundsAvailability::Total.check(120.0, 100.0, 150.0), Ok(()));
+        assert_eq!(
+            FundsAvailability::Total.check(200.0, 100.0, 150.0),
+            Err(Reason::InsufficientFunds)
+        );
+        assert_eq!(
+            FundsAvailability::Available.check(120.0, 100.0, 150.0),
+            Err(Reason::InsufficientAvailableFunds)
+        );
+        assert_eq!(
+            FundsAvailability::Available.check(100.0, 100.0, 150.0),
+            Ok(())
+        );
+        assert_eq!(
+            FundsAvailability::Overdraft(50.0).check(200.0, 100.0, 150.0),
+            Err(Reason::OverdraftLimitExceeded)
+        );
+        assert_eq!(
+            FundsAvailability::Overdraft(50.0).check(150.0, 100.0, 150.0),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn it_parses_funds_availability_policies() {
+        assert_eq!("total".parse(), Ok(FundsAvailability::Total));
+        assert_eq!("Available".parse(), Ok(FundsAvailability::Available));
+        assert_eq!(
+            "overdraft:250.5".parse(),
+            Ok(FundsAvailability::Overdraft(250.5))
+        );
+        assert_eq!(
+            "overdraft:-1".parse::<FundsAvailability>(),
+            Err(FundsError::UnknownPolicy("overdraft:-1".to_owned()))
+        );
+        assert!("held".parse::<FundsAvailability>().is_err());
+    }
+}
@@ -0,0 +1,376 @@
+//! This module holds the `RateTable` that `convert` transactions move funds between currencies at,
+//! and the `Conversion` record kept of every conversion for audit.
+//!
+//! Rates are read from a local CSV file with the columns `from`, `to`, `rate`, `spread` and
+//! `effective_at`.  A conversion uses the latest rate for its pair that is effective at the time of
+//! the transaction.  The spread is the share of the converted amount kept as a fee, and the amount
+//! left after the fee is rounded to the minor unit of the currency it is credited in by the
+//! `Rounding` policy, leaving a remainder that is recorded along with the rate.
+
+use crate::currency::Currency;
+use crate::report::format_amount;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the fx module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum FxError {
+    /// Triggered if the rate table cannot be read
+    #[error("Failed to read rate table: {0}")]
+    Io(#[from] io::Error),
+    /// Triggered if a rate or conversion cannot be read or written as CSV
+    #[error("Invalid rate table or conversion report: {0}")]
+    Csv(#[from] csv::Error),
+    /// Triggered if a rate is not a positive number, or its spread is not a fraction
+    #[error("Invalid rate from {0} to {1}")]
+    InvalidRate(Currency, Currency),
+    /// Triggered if a rounding policy name is not recognized
+    #[error("Unknown rounding policy: {0}")]
+    UnknownRounding(String),
+}
+
+/// How the amount a conversion credits is rounded to the minor unit of its currency.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    /// Round towards zero, as every other amount is truncated.
+    #[default]
+    Down,
+    /// Round away from zero.
+    Up,
+    /// Round to the nearest minor unit, with halves rounded away from zero.
+    HalfUp,
+    /// Round to the nearest minor unit, with halves rounded to the even minor unit.
+    HalfEven,
+}
+
+impl Rounding {
+    /// Rounds an amount to the given number of decimal places.
+    #[inline]
+    #[must_use]
+    pub fn round(self, amount: f64, places: u8) -> f64 {
+        let ten = 10.0_f64.powi(i32::from(places));
+        // A whole number of minor units can scale to a hair below itself, e.g. 0.29 to
+        // 28.999999999999996, so the scaled amount is snapped to a millionth of a minor unit first.
+        let scaled = (amount * ten * 1e6_f64).round() / 1e6_f64;
+        if !scaled.is_finite() {
+            return amount;
+        }
+        let rounded = match self {
+            Self::Down => scaled.trunc(),
+            Self::Up => scaled.signum() * scaled.abs().ceil(),
+            Self::HalfUp => scaled.round(),
+            Self::HalfEven => scaled.round_ties_even(),
+        };
+        rounded / ten
+    }
+}
+
+impl FromStr for Rounding {
+    type Err = FxError;
+
+    /// Parses `down`, `up`, `half-up` or `half-even`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().replace('_', "-").as_str() {
+            "down" => Ok(Self::Down),
+            "up" => Ok(Self::Up),
+            "half-up" => Ok(Self::HalfUp),
+            "half-even" => Ok(Self::HalfEven),
+            _ => Err(FxError::UnknownRounding(s.to_owned())),
+        }
+    }
+}
+
+/// The rate of one currency in another, from the time it is effective.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct Rate {
+    /// The currency converted from
+    pub from: Currency,
+    /// The currency converted into
+    pub to: Currency,
+    /// The amount of `to` that one unit of `from` converts into
+    pub rate: f64,
+    /// The share of the converted amount kept as a fee
+    #[serde(default)]
+    pub spread: f64,
+    /// When the rate becomes effective, in seconds since the Unix epoch
+    #[serde(default)]
+    pub effective_at: u64,
+}
+
+impl Rate {
+    /// Creates a new `Rate`.
+    #[inline]
+    #[must_use]
+    pub const fn new(
+        from: Currency,
+        to: Currency,
+        rate: f64,
+        spread: f64,
+        effective_at: u64,
+    ) -> Self {
+        Self {
+            from,
+            to,
+            rate,
+            spread,
+            effective_at,
+        }
+    }
+}
+
+/// The rates between currencies, each pair ordered by the time its rates become effective.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateTable {
+    /// The rates of each pair of currencies, earliest first
+    rates: HashMap<(Currency, Currency), Vec<Rate>>,
+}
+
+impl RateTable {
+    /// Adds a rate to the table.
+    /// # Errors
+    /// Returns an error if the rate is not a positive number, or its spread is not a fraction.
+    #[inline]
+    pub fn insert(&mut self, rate: Rate) -> Result<(), FxError> {
+        if rate.rate.is_nan() || rate.rate <= 0.0_f64 || !(0.0_f64..1.0_f64).contains(&rate.spread)
+        {
+            return Err(FxError::InvalidRate(rate.from, rate.to));
+        }
+        let rates = self.rates.entry((rate.from, rate.to)).or_default();
+        let index = rates.partition_point(|r| r.effective_at <= rate.effective_at);
+        rates.insert(index, rate);
+        Ok(())
+    }
+
+    /// The latest rate from one currency to another that is effective at the given time, or the
+    /// latest rate of all if the time is not known.
+    #[inline]
+    #[must_use]
+    pub fn rate(&self, from: Currency, to: Currency, at: Option<u64>) -> Option<&Rate> {
+        let rates = self.rates.get(&(from, to))?;
+        at.map_or_else(
+            || rates.last(),
+            |time| rates.iter().rev().find(|r| r.effective_at <= time),
+        )
+    }
+}
+
+/// Reads a rate table from a CSV file.
+/// # Errors
+/// Returns an error if the file cannot be read, or holds an invalid rate.
+#[inline]
+pub fn read_rates(path: &Path) -> Result<RateTable, FxError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut table = RateTable::default();
+    for rate in reader.deserialize() {
+        table.insert(rate?)?;
+    }
+    Ok(table)
+}
+
+/// The record of a conversion, with everything needed to audit the amount it credited.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conversion {
+    /// The ID of the client
+    pub client: u16,
+    /// The ID of the convert transaction
+    pub tx: u32,
+    /// The currency converted from
+    pub from: Currency,
+    /// The currency converted into
+    pub to: Currency,
+    /// The amount converted, in `from`
+    pub amount: f64,
+    /// The rate the amount was converted at
+    pub rate: f64,
+    /// When the rate became effective
+    pub effective_at: u64,
+    /// The amount converted into before the fee, in `to`
+    pub gross: f64,
+    /// The share of `gross` kept as the spread fee
+    pub spread: f64,
+    /// The spread fee, in `to`
+    pub fee: f64,
+    /// The amount credited in `to`, rounded to its minor unit
+    pub credited: f64,
+    /// What was left over by rounding the amount after the fee to the minor unit of `to`
+    pub remainder: f64,
+}
+
+impl Conversion {
+    /// Converts an amount at the given rate, rounding the amount credited with the given policy.
+    #[inline]
+    #[must_use]
+    pub fn new(client: u16, tx: u32, amount: f64, rate: &Rate, rounding: Rounding) -> Self {
+        let gross = amount * rate.rate;
+        let fee = gross * rate.spread;
+        let net = gross - fee;
+        let credited = rounding.round(net, rate.to.minor_units());
+        Self {
+            client,
+            tx,
+            from: rate.from,
+            to: rate.to,
+            amount,
+            rate: rate.rate,
+            effective_at: rate.effective_at,
+            gross,
+            spread: rate.spread,
+            fee,
+            credited,
+            remainder: net - credited,
+        }
+    }
+}
+
+/// A row of the conversions report.  The amount converted and the amount credited are written
+/// with the decimal places of the minor unit of their currency, and the amounts before rounding
+/// as the client report writes balances.
+#[derive(Debug, Serialize)]
+struct ConversionRow {
+    /// The ID of the client
+    client: u16,
+    /// The ID of the convert transaction
+    tx: u32,
+    /// The currency converted from
+    from: Currency,
+    /// The currency converted into
+    to: Currency,
+    /// The amount converted, in `from`
+    amount: String,
+    /// The rate the amount was converted at
+    rate: f64,
+    /// When the rate became effective
+    effective_at: u64,
+    /// The amount converted into before the fee, in `to`
+    gross: String,
+    /// The share of `gross` kept as the spread fee
+    spread: f64,
+    /// The spread fee, in `to`
+    fee: String,
+    /// The amount credited, in `to`
+    credited: String,
+    /// What was left over by rounding the amount after the fee to the minor unit of `to`
+    remainder: String,
+}
+
+impl From<&Conversion> for ConversionRow {
+    #[inline]
+    fn from(conversion: &Conversion) -> Self {
+        Self {
+            client: conversion.client,
+            tx: conversion.tx,
+            from: conversion.from,
+            to: conversion.to,
+            amount: conversion.from.format(conversion.amount),
+            rate: conversion.rate,
+            effective_at: conversion.effective_at,
+            gross: format_amount(conversion.gross),
+            spread: conversion.spread,
+            fee: format_amount(conversion.fee),
+            credited: conversion.to.format(conversion.credited),
+            remainder: format_amount(conversion.remainder),
+        }
+    }
+}
+
+/// Writes the conversions as CSV to `writer`.
+/// # Errors
+/// Returns an error if the conversions cannot be serialized or written.
+#[inline]
+pub fn write_conversions<W: Write>(conversions: &[Conversion], writer: W) -> Result<(), FxError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for conversion in conversions {
+        csv_writer.serialize(ConversionRow::from(conversion))?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn rate(rate: f64, effective_at: u64) -> Result<Rate> {
+        Ok(Rate {
+            from: "EUR".parse()?,
+            to: "USD".parse()?,
+            rate,
+            spread: 0.01,
+            effective_at,
+        })
+    }
+
+    #[test]
+    fn it_uses_the_latest_rate_effective_at_the_time_of_the_conversion() -> Result<()> {
+        let mut table = RateTable::default();
+        table.insert(rate(1.2, 100)?)?;
+        table.insert(rate(1.1, 0)?)?;
+        let (eur, usd) = ("EUR".parse()?, "USD".parse()?);
+        assert_eq!(
+            table.rate(eur, usd, Some(50)).map(|r| r.rate),
+            Some(1.1_f64)
+        );
+        assert_eq!(
+            table.rate(eur, usd, Some(100)).map(|r| r.rate),
+            Some(1.2_f64)
+        );
+        assert_eq!(table.rate(eur, usd, None).map(|r| r.rate), Some(1.2_f64));
+        assert!(table.rate(usd, eur, None).is_none());
+        assert!(table.insert(rate(-1.0, 0)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_rounds_with_the_given_policy() {
+        assert!((Rounding::Down.round(1.239, 2) - 1.23).abs() < f64::EPSILON);
+        assert!((Rounding::Up.round(1.231, 2) - 1.24).abs() < f64::EPSILON);
+        assert!((Rounding::HalfUp.round(2.5, 0) - 3.0).abs() < f64::EPSILON);
+        assert!((Rounding::HalfEven.round(2.5, 0) - 2.0).abs() < f64::EPSILON);
+        assert!((Rounding::Down.round(0.29, 2) - 0.29).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn it_records_the_fee_and_rounding_remainder_of_a_conversion() -> Result<()> {
+        let conversion = Conversion::new(1, 7, 100.0, &rate(1.23456, 0)?, Rounding::Down);
+        assert!((conversion.gross - 123.456).abs() < 1e-9_f64);
+        assert!((conversion.fee - 1.234_56).abs() < 1e-9_f64);
+        assert!((conversion.credited - 122.22).abs() < 1e-9_f64);
+        assert!((conversion.remainder - 0.001_44).abs() < 1e-9_f64);
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_amounts_in_the_minor_unit_of_their_currency() -> Result<()> {
+        let rate = Rate {
+            from: "EUR".parse()?,
+            to: "JPY".parse()?,
+            rate: 161.234,
+            spread: 0.01,
+            effective_at: 0,
+        };
+        let conversion = Conversion::new(1, 7, 10.5, &rate, Rounding::Down);
+        let mut output = vec![];
+        write_conversions(&[conversion], &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,tx,from,to,amount,rate,effective_at,gross,spread,fee,credited,remainder
+1,7,EUR,JPY,10.50,161.234,0,1692.957,0.01,16.9295,1676,0.0274
+"
+        );
+        Ok(())
+    }
+}
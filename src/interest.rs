@@ -0,0 +1,183 @@
+//! This module holds the `InterestTiers` that an `accrue` operation credits interest at.
+//!
+//! An `accrue` row credits each client interest on the average available balance of its account
+//! since the previous accrual, weighted by the time between transaction timestamps.  Rates are
+//! marginal: each tier's rate applies to the part of the balance above its threshold and below the
+//! next tier's.  The interest is rounded down to the minor unit of the account's currency, and the
+//! residue left by rounding is posted to its own ledger account.
+
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the interest module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InterestError {
+    /// Triggered if a tier is not a non-negative threshold and rate, as `<threshold>:<rate>`
+    #[error("Invalid interest tier: {0}")]
+    InvalidTier(String),
+}
+
+/// The tiers of interest rates, by the balance each applies from.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InterestTiers {
+    /// The threshold and rate of each tier, lowest threshold first
+    tiers: Vec<(f64, f64)>,
+}
+
+impl InterestTiers {
+    /// Creates the tiers from their thresholds and rates, in any order.
+    /// # Errors
+    /// Returns an error if a threshold or rate is negative or not a number.
+    #[inline]
+    pub fn new(mut tiers: Vec<(f64, f64)>) -> Result<Self, InterestError> {
+        if let Some(&(threshold, rate)) = tiers
+            .iter()
+            .find(|&&(threshold, rate)| !(threshold >= 0.0_f64 && rate >= 0.0_f64))
+        {
+            return Err(InterestError::InvalidTier(format!("{threshold}:{rate}")));
+        }
+        tiers.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self { tiers })
+    }
+
+    /// Whether there are no tiers, so no interest is paid.
+    #[inline]
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.tiers.is_empty()
+    }
+
+    /// The interest earned on a balance for one accrual period.  Negative balances earn none.
+    #[inline]
+    #[must_use]
+    pub fn interest(&self, balance: f64) -> f64 {
+        let ceilings = self
+            .tiers
+            .iter()
+            .skip(1)
+            .map(|&(threshold, _)| threshold)
+            .chain(std::iter::once(f64::INFINITY));
+        self.tiers
+            .iter()
+            .zip(ceilings)
+            .map(|(&(threshold, rate), ceiling)| {
+                (balance.min(ceiling) - threshold).max(0.0_f64) * rate
+            })
+            .sum()
+    }
+}
+
+impl FromStr for InterestTiers {
+    type Err = InterestError;
+
+    /// Parses tiers given as `<threshold>:<rate>,...`, e.g. `0:0.0001,1000:0.0002`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tiers = s
+            .split(',')
+            .map(|tier| {
+                tier.split_once(':')
+                    .and_then(|(threshold, rate)| {
+                        Some((threshold.trim().parse().ok()?, rate.trim().parse().ok()?))
+                    })
+                    .ok_or_else(|| InterestError::InvalidTier(tier.to_owned()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(tiers)
+    }
+}
+
+/// The time-weighted average of a balance over an accrual period.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AverageBalance {
+    /// When the period started, once a time is known
+    start: Option<u64>,
+    /// When the balance last changed, once a time is known
+    at: Option<u64>,
+    /// The balance since it last changed
+    balance: f64,
+    /// The sum of the balance over every second of the period so far
+    area: f64,
+}
+
+impl AverageBalance {
+    /// Records the balance from the given time.  Changes without a time are weighted as though
+    /// they happened at the last time known.
+    #[inline]
+    pub fn record(&mut self, at: Option<u64>, balance: f64) {
+        self.advance(at);
+        self.balance = balance;
+    }
+
+    /// Ends the period at the given time, returning the average balance over it and starting the
+    /// next.  A period without any elapsed time averages to the balance at its end.
+    #[inline]
+    pub fn close(&mut self, at: Option<u64>) -> f64 {
+        self.advance(at);
+        let elapsed = match (self.start, self.at) {
+            (Some(start), Some(end)) => seconds(end.saturating_sub(start)),
+            _ => 0.0_f64,
+        };
+        let average = if elapsed > 0.0_f64 {
+            self.area / elapsed
+        } else {
+            self.balance
+        };
+        self.start = self.at;
+        self.area = 0.0_f64;
+        average
+    }
+
+    /// Adds the balance over the time since it last changed.
+    fn advance(&mut self, at: Option<u64>) {
+        let Some(now) = at else {
+            return;
+        };
+        if let Some(then) = self.at {
+            self.area += self.balance * seconds(now.saturating_sub(then));
+        }
+        self.start.get_or_insert(now);
+        self.at = Some(self.at.map_or(now, |then| then.max(now)));
+    }
+}
+
+/// A number of seconds as a float, saturating at about 136 years.
+fn seconds(elapsed: u64) -> f64 {
+    f64::from(u32::try_from(elapsed).unwrap_or(u32::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_applies_each_rate_to_the_part_of_the_balance_in_its_tier() -> Result<()> {
+        let tiers: InterestTiers = "1000:0.02, 0:0.01".parse()?;
+        assert!((tiers.interest(500.0) - 5.0).abs() < 1e-9_f64);
+        assert!((tiers.interest(1500.0) - 20.0).abs() < 1e-9_f64);
+        assert!(tiers.interest(-100.0).abs() < f64::EPSILON);
+        assert!(InterestTiers::default().interest(1500.0).abs() < f64::EPSILON);
+        assert_eq!(
+            "0:-0.01".parse::<InterestTiers>(),
+            Err(InterestError::InvalidTier("0:-0.01".to_owned()))
+        );
+        assert!("0.01".parse::<InterestTiers>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_weights_the_average_balance_by_time() {
+        let mut average = AverageBalance::default();
+        average.record(Some(0), 100.0);
+        average.record(Some(30), 400.0);
+        assert!((average.close(Some(60)) - 250.0).abs() < 1e-9_f64);
+        assert!((average.close(Some(90)) - 400.0).abs() < 1e-9_f64);
+        let mut untimed = AverageBalance::default();
+        untimed.record(None, 50.0);
+        assert!((untimed.close(None) - 50.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,287 @@
+//! This module checks the invariants every client account must keep, and builds the trial balance
+//! of the client ledgers.
+//!
+//! Each `Client` checks its own account as it processes transactions: that its available and held
+//! balances add up to its total, that its total equals the opening balance plus what each
+//! transaction applied moved according to its record, and that its held balance is backed by open
+//! disputes and authorizations.  The verification pass run after processing collects those
+//! violations, checks that every ledger balances, and checks that the recorded movements across all
+//! clients add up to the sum of their totals in every currency.
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::ledger::LedgerAccount;
+use crate::report::format_amount;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use thiserror::Error;
+
+/// How far apart two amounts that should be equal may drift through floating point error.
+const TOLERANCE: f64 = 1e-6;
+
+/// Serializer for the amounts of the trial balance, matching the client report.
+fn serialize_amount<S: Serializer>(value: &f64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_amount(*value))
+}
+
+/// An error type for the invariant module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum InvariantError {
+    /// Triggered if the verification pass finds violations
+    #[error("Found {} invariant violations, the first: {}", .0.len(), .0.first().map(ToString::to_string).unwrap_or_default())]
+    Violations(Vec<Violation>),
+    /// Triggered if the trial balance cannot be written
+    #[error("Failed to write trial balance: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// The invariants a client account must keep.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ViolationKind {
+    /// The available and held balances do not add up to the total balance.
+    UnbalancedTotal,
+    /// The total balance is not the opening balance plus the recorded movements.
+    FundsNotConserved,
+    /// The held balance is not backed by open disputes and authorizations.
+    UnbackedHold,
+    /// The debits posted to the ledger do not equal its credits.
+    UnbalancedLedger,
+}
+
+/// A broken invariant, naming the transaction after which it was found.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Violation {
+    /// The invariant that was broken
+    pub kind: ViolationKind,
+    /// The ID of the client, if the violation is in a single client's account
+    pub client: Option<u16>,
+    /// The currency of the account, if any
+    pub currency: Option<Currency>,
+    /// The ID of the transaction after which the violation was found, if known
+    pub tx: Option<u32>,
+    /// The amount the invariant expected
+    pub expected: f64,
+    /// The amount found
+    pub actual: f64,
+}
+
+impl Violation {
+    /// Compares an amount with the one an invariant expects, returning a violation if they differ.
+    #[inline]
+    #[must_use]
+    pub fn compare(
+        kind: ViolationKind,
+        client: Option<u16>,
+        currency: Option<Currency>,
+        tx: Option<u32>,
+        expected: f64,
+        actual: f64,
+    ) -> Option<Self> {
+        let scale = expected.abs().max(actual.abs()).max(1.0_f64);
+        ((expected - actual).abs() > TOLERANCE * scale).then_some(Self {
+            kind,
+            client,
+            currency,
+            tx,
+            expected,
+            actual,
+        })
+    }
+}
+
+impl Display for Violation {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.kind)?;
+        if let Some(client) = self.client {
+            write!(f, " for client {client}")?;
+        }
+        if let Some(currency) = self.currency {
+            write!(f, " in {currency}")?;
+        }
+        if let Some(tx) = self.tx {
+            write!(f, " after transaction {tx}")?;
+        }
+        write!(f, ": expected {}, found {}", self.expected, self.actual)
+    }
+}
+
+/// One row of the trial balance: the debits and credits of an account summed over every client.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrialBalanceRow {
+    /// The currency of the accounts, if any
+    pub currency: Option<Currency>,
+    /// The account
+    pub account: LedgerAccount,
+    /// The balance of the account, if it is a debit
+    #[serde(serialize_with = "serialize_amount")]
+    pub debit: f64,
+    /// The balance of the account, if it is a credit
+    #[serde(serialize_with = "serialize_amount")]
+    pub credit: f64,
+}
+
+/// Builds the trial balance of the ledgers of the given clients, with one row per account in
+/// every currency.
+#[inline]
+#[must_use]
+pub fn trial_balance(clients: &[Client]) -> Vec<TrialBalanceRow> {
+    let mut balances: BTreeMap<Option<Currency>, [f64; LedgerAccount::ALL.len()]> = BTreeMap::new();
+    for client in clients {
+        let sums = balances.entry(client.currency).or_default();
+        for (sum, account) in sums.iter_mut().zip(LedgerAccount::ALL) {
+            *sum += client.ledger().balance(account);
+        }
+    }
+    balances
+        .into_iter()
+        .flat_map(|(currency, sums)| {
+            LedgerAccount::ALL
+                .into_iter()
+                .zip(sums)
+                .map(move |(account, balance)| TrialBalanceRow {
+                    currency,
+                    account,
+                    debit: balance.max(0.0_f64),
+                    credit: (0.0_f64 - balance).max(0.0_f64),
+                })
+        })
+        .collect()
+}
+
+/// Verifies the invariants of the given clients after processing, returning every violation
+/// found.
+#[inline]
+#[must_use]
+pub fn verify(clients: &[Client]) -> Vec<Violation> {
+    let mut violations: Vec<Violation> = clients
+        .iter()
+        .flat_map(|client| client.violations().iter().cloned())
+        .collect();
+    violations.extend(clients.iter().filter_map(|client| {
+        Violation::compare(
+            ViolationKind::UnbalancedLedger,
+            Some(client.id),
+            client.currency,
+            None,
+            client.ledger().debits(),
+            client.ledger().credits(),
+        )
+    }));
+    let mut totals: BTreeMap<Option<Currency>, (f64, f64)> = BTreeMap::new();
+    for client in clients {
+        let (recorded, total) = totals.entry(client.currency).or_default();
+        *recorded += client.recorded_total();
+        *total += client.total_balance;
+    }
+    violations.extend(
+        totals
+            .into_iter()
+            .filter_map(|(currency, (recorded, total))| {
+                Violation::compare(
+                    ViolationKind::FundsNotConserved,
+                    None,
+                    currency,
+                    None,
+                    recorded,
+                    total,
+                )
+            }),
+    );
+    violations
+}
+
+/// Writes the trial balance as CSV to `writer`.
+/// # Errors
+/// Returns an error if the rows cannot be serialized or written.
+#[inline]
+pub fn write_trial_balance<W: Write>(
+    rows: &[TrialBalanceRow],
+    writer: W,
+) -> Result<(), InvariantError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for row in rows {
+        csv_writer.serialize(row)?;
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::RawTransactionStream;
+    use crate::transaction::{RawTransaction, RawTransactionVariant};
+    use anyhow::Result;
+    use async_stream::stream;
+
+    #[test]
+    fn it_only_reports_amounts_that_differ() {
+        assert!(Violation::compare(
+            ViolationKind::UnbalancedTotal,
+            Some(1),
+            None,
+            Some(2),
+            0.3,
+            0.1 + 0.2
+        )
+        .is_none());
+        let violation = Violation::compare(
+            ViolationKind::UnbackedHold,
+            Some(1),
+            None,
+            Some(2),
+            0.0,
+            5.0,
+        );
+        assert_eq!(
+            violation.map(|v| v.to_string()).as_deref(),
+            Some("UnbackedHold for client 1 after transaction 2: expected 0, found 5")
+        );
+    }
+
+    #[tokio::test]
+    async fn it_writes_the_trial_balance_of_the_client_ledgers() -> Result<()> {
+        let mut clients = vec![Client::new(1), Client::new(2)];
+        for (client, (deposit, withdrawal)) in clients
+            .iter_mut()
+            .zip([(10.0_f64, 4.0_f64), (5.0_f64, 0.5_f64)])
+        {
+            let id = client.id;
+            let stream = RawTransactionStream::new(stream! {
+                for (tx_id, amount, variant) in [
+                    (1, deposit, RawTransactionVariant::Deposit),
+                    (2, withdrawal, RawTransactionVariant::Withdrawal),
+                ] {
+                    yield RawTransaction::new(variant, id, tx_id, Some(amount));
+                }
+            });
+            client.process_activity(stream).await;
+        }
+        assert!(verify(&clients).is_empty());
+        let mut output = vec![];
+        write_trial_balance(&trial_balance(&clients), &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+currency,account,debit,credit
+,client_available,0.0,10.5
+,client_held,0.0,0.0
+,settlement,10.5,0.0
+,chargeback_losses,0.0,0.0
+,fee_income,0.0,0.0
+,interest_expense,0.0,0.0
+,interest_residue,0.0,0.0
+,shortfall_allowance,0.0,0.0
+"
+        );
+        Ok(())
+    }
+}
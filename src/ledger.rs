@@ -0,0 +1,319 @@
+//! This module holds the double-entry `Ledger` behind a client's balances.
+//!
+//! Every change to a client's balances is posted as a balanced journal entry across a small chart
+//! of accounts, and the client's available and held balances are read back from the ledger.
+//! Amounts are kept debit-positive: the client accounts are what the platform owes the client, so
+//! their balances are credits, while money held at the settlement account is a debit.
+
+use crate::currency::Currency;
+use crate::fx::Rounding;
+use crate::report::format_amount;
+use crate::transaction::TransactionKind;
+use serde::{Serialize, Serializer};
+use std::io::Write;
+use thiserror::Error;
+
+/// How far apart the debits and credits of a ledger may drift through floating point error.
+const TOLERANCE: f64 = 1e-6;
+
+/// Serializer for the amounts of a journal line.  Accrued interest is posted at full precision, so
+/// amounts are rounded to 4 decimal places rather than truncated like the report's.
+fn serialize_amount<S: Serializer>(value: &f64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_amount(Rounding::HalfUp.round(*value, 4)))
+}
+
+/// An error type for the ledger module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum LedgerError {
+    /// Triggered if the debits posted to a client's ledger do not equal its credits
+    #[error("Ledger of client {client} does not balance: debits {debits}, credits {credits}")]
+    Unbalanced {
+        /// The ID of the client
+        client: u16,
+        /// The sum of the debits posted
+        debits: f64,
+        /// The sum of the credits posted
+        credits: f64,
+    },
+    /// Triggered if the journal cannot be written
+    #[error("Failed to write journal: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// The chart of accounts that journal entries are posted to.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LedgerAccount {
+    /// The client's available funds.
+    ClientAvailable,
+    /// The client's held funds, under dispute or authorization.
+    ClientHeld,
+    /// The money that moves in and out of the platform, the counterpart of deposits, withdrawals
+    /// and chargebacks.
+    Settlement,
+    /// Chargeback shortfalls left uncovered, provisioned against the client's negative balance
+    /// until they are repaid.
+    ChargebackLosses,
+    /// The fees the platform earns, such as the spread of a conversion.
+    FeeIncome,
+    /// The interest the platform pays on clients' available balances.
+    InterestExpense,
+    /// The interest accrued but left uncredited by rounding it to the minor unit, which the
+    /// platform still owes.
+    InterestResidue,
+    /// The allowance against uncovered chargeback shortfalls, the counterpart of
+    /// `ChargebackLosses`.  The client's negative balance is still carried for collections, and
+    /// the allowance is released as it is repaid.
+    ShortfallAllowance,
+}
+
+impl LedgerAccount {
+    /// Every account in the chart.
+    pub const ALL: [Self; 8] = [
+        Self::ClientAvailable,
+        Self::ClientHeld,
+        Self::Settlement,
+        Self::ChargebackLosses,
+        Self::FeeIncome,
+        Self::InterestExpense,
+        Self::InterestResidue,
+        Self::ShortfallAllowance,
+    ];
+
+    /// The position of the account in `ALL`.
+    const fn index(self) -> usize {
+        match self {
+            Self::ClientAvailable => 0,
+            Self::ClientHeld => 1,
+            Self::Settlement => 2,
+            Self::ChargebackLosses => 3,
+            Self::FeeIncome => 4,
+            Self::InterestExpense => 5,
+            Self::InterestResidue => 6,
+            Self::ShortfallAllowance => 7,
+        }
+    }
+}
+
+/// One line of a journal entry, debiting or crediting a single account.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalLine {
+    /// The ID of the transaction the entry was posted for
+    pub tx: u32,
+    /// The ID of the client
+    pub client: u16,
+    /// The currency of the client's account, if any
+    pub currency: Option<Currency>,
+    /// The type of the transaction
+    #[serde(rename = "type")]
+    pub variant: TransactionKind,
+    /// The account posted to
+    pub account: LedgerAccount,
+    /// The amount debited
+    #[serde(serialize_with = "serialize_amount")]
+    pub debit: f64,
+    /// The amount credited
+    #[serde(serialize_with = "serialize_amount")]
+    pub credit: f64,
+}
+
+/// The double-entry ledger of a single client's account.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ledger {
+    /// The balance of each account, debit-positive, in the order of `LedgerAccount::ALL`
+    balances: [f64; LedgerAccount::ALL.len()],
+    /// The sum of every debit posted
+    debits: f64,
+    /// The sum of every credit posted
+    credits: f64,
+    /// Every line posted, if the journal is being recorded
+    journal: Option<Vec<JournalLine>>,
+}
+
+impl Ledger {
+    /// Creates an empty ledger, which keeps every journal line posted if `record_journal` is set.
+    #[inline]
+    #[must_use]
+    pub fn new(record_journal: bool) -> Self {
+        Self {
+            journal: record_journal.then(Vec::new),
+            ..Self::default()
+        }
+    }
+
+    /// Posts a journal entry for a transaction.  Each line is an account with a debit-positive
+    /// amount, and the amounts must sum to zero.  Lines with nothing to post are left out.
+    #[inline]
+    pub fn post(
+        &mut self,
+        tx: u32,
+        client: u16,
+        currency: Option<Currency>,
+        variant: TransactionKind,
+        lines: &[(LedgerAccount, f64)],
+    ) {
+        for &(account, amount) in lines {
+            if amount == 0.0_f64 {
+                continue;
+            }
+            if let Some(balance) = self.balances.get_mut(account.index()) {
+                *balance += amount;
+            }
+            let (debit, credit) = if amount > 0.0_f64 {
+                (amount, 0.0_f64)
+            } else {
+                (0.0_f64, -amount)
+            };
+            self.debits += debit;
+            self.credits += credit;
+            if let Some(ref mut journal) = self.journal {
+                journal.push(JournalLine {
+                    tx,
+                    client,
+                    currency,
+                    variant,
+                    account,
+                    debit,
+                    credit,
+                });
+            }
+        }
+    }
+
+    /// The balance of an account, debit-positive.
+    #[inline]
+    #[must_use]
+    pub fn balance(&self, account: LedgerAccount) -> f64 {
+        self.balances
+            .get(account.index())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// The balance of an account that is normally a credit, such as a client account.
+    #[inline]
+    #[must_use]
+    pub fn credit_balance(&self, account: LedgerAccount) -> f64 {
+        0.0_f64 - self.balance(account)
+    }
+
+    /// The sum of every debit posted.
+    #[inline]
+    #[must_use]
+    pub const fn debits(&self) -> f64 {
+        self.debits
+    }
+
+    /// The sum of every credit posted.
+    #[inline]
+    #[must_use]
+    pub const fn credits(&self) -> f64 {
+        self.credits
+    }
+
+    /// Proves that the debits posted equal the credits.
+    /// # Errors
+    /// Returns an error naming the client if they do not.
+    #[inline]
+    pub fn verify(&self, client: u16) -> Result<(), LedgerError> {
+        if (self.debits - self.credits).abs() > TOLERANCE * self.debits.max(1.0_f64) {
+            return Err(LedgerError::Unbalanced {
+                client,
+                debits: self.debits,
+                credits: self.credits,
+            });
+        }
+        Ok(())
+    }
+
+    /// Takes the journal lines posted so far, if the journal is being recorded.
+    #[inline]
+    pub fn take_journal(&mut self) -> Vec<JournalLine> {
+        self.journal
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+}
+
+/// Writes the journal lines as CSV to `writer`.
+/// # Errors
+/// Returns an error if the lines cannot be serialized or written.
+#[inline]
+pub fn write_journal<W: Write>(lines: &[JournalLine], writer: W) -> Result<(), LedgerError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for line in lines {
+        csv_writer.serialize(line)?;
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::RawTransactionVariant;
+    use anyhow::Result;
+
+    #[test]
+    fn it_posts_balanced_entries_and_writes_the_journal() -> Result<()> {
+        let mut ledger = Ledger::new(true);
+        ledger.post(
+            1,
+            1,
+            None,
+            RawTransactionVariant::Deposit.into(),
+            &[
+                (LedgerAccount::ClientAvailable, -10.0_f64),
+                (LedgerAccount::ClientHeld, 0.0_f64),
+                (LedgerAccount::Settlement, 10.0_f64),
+            ],
+        );
+        ledger.post(
+            1,
+            1,
+            None,
+            RawTransactionVariant::Dispute.into(),
+            &[
+                (LedgerAccount::ClientAvailable, 10.0_f64),
+                (LedgerAccount::ClientHeld, -10.0_f64),
+            ],
+        );
+        assert!(ledger.verify(1).is_ok());
+        assert!(ledger.balance(LedgerAccount::ClientAvailable).abs() < f64::EPSILON);
+        assert!((ledger.balance(LedgerAccount::ClientHeld) + 10.0).abs() < f64::EPSILON);
+        let mut output = vec![];
+        write_journal(&ledger.take_journal(), &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+tx,client,currency,type,account,debit,credit
+1,1,,deposit,client_available,0.0,10.0
+1,1,,deposit,settlement,10.0,0.0
+1,1,,dispute,client_available,10.0,0.0
+1,1,,dispute,client_held,0.0,10.0
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_fails_to_verify_a_ledger_that_does_not_balance() {
+        let mut ledger = Ledger::new(false);
+        ledger.post(
+            1,
+            7,
+            None,
+            RawTransactionVariant::Deposit.into(),
+            &[(LedgerAccount::Settlement, 10.0_f64)],
+        );
+        assert!(matches!(
+            ledger.verify(7),
+            Err(LedgerError::Unbalanced { client: 7, .. })
+        ));
+    }
+}
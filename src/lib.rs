@@ -20,7 +20,31 @@
 #![allow(clippy::struct_excessive_bools)]
 #![allow(clippy::use_self)]
 #![allow(clippy::pattern_type_mismatch)]
+pub mod chargeback;
 pub mod client;
+pub mod config;
+pub mod currency;
+pub mod debt;
+pub mod dispute_fee;
+pub mod duplicate;
+pub mod funds;
+pub mod fx;
+pub mod interest;
+pub mod invariant;
+pub mod ledger;
+pub mod mt940;
+pub mod outcome;
+pub mod period;
 pub mod processor;
+pub mod profile;
 pub mod reader;
+pub mod report;
+pub mod reserve;
+pub mod risk;
+pub mod rules;
+pub mod settlement;
+pub mod shortfall;
+pub mod statement;
 pub mod transaction;
+pub mod velocity;
+pub mod window;
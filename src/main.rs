@@ -1,25 +1,448 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use coding_test::chargeback::{write_collections, write_reversals};
+use coding_test::client::Client;
+use coding_test::config::EngineConfig;
+use coding_test::currency::Currency;
+use coding_test::dispute_fee::DisputeFee;
+use coding_test::duplicate::DuplicateDeposit;
+use coding_test::funds::FundsAvailability;
+use coding_test::fx::{read_rates, write_conversions, RateTable, Rounding};
+use coding_test::interest::InterestTiers;
+use coding_test::invariant::{trial_balance, verify, write_trial_balance, InvariantError};
+use coding_test::ledger::write_journal;
+use coding_test::mt940::read_accounts;
+use coding_test::outcome::write_outcomes;
+use coding_test::period::write_period_closes;
 use coding_test::processor::Processor;
-use coding_test::reader::read_transactions_from_file;
+use coding_test::profile::{read_profiles, ClientProfile};
+use coding_test::reader::{
+    read_mt940_transactions_from_file, read_transactions_from_file_as, InputFormat,
+};
+use coding_test::report::{write_report, OutputFormat};
+use coding_test::reserve::ReservePolicy;
+use coding_test::risk::RiskWeights;
+use coding_test::rules::{read_rules, Rule};
+use coding_test::settlement::{net_batches, reconcile, write_settlement};
+use coding_test::shortfall::{builtin_policy, ShortfallPolicy};
+use coding_test::statement::{write_camt053, Camt053Options};
+use coding_test::velocity::VelocityRule;
+use coding_test::window::Window;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::path::Path;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The usage message printed by `--help`.
+const USAGE: &str = "\
+Usage: <input file path> [options]
+
+Options:
+  --input-format <csv|ndjson|mt940>  Format of the input file.  Defaults to the file extension, or csv.
+  --mt940-accounts <path>            Read the client of each MT940 account identification from a CSV
+                                     file with the columns account and client.
+  --mt940-client <id>                Assign every MT940 entry to this client instead of the client
+                                     of the statement's account identification.
+  --output <path>                    Write the client report to a file instead of stdout.
+  --output-format <csv|json|ndjson>  Format of the client report.  Defaults to the output file
+                                     extension, or csv.
+  --camt053 <path>                   Write a camt.053 statement for every client to a file, with one
+                                     statement per period closed.
+  --camt053-currency <code>          Currency of the camt.053 statements of accounts whose transactions
+                                     name none.  Defaults to EUR.
+  --outcomes <path>                  Write the outcome of every transaction to a CSV file.
+  --reversals <path>                 Write the withdrawals reversed by chargebacks to a CSV file.
+  --shortfall-policy <policy>        How a chargeback that leaves a negative balance is covered:
+                                     newest-first (default), oldest-first, allow-negative or
+                                     smallest-cover.
+  --collections <path>               Write the chargeback shortfalls left uncovered to a CSV file.
+  --funds-check <policy>             Which funds a withdrawal may draw on: total (default), available
+                                     or overdraft:<limit>.
+  --profiles <path>                  Read per-client withdrawal limits from a CSV file.
+  --velocity-rule <rule>             Flag, hold or reject transactions that go over a velocity
+                                     threshold, as <deposit|withdrawal>:<count|total>:<threshold>:
+                                     <window>:<flag|hold|reject>.  May be given more than once.
+  --rules <path>                     Read the rules evaluated before each deposit and withdrawal from
+                                     a JSON file.  May be given more than once.
+  --duplicate-deposits <window>      Flag deposits of the same amount as an earlier deposit within the
+                                     window, as <window>[:flag|hold].
+  --risk-score <weights>             Add a risk_score column to the report, weighted by default or by
+                                     <signal>=<weight>,... overriding the default weights, over the
+                                     last 1000 transactions or window=<window>.
+  --authorization-expiry <window>    Expire authorizations left open for longer than the window,
+                                     releasing their hold.
+  --rates <path>                     Read the rates converts move funds between currencies at from a
+                                     CSV file.
+  --fx-rounding <policy>             How the amount a convert credits is rounded: down (default), up,
+                                     half-up or half-even.
+  --conversions <path>               Write the rate, fee and rounding remainder of every convert to a
+                                     CSV file.
+  --journal <path>                   Write every journal line posted to the client ledgers to a CSV
+                                     file.
+  --trial-balance <path>             Write the balance of every ledger account summed over the clients
+                                     to a CSV file.
+  --verify <each|end>                Recount the holds backed by open disputes after each
+                                     transaction, or only at the end, checking held funds against a
+                                     running sum of them in between.  Defaults to end.
+  --periods <path>                   Write the balances of every client at the close of each period
+                                     to a CSV file.
+  --settlement <path>                Write the deposits, withdrawals, chargebacks and fees netted per
+                                     partner per period, and the amount owed, to a CSV file.
+  --interest <tiers>                 Credit interest on the average available balance at each accrue,
+                                     at tiered rates given as <threshold>:<rate>,...
+  --dispute-fee <fee>                Charge a fee for every chargeback: fixed:<amount> or
+                                     percent:<percent> of the amount charged back.
+  --reserve <percent>:<window>       Keep back a percentage of every deposit in a rolling reserve until
+                                     the deposit leaves the window, e.g. 10:90d.  Time windows need
+                                     timestamps to pass.
+  --sub-accounts <rollup|list>       Roll the balances of sub-accounts up into each client's row
+                                     (default), or also list a row per sub-account.";
+
+/// The options given on the command line.
+#[derive(Debug)]
+struct Options {
+    /// The path of the input file.
+    input: PathBuf,
+    /// The format of the input file.
+    input_format: InputFormat,
+    /// The client of each MT940 account identification.
+    mt940_accounts: BTreeMap<String, u16>,
+    /// The client every MT940 entry is assigned to, if any.
+    mt940_client: Option<u16>,
+    /// The path to write the report to, or `None` for stdout.
+    output: Option<PathBuf>,
+    /// The format of the report.
+    output_format: OutputFormat,
+    /// The path to write the camt.053 statements to, if any.
+    camt053: Option<PathBuf>,
+    /// The currency of the camt.053 statements of accounts without a currency of their own.
+    camt053_currency: Currency,
+    /// The path to write the transaction outcomes to, if any.
+    outcomes: Option<PathBuf>,
+    /// The path to write the withdrawals reversed by chargebacks to, if any.
+    reversals: Option<PathBuf>,
+    /// How the shortfall left by a chargeback is covered, if not the default.
+    shortfall_policy: Option<Arc<dyn ShortfallPolicy>>,
+    /// The path to write the uncovered chargeback shortfalls to, if any.
+    collections: Option<PathBuf>,
+    /// Which funds a withdrawal may draw on.
+    funds_availability: FundsAvailability,
+    /// The per-client limits read from the profile file.
+    profiles: HashMap<u16, ClientProfile>,
+    /// The rules evaluated before every client's deposits and withdrawals.
+    rules: Arc<[Box<dyn Rule>]>,
+    /// The weights of the risk score, if it should be reported.
+    risk_weights: Option<RiskWeights>,
+    /// How long an authorization may stay open, if not only the dispute window.
+    authorization_expiry: Option<Window>,
+    /// The rates converts move funds between currencies at.
+    rates: RateTable,
+    /// How the amount a convert credits is rounded.
+    fx_rounding: Rounding,
+    /// The path to write the conversions to, if any.
+    conversions: Option<PathBuf>,
+    /// The path to write the journal to, if any.
+    journal: Option<PathBuf>,
+    /// The path to write the trial balance to, if any.
+    trial_balance: Option<PathBuf>,
+    /// Whether held funds are checked after each transaction, if not the build's default.
+    verify_each_transaction: Option<bool>,
+    /// The path to write the snapshots of each closed period to, if any.
+    periods: Option<PathBuf>,
+    /// The path to write the settlement batches to, if any.
+    settlement: Option<PathBuf>,
+    /// The tiers of the interest credited by an accrue.
+    interest: InterestTiers,
+    /// The fee charged for every chargeback, if any.
+    dispute_fee: Option<DisputeFee>,
+    /// The rolling reserve kept back from every deposit, if any.
+    reserve: Option<ReservePolicy>,
+    /// Whether the report lists a row per sub-account.
+    list_sub_accounts: bool,
+}
+
+impl Options {
+    /// Parses the command line arguments, returning `None` if the usage message should be shown.
+    fn parse(args: &[String]) -> Result<Option<Self>> {
+        let mut input = None;
+        let mut input_format = None;
+        let mut mt940_accounts = BTreeMap::new();
+        let mut mt940_client = None;
+        let mut output = None;
+        let mut output_format = None;
+        let mut camt053 = None;
+        let mut camt053_currency = "EUR".parse()?;
+        let mut outcomes = None;
+        let mut reversals = None;
+        let mut shortfall_policy = None;
+        let mut collections = None;
+        let mut funds_availability = FundsAvailability::default();
+        let mut profiles = HashMap::new();
+        let mut rules: Vec<Box<dyn Rule>> = vec![];
+        let mut risk_weights = None;
+        let mut authorization_expiry = None;
+        let mut rates = RateTable::default();
+        let mut fx_rounding = Rounding::default();
+        let mut conversions = None;
+        let mut journal = None;
+        let mut trial_balance = None;
+        let mut verify_each_transaction = None;
+        let mut periods = None;
+        let mut settlement = None;
+        let mut interest = InterestTiers::default();
+        let mut dispute_fee = None;
+        let mut reserve = None;
+        let mut list_sub_accounts = false;
+        let mut iter = args.iter().skip(1);
+        while let Some(arg) = iter.next() {
+            if arg == "--help" {
+                return Ok(None);
+            }
+            if !arg.starts_with("--") {
+                if input.is_some() {
+                    return Err(anyhow!("Unexpected argument: {}", arg));
+                }
+                input = Some(PathBuf::from(arg));
+                continue;
+            }
+            let value = iter
+                .next()
+                .ok_or_else(|| anyhow!("Missing value for {}", arg))?;
+            match arg.as_str() {
+                "--input-format" => input_format = Some(value.parse()?),
+                "--mt940-accounts" => mt940_accounts = read_accounts(Path::new(value))?,
+                "--mt940-client" => mt940_client = Some(value.parse()?),
+                "--output" => output = Some(PathBuf::from(value)),
+                "--output-format" => output_format = Some(value.parse()?),
+                "--camt053" => camt053 = Some(PathBuf::from(value)),
+                "--camt053-currency" => camt053_currency = value.parse()?,
+                "--outcomes" => outcomes = Some(PathBuf::from(value)),
+                "--reversals" => reversals = Some(PathBuf::from(value)),
+                "--shortfall-policy" => shortfall_policy = Some(builtin_policy(value)?),
+                "--collections" => collections = Some(PathBuf::from(value)),
+                "--funds-check" => funds_availability = value.parse()?,
+                "--profiles" => profiles = read_profiles(Path::new(value))?,
+                "--velocity-rule" => rules.push(Box::new(value.parse::<VelocityRule>()?)),
+                "--rules" => rules.extend(read_rules(Path::new(value))?),
+                "--risk-score" => risk_weights = Some(value.parse()?),
+                "--authorization-expiry" => authorization_expiry = Some(value.parse()?),
+                "--rates" => rates = read_rates(Path::new(value))?,
+                "--fx-rounding" => fx_rounding = value.parse()?,
+                "--conversions" => conversions = Some(PathBuf::from(value)),
+                "--journal" => journal = Some(PathBuf::from(value)),
+                "--periods" => periods = Some(PathBuf::from(value)),
+                "--settlement" => settlement = Some(PathBuf::from(value)),
+                "--interest" => interest = value.parse()?,
+                "--dispute-fee" => dispute_fee = Some(value.parse()?),
+                "--reserve" => reserve = Some(value.parse()?),
+                "--sub-accounts" => {
+                    list_sub_accounts = match value.as_str() {
+                        "rollup" => false,
+                        "list" => true,
+                        _ => return Err(anyhow!("Unknown sub-account mode: {}", value)),
+                    };
+                }
+                "--trial-balance" => trial_balance = Some(PathBuf::from(value)),
+                "--verify" => {
+                    verify_each_transaction = Some(match value.as_str() {
+                        "each" => true,
+                        "end" => false,
+                        _ => return Err(anyhow!("Unknown verification mode: {}", value)),
+                    });
+                }
+                "--duplicate-deposits" => {
+                    rules.push(Box::new(value.parse::<DuplicateDeposit>()?));
+                }
+                _ => return Err(anyhow!("Unknown option: {}", arg)),
+            }
+        }
+        Ok(input.map(|input| {
+            let input_format = input_format.unwrap_or_else(|| InputFormat::from_path(&input));
+            let output_format = output_format.unwrap_or_else(|| {
+                output
+                    .as_deref()
+                    .map_or(OutputFormat::Csv, OutputFormat::from_path)
+            });
+            Self {
+                input,
+                input_format,
+                mt940_accounts,
+                mt940_client,
+                output,
+                output_format,
+                camt053,
+                camt053_currency,
+                outcomes,
+                reversals,
+                shortfall_policy,
+                collections,
+                funds_availability,
+                profiles,
+                rules: Arc::from(rules),
+                risk_weights,
+                authorization_expiry,
+                rates,
+                fx_rounding,
+                conversions,
+                journal,
+                trial_balance,
+                verify_each_transaction,
+                periods,
+                settlement,
+                interest,
+                dispute_fee,
+                reserve,
+                list_sub_accounts,
+            }
+        }))
+    }
+
+    /// Builds the engine configuration needed for the requested outputs.
+    fn engine_config(&self) -> EngineConfig {
+        let mut config = EngineConfig::default();
+        config.record_statements = self.camt053.is_some();
+        config.record_outcomes = self.outcomes.is_some();
+        config.record_journal = self.journal.is_some();
+        config.funds_availability = self.funds_availability;
+        config.profiles = Arc::new(self.profiles.clone());
+        config.rules = Arc::clone(&self.rules);
+        config.risk_weights = self.risk_weights;
+        config.authorization_expiry = self.authorization_expiry;
+        config.rates = Arc::new(self.rates.clone());
+        config.fx_rounding = self.fx_rounding;
+        config.interest = Arc::new(self.interest.clone());
+        config.dispute_fee = self.dispute_fee;
+        config.reserve = self.reserve;
+        config.list_sub_accounts = self.list_sub_accounts;
+        if let Some(verify_each_transaction) = self.verify_each_transaction {
+            config.verify_each_transaction = verify_each_transaction;
+        }
+        if let Some(ref policy) = self.shortfall_policy {
+            config.shortfall_policy = Arc::clone(policy);
+        }
+        config
+    }
+
+    /// Opens the destination of the client report.
+    fn open_output(&self) -> Result<Box<dyn Write>> {
+        Ok(match self.output {
+            Some(ref path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(std::io::stdout()),
+        })
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 || args[1] == "--help" {
-        println!("Usage: <csv file path>");
-        return Ok(());
-    }
-    let path = Path::new(&args[1]);
-    let stream = read_transactions_from_file(path).await?;
-    let mut processor = Processor::default();
-    let clients = processor.process_transactions(stream).await?;
-    let mut writer = csv::Writer::from_writer(std::io::stdout());
-    for client in clients {
-        writer.serialize(client)?;
-    }
-    writer.flush()?;
+    let options = match Options::parse(&args)? {
+        Some(options) => options,
+        None => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let path = Path::new(&options.input);
+    let stream = if options.input_format == InputFormat::Mt940 {
+        read_mt940_transactions_from_file(
+            path,
+            options.mt940_accounts.clone(),
+            options.mt940_client,
+        )
+        .await?
+    } else {
+        read_transactions_from_file_as(path, options.input_format).await?
+    };
+    let mut processor = Processor::new(options.engine_config());
+    let mut clients = processor.process_transactions(stream).await?;
+    clients.sort_by_key(|c| (c.id, c.currency));
+    let violations = verify(&clients);
+    if !violations.is_empty() {
+        for violation in &violations {
+            eprintln!("Invariant violated: {}", violation);
+        }
+        return Err(InvariantError::Violations(violations).into());
+    }
+    let batches = net_batches(
+        clients
+            .iter_mut()
+            .flat_map(Client::take_settlement)
+            .collect(),
+    );
+    reconcile(&batches, &clients)?;
+    if let Some(ref path) = options.settlement {
+        write_settlement(&batches, BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(ref path) = options.trial_balance {
+        write_trial_balance(
+            &trial_balance(&clients),
+            BufWriter::new(File::create(path)?),
+        )?;
+    }
+    if let Some(ref path) = options.camt053 {
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let statements = clients
+            .iter_mut()
+            .flat_map(|client| {
+                let mut statements = client.take_closed_statements();
+                statements.extend(client.take_statement());
+                statements
+            })
+            .collect::<Vec<_>>();
+        let camt053_options = Camt053Options::new(
+            &format!("STMT-{}", created_at),
+            created_at,
+            options.camt053_currency,
+        );
+        write_camt053(
+            &statements,
+            &camt053_options,
+            BufWriter::new(File::create(path)?),
+        )?;
+    }
+    if let Some(ref path) = options.periods {
+        let closes = clients
+            .iter_mut()
+            .flat_map(Client::take_period_closes)
+            .collect::<Vec<_>>();
+        write_period_closes(&closes, BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(ref path) = options.outcomes {
+        // Each client's outcomes are written in the order they were recorded, across its accounts.
+        let mut outcomes = clients
+            .iter_mut()
+            .flat_map(Client::take_outcomes)
+            .collect::<Vec<_>>();
+        outcomes.sort_by_key(|o| (o.client_id, o.seq));
+        write_outcomes(&outcomes, BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(ref path) = options.journal {
+        let journal = clients
+            .iter_mut()
+            .flat_map(Client::take_journal)
+            .collect::<Vec<_>>();
+        write_journal(&journal, BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(ref path) = options.conversions {
+        let conversions = clients
+            .iter_mut()
+            .flat_map(Client::take_conversions)
+            .collect::<Vec<_>>();
+        write_conversions(&conversions, BufWriter::new(File::create(path)?))?;
+    }
+    let events = clients
+        .iter_mut()
+        .flat_map(Client::take_chargeback_events)
+        .collect::<Vec<_>>();
+    if let Some(ref path) = options.reversals {
+        write_reversals(&events, BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(ref path) = options.collections {
+        write_collections(&events, BufWriter::new(File::create(path)?))?;
+    }
+    write_report(&clients, options.output_format, options.open_output()?)?;
 
     Ok(())
 }
@@ -0,0 +1,425 @@
+//! This module parses SWIFT MT940 customer statement messages into `RawTransaction`s, so that
+//! statements received from a bank can be run through the same engine as other transaction sources.
+//!
+//! Only the fields needed to build transactions are interpreted:
+//! * `:25:` (account identification) selects the client the following entries belong to, as given
+//!   by a mapping of account identifications to clients.
+//! * `:60F:` or `:60M:` (opening balance) gives the currency of the following entries.
+//! * `:61:` (statement line) becomes a deposit for credits and reversed debits, or a withdrawal for
+//!   debits and reversed credits.  The bank reference (after `//`) becomes the transaction id,
+//!   falling back to the account owner's reference when no bank reference is given.  A line with
+//!   neither, i.e. with only the `NONREF` filler, is rejected, as it cannot be told apart from
+//!   other such lines.  A statement line may continue on the lines after it: a continuation that
+//!   starts with `//` carries the bank reference, and any other holds supplementary details.
+//!
+//! Every other field, and the supplementary details of a statement line, are ignored.
+
+use crate::currency::Currency;
+use crate::transaction::{RawTransaction, RawTransactionVariant};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// The FNV-1a offset basis for 32 bit hashes.
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+
+/// The FNV-1a prime for 32 bit hashes.
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// The bit set in every transaction id derived from a hash, and clear in every numeric reference
+/// used as it is, so that the two cannot collide.
+const HASHED_ID_BIT: u32 = 0x8000_0000;
+
+/// The filler used in place of a reference that does not exist.
+const NO_REFERENCE: &str = "NONREF";
+
+/// An error type for the MT940 module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Mt940Error {
+    /// Triggered if a statement line appears before any account identification
+    #[error("Statement line has no account identification")]
+    MissingAccount,
+    /// Triggered if an account identification cannot be mapped to a client id
+    #[error("Account cannot be mapped to a client: {0}")]
+    UnknownAccount(String),
+    /// Triggered if a statement line is malformed
+    #[error("Invalid statement line: {0}")]
+    InvalidStatementLine(String),
+    /// Triggered if a statement line has neither a bank reference nor an owner reference
+    #[error("Statement line has no reference: {0}")]
+    MissingReference(String),
+    /// Triggered if an opening balance is malformed
+    #[error("Invalid opening balance: {0}")]
+    InvalidOpeningBalance(String),
+    /// Triggered if the mapping of accounts to clients cannot be read
+    #[error("Failed to read the account mapping: {0}")]
+    Accounts(String),
+}
+
+/// A row of the mapping of account identifications to clients.
+#[derive(Debug, Deserialize)]
+struct AccountClient {
+    /// The account identification, as given by `:25:`
+    account: String,
+    /// The client the account belongs to
+    client: u16,
+}
+
+/// Reads the mapping of account identifications to clients from a CSV file with the columns
+/// `account` and `client`.
+/// # Errors
+/// Returns an error if the file cannot be read or a row cannot be parsed.
+#[inline]
+pub fn read_accounts(path: &Path) -> Result<BTreeMap<String, u16>, Mt940Error> {
+    let read = || -> Result<BTreeMap<String, u16>, csv::Error> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?;
+        reader
+            .deserialize::<AccountClient>()
+            .map(|row| row.map(|row| (row.account, row.client)))
+            .collect()
+    };
+    read().map_err(|error| Mt940Error::Accounts(error.to_string()))
+}
+
+/// Maps a reference to a transaction id.
+///
+/// Numeric references below 2^31 are used as they are, other references are hashed with FNV-1a so
+/// that the same reference always maps to the same id.  Hashed ids have their top bit set, so they
+/// never collide with a numeric reference.
+#[must_use]
+#[inline]
+pub fn reference_to_tx_id(reference: &str) -> u32 {
+    match reference.parse::<u32>() {
+        Ok(id) if id & HASHED_ID_BIT == 0 => id,
+        _ => {
+            reference.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+                (hash ^ u32::from(byte)).wrapping_mul(FNV_PRIME)
+            }) | HASHED_ID_BIT
+        }
+    }
+}
+
+/// A line-by-line parser of MT940 messages.
+///
+/// A statement line is only complete once the line after it starts another field, so each one is
+/// returned by the call that reads the next field, or by `finish` at the end of the input.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Mt940Parser {
+    /// The client of each account identification.
+    accounts: BTreeMap<String, u16>,
+    /// The client that every entry is assigned to, regardless of the account identification.
+    client_override: Option<u16>,
+    /// The client of the account currently being read.
+    current_client: Option<u16>,
+    /// The currency of the statement currently being read, once its opening balance is read.
+    current_currency: Option<Currency>,
+    /// The statement line being read, with the bank reference of any continuation line.
+    pending: Option<String>,
+}
+
+impl Mt940Parser {
+    /// Creates a new `Mt940Parser`.  If `client_override` is given, every entry is assigned to
+    /// that client, otherwise the client of each statement's account identification is looked up
+    /// in `accounts`.
+    #[inline]
+    #[must_use]
+    pub const fn new(accounts: BTreeMap<String, u16>, client_override: Option<u16>) -> Self {
+        Self {
+            accounts,
+            client_override,
+            current_client: None,
+            current_currency: None,
+            pending: None,
+        }
+    }
+
+    /// Parses a single line of an MT940 file, returning a transaction if the line completes a
+    /// statement line.
+    /// # Errors
+    /// Returns an error if the line completes a statement line, or is an account identification or
+    /// opening balance, that cannot be interpreted.
+    #[inline]
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<RawTransaction>, Mt940Error> {
+        let line = line.trim();
+        if !line.starts_with(':') && !line.starts_with('-') {
+            if let Some(ref mut pending) = self.pending {
+                if line.starts_with("//") && !pending.contains("//") {
+                    pending.push_str(line);
+                }
+            }
+            return Ok(None);
+        }
+        let completed = self.finish();
+        if let Some(account) = line.strip_prefix(":25:") {
+            self.current_client = None;
+            self.current_currency = None;
+            if self.client_override.is_none() {
+                let client_id = self
+                    .accounts
+                    .get(account.trim())
+                    .ok_or_else(|| Mt940Error::UnknownAccount(account.to_owned()))?;
+                self.current_client = Some(*client_id);
+            }
+        } else if let Some(balance) = line
+            .strip_prefix(":60F:")
+            .or_else(|| line.strip_prefix(":60M:"))
+        {
+            // The currency follows the debit or credit mark and the date.
+            let currency = balance
+                .get(7..10)
+                .and_then(|code| code.parse().ok())
+                .ok_or_else(|| Mt940Error::InvalidOpeningBalance(balance.to_owned()))?;
+            self.current_currency = Some(currency);
+        } else if let Some(statement_line) = line.strip_prefix(":61:") {
+            self.pending = Some(statement_line.to_owned());
+        }
+        completed
+    }
+
+    /// Completes the statement line being read, if any, returning its transaction.
+    /// # Errors
+    /// Returns an error if the statement line cannot be interpreted.
+    #[inline]
+    pub fn finish(&mut self) -> Result<Option<RawTransaction>, Mt940Error> {
+        let Some(statement_line) = self.pending.take() else {
+            return Ok(None);
+        };
+        self.parse_statement_line(&statement_line).map(Some)
+    }
+
+    /// Parses the body of a `:61:` statement line into a `RawTransaction` for the account being
+    /// read.
+    fn parse_statement_line(&self, line: &str) -> Result<RawTransaction, Mt940Error> {
+        let client_id = self
+            .client_override
+            .or(self.current_client)
+            .ok_or(Mt940Error::MissingAccount)?;
+        let invalid = || Mt940Error::InvalidStatementLine(line.to_owned());
+        let rest = line.get(6..).ok_or_else(invalid)?;
+        if !line
+            .get(..6)
+            .ok_or_else(invalid)?
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+        // The optional entry date is four more digits.
+        let rest = match rest.get(..4) {
+            Some(entry_date) if entry_date.bytes().all(|b| b.is_ascii_digit()) => {
+                rest.get(4..).ok_or_else(invalid)?
+            }
+            _ => rest,
+        };
+        let (variant, rest) = if let Some(r) = rest.strip_prefix("RC") {
+            (RawTransactionVariant::Withdrawal, r)
+        } else if let Some(r) = rest.strip_prefix("RD") {
+            (RawTransactionVariant::Deposit, r)
+        } else if let Some(r) = rest.strip_prefix('C') {
+            (RawTransactionVariant::Deposit, r)
+        } else if let Some(r) = rest.strip_prefix('D') {
+            (RawTransactionVariant::Withdrawal, r)
+        } else {
+            return Err(invalid());
+        };
+        // The optional funds code is a single letter before the amount.
+        let rest = match rest.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() => rest.get(1..).ok_or_else(invalid)?,
+            _ => rest,
+        };
+        let amount_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != ',')
+            .ok_or_else(invalid)?;
+        let amount = rest
+            .get(..amount_len)
+            .ok_or_else(invalid)?
+            .replace(',', ".")
+            .parse::<f64>()
+            .ok()
+            .ok_or_else(invalid)?;
+        // Skip the transaction type identification code, e.g. `NTRF`.
+        let references = rest
+            .get(amount_len.saturating_add(4)..)
+            .ok_or_else(invalid)?;
+        let (owner_reference, bank_reference) = match references.split_once("//") {
+            Some((owner, bank)) => (owner.trim(), bank.trim()),
+            None => (references.trim(), ""),
+        };
+        let reference = if bank_reference.is_empty() {
+            owner_reference
+        } else {
+            bank_reference
+        };
+        if reference.is_empty() {
+            return Err(invalid());
+        }
+        if reference == NO_REFERENCE {
+            return Err(Mt940Error::MissingReference(line.to_owned()));
+        }
+        Ok(RawTransaction {
+            currency: self.current_currency,
+            ..RawTransaction::new(
+                variant,
+                client_id,
+                reference_to_tx_id(reference),
+                Some(amount),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    fn accounts() -> BTreeMap<String, u16> {
+        BTreeMap::from([("DE89370400440532013000".to_owned(), 7)])
+    }
+
+    #[test]
+    fn it_parses_credits_as_deposits_and_debits_as_withdrawals() -> Result<()> {
+        let mut parser = Mt940Parser::new(accounts(), None);
+        assert_eq!(parser.parse_line(":20:STMT1")?, None);
+        assert_eq!(parser.parse_line(":25:DE89370400440532013000")?, None);
+        assert_eq!(
+            parser.parse_line(":61:2206010601C1000,00NTRFNONREF//100")?,
+            None
+        );
+        assert_eq!(
+            parser.parse_line(":61:220602D250,5NTRF42")?,
+            Some(RawTransaction::new(
+                RawTransactionVariant::Deposit,
+                7,
+                100,
+                Some(1000.0_f64)
+            ))
+        );
+        assert_eq!(
+            parser.parse_line(":86:Supplementary information")?,
+            Some(RawTransaction::new(
+                RawTransactionVariant::Withdrawal,
+                7,
+                42,
+                Some(250.5_f64)
+            ))
+        );
+        assert_eq!(parser.finish()?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn it_parses_reversals_and_funds_codes() -> Result<()> {
+        let mut parser = Mt940Parser::new(BTreeMap::new(), Some(3));
+        assert_eq!(parser.parse_line(":61:220601RCR10,NCHGREF//5")?, None);
+        assert_eq!(
+            parser.parse_line(":61:220601RD10,NCHGREF//6")?,
+            Some(RawTransaction::new(
+                RawTransactionVariant::Withdrawal,
+                3,
+                5,
+                Some(10.0_f64)
+            ))
+        );
+        assert_eq!(
+            parser.finish()?,
+            Some(RawTransaction::new(
+                RawTransactionVariant::Deposit,
+                3,
+                6,
+                Some(10.0_f64)
+            ))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_continuation_lines_and_the_statement_currency() -> Result<()> {
+        let mut parser = Mt940Parser::new(accounts(), None);
+        for line in [
+            ":25:DE89370400440532013000",
+            ":60F:C220531JPY0,",
+            ":61:2206010601C1500,NTRFPAYIN",
+            "//B2206010001",
+            "Supplementary details",
+        ] {
+            assert_eq!(parser.parse_line(line)?, None);
+        }
+        assert_eq!(
+            parser.parse_line(":62F:C220601JPY1500,")?,
+            Some(RawTransaction {
+                currency: Some("JPY".parse()?),
+                ..RawTransaction::new(
+                    RawTransactionVariant::Deposit,
+                    7,
+                    reference_to_tx_id("B2206010001"),
+                    Some(1500.0_f64)
+                )
+            })
+        );
+        assert_eq!(
+            parser.parse_line(":60F:C2205EUR"),
+            Err(Mt940Error::InvalidOpeningBalance("C2205EUR".to_owned()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_reads_the_mapping_of_accounts_to_clients() -> Result<()> {
+        let accounts = read_accounts(Path::new("test_data/test_mt940_accounts.csv"))?;
+        assert_eq!(accounts.get("BANKDEFF/1"), Some(&1));
+        assert_eq!(accounts.get("BANKDEFF/2"), Some(&2));
+        assert!(read_accounts(Path::new("test_data/missing.csv")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_hashes_non_numeric_references() {
+        let id = reference_to_tx_id("B22060100001");
+        assert_eq!(id, reference_to_tx_id("B22060100001"));
+        assert_ne!(id, reference_to_tx_id("B22060100002"));
+        assert_ne!(id & HASHED_ID_BIT, 0);
+        assert_eq!(reference_to_tx_id("12345"), 12345);
+        assert_ne!(reference_to_tx_id("3000000000") & HASHED_ID_BIT, 0);
+    }
+
+    #[test]
+    fn it_rejects_invalid_statement_lines() -> Result<()> {
+        let mut parser = Mt940Parser::new(accounts(), None);
+        assert_eq!(parser.parse_line(":61:220601C1000,00NTRF//1")?, None);
+        assert_eq!(parser.finish(), Err(Mt940Error::MissingAccount));
+        assert_eq!(
+            parser.parse_line(":25:NL91ABNA0417164300"),
+            Err(Mt940Error::UnknownAccount("NL91ABNA0417164300".to_owned()))
+        );
+        let mut parser = Mt940Parser::new(BTreeMap::new(), Some(1));
+        for line in [
+            ":61:2206X1C1000,00NTRF//1",
+            ":61:220601X1000,00NTRF//1",
+            ":61:220601C1000,00NTRF",
+        ] {
+            assert_eq!(parser.parse_line(line)?, None);
+            assert!(parser.finish().is_err());
+        }
+        assert_eq!(parser.parse_line(":61:220601C1000,00NTRFNONREF")?, None);
+        assert_eq!(
+            parser.finish(),
+            Err(Mt940Error::MissingReference(
+                "220601C1000,00NTRFNONREF".to_owned()
+            ))
+        );
+        assert_eq!(parser.parse_line(":61:220601C1000,00NTRFNONREF//")?, None);
+        assert_eq!(
+            parser.finish(),
+            Err(Mt940Error::MissingReference(
+                "220601C1000,00NTRFNONREF//".to_owned()
+            ))
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+//! This module describes what happened to each transaction processed by a `Client`, and writes
+//! those outcomes as a per-transaction outcome report.
+
+use crate::report::format_amount;
+use crate::statement::BalanceSnapshot;
+use crate::transaction::TransactionKind;
+use serde::{Serialize, Serializer};
+use std::io::Write;
+use thiserror::Error;
+
+/// Serializer for the balances of an outcome, matching the client report.
+fn serialize_amount<S: Serializer>(value: &f64, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&format_amount(*value))
+}
+
+/// An error type for the outcome module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OutcomeError {
+    /// Triggered if an outcome cannot be written
+    #[error("Failed to write outcome report: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// What happened to a transaction.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The transaction was applied to the client's balances.
+    Accepted,
+    /// The transaction was valid but could not be applied, e.g. for lack of funds.
+    Failed,
+    /// The transaction was invalid and was ignored.
+    Rejected,
+    /// An earlier withdrawal was reversed to cover the shortfall left by a chargeback.
+    Reversed,
+    /// An open dispute was resolved because its transaction left the dispute window.
+    AutoResolved,
+    /// A rule held a withdrawal for review, and it was not made.
+    Held,
+    /// An authorization expired before it was captured or voided, releasing its hold.
+    Expired,
+}
+
+/// Why a transaction was not applied.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reason {
+    /// The client did not have the funds to cover a withdrawal.
+    InsufficientFunds,
+    /// The withdrawal would have brought the available balance below zero.
+    InsufficientAvailableFunds,
+    /// The withdrawal would have taken the available balance beyond the overdraft limit.
+    OverdraftLimitExceeded,
+    /// The withdrawal was larger than the client's maximum single withdrawal.
+    WithdrawalLimitExceeded,
+    /// The withdrawal would have taken the client over its withdrawal cap for the window.
+    WithdrawalCapExceeded,
+    /// The transaction could not be parsed into a valid transaction.
+    InvalidTransaction,
+    /// The transaction names a different client than the one it was given to.
+    WrongClient,
+    /// The client's account is locked.
+    AccountLocked,
+    /// The referenced transaction is unknown, or has left the dispute window.
+    UnknownTransaction,
+    /// The referenced transaction cannot be disputed, resolved or charged back.
+    NotDisputable,
+    /// The referenced transaction is already disputed, or its dispute has been settled.
+    AlreadyDisputed,
+    /// The referenced transaction is not under an open dispute.
+    NotDisputed,
+    /// The withdrawal was reversed to cover the shortfall left by a chargeback.
+    ReversedByChargeback,
+    /// The dispute window of the transaction expired before its dispute was settled.
+    DisputeWindowExpired,
+    /// A rule held the withdrawal for review.
+    HeldByRule,
+    /// A rule rejected the transaction.
+    RejectedByRule,
+    /// The referenced transaction is not an authorization.
+    NotAnAuthorization,
+    /// The authorization was already voided or expired.
+    AuthorizationReleased,
+    /// The capture was for more than the amount authorized.
+    CaptureExceedsAuthorization,
+    /// The authorization passed its deadline before it was captured or voided.
+    AuthorizationExpired,
+    /// There is no rate to convert between the currencies of a convert.
+    NoExchangeRate,
+    /// The transaction a refund names is not a deposit.
+    NotADeposit,
+    /// The refunds of a deposit would have added up to more than its amount.
+    RefundExceedsDeposit,
+}
+
+impl Reason {
+    /// The outcome of a transaction that ended for this reason.
+    #[inline]
+    #[must_use]
+    pub const fn outcome(self) -> Outcome {
+        match self {
+            Self::InsufficientFunds
+            | Self::InsufficientAvailableFunds
+            | Self::OverdraftLimitExceeded
+            | Self::WithdrawalLimitExceeded
+            | Self::WithdrawalCapExceeded => Outcome::Failed,
+            Self::HeldByRule => Outcome::Held,
+            Self::ReversedByChargeback => Outcome::Reversed,
+            Self::DisputeWindowExpired => Outcome::AutoResolved,
+            Self::AuthorizationExpired => Outcome::Expired,
+            Self::InvalidTransaction
+            | Self::WrongClient
+            | Self::AccountLocked
+            | Self::UnknownTransaction
+            | Self::NotDisputable
+            | Self::AlreadyDisputed
+            | Self::NotDisputed
+            | Self::RejectedByRule
+            | Self::NotAnAuthorization
+            | Self::AuthorizationReleased
+            | Self::CaptureExceedsAuthorization
+            | Self::NoExchangeRate
+            | Self::NotADeposit
+            | Self::RefundExceedsDeposit => Outcome::Rejected,
+        }
+    }
+}
+
+/// What is worth noting about a transaction that was applied.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Note {
+    /// The withdrawal drew on held funds or an overdraft, and took the available balance below
+    /// zero.
+    AvailableBelowZero,
+    /// A rule flagged the client for review.
+    FlaggedByRule,
+    /// A rule held the deposit for review, crediting it to the held balance.
+    HeldByRule,
+}
+
+impl Note {
+    /// The name of the note in the outcome report.
+    #[inline]
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::AvailableBelowZero => "available_below_zero",
+            Self::FlaggedByRule => "flagged_by_rule",
+            Self::HeldByRule => "held_by_rule",
+        }
+    }
+
+    /// Serializes the notes on an outcome as their names, separated by semicolons.
+    /// # Errors
+    /// Returns an error if the serializer fails.
+    #[inline]
+    pub fn serialize_all<S: Serializer>(notes: &[Self], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(
+            &notes
+                .iter()
+                .map(|note| note.name())
+                .collect::<Vec<_>>()
+                .join(";"),
+        )
+    }
+}
+
+/// The outcome of a single transaction, with the client's balances after it was processed.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TransactionOutcome {
+    /// The ID of the transaction
+    #[serde(rename = "tx")]
+    pub tx_id: u32,
+    /// The ID of the client
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    /// The currency of the client's account, if any transaction named a currency
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The type of the transaction
+    #[serde(rename = "type")]
+    pub variant: TransactionKind,
+    /// What happened to the transaction
+    pub outcome: Outcome,
+    /// Why the transaction was not applied
+    pub reason: Option<Reason>,
+    /// The available balance of the client after the transaction
+    #[serde(serialize_with = "serialize_amount")]
+    pub available_after: f64,
+    /// The held balance of the client after the transaction
+    #[serde(serialize_with = "serialize_amount")]
+    pub held_after: f64,
+    /// The total balance of the client after the transaction
+    #[serde(serialize_with = "serialize_amount")]
+    pub total_after: f64,
+    /// What is worth noting about the transaction, if it was applied
+    #[serde(serialize_with = "Note::serialize_all")]
+    pub notes: Vec<Note>,
+    /// The rule that decided the outcome, if a rule matched
+    pub rule: Option<&'static str>,
+    /// The position of the outcome among those recorded for the client, across its accounts
+    #[serde(skip)]
+    pub seq: u64,
+}
+
+impl TransactionOutcome {
+    /// Creates a new `TransactionOutcome` from the result of processing a transaction: the notes
+    /// on it if it was applied, or the reason it was not.
+    #[inline]
+    #[must_use]
+    pub fn new(
+        tx_id: u32,
+        client_id: u16,
+        variant: TransactionKind,
+        result: Result<Vec<Note>, Reason>,
+        after: BalanceSnapshot,
+    ) -> Self {
+        let (outcome, reason, notes) = match result {
+            Ok(notes) => (Outcome::Accepted, None, notes),
+            Err(reason) => (reason.outcome(), Some(reason), Vec::new()),
+        };
+        Self {
+            tx_id,
+            client_id,
+            currency: None,
+            variant,
+            outcome,
+            reason,
+            available_after: after.available,
+            held_after: after.held,
+            total_after: after.total,
+            notes,
+            rule: None,
+            seq: 0,
+        }
+    }
+
+    /// Names the rule that decided the outcome.
+    #[inline]
+    #[must_use]
+    pub const fn with_rule(mut self, rule: Option<&'static str>) -> Self {
+        self.rule = rule;
+        self
+    }
+}
+
+/// Writes the outcomes as CSV to `writer`.  Once any outcome names a currency, every row gets a
+/// `currency` column.
+/// # Errors
+/// Returns an error if the outcomes cannot be serialized or written.
+#[inline]
+pub fn write_outcomes<W: Write>(
+    outcomes: &[TransactionOutcome],
+    writer: W,
+) -> Result<(), OutcomeError> {
+    let with_currency = outcomes.iter().any(|o| o.currency.is_some());
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for outcome in outcomes {
+        if with_currency && outcome.currency.is_none() {
+            csv_writer.serialize(TransactionOutcome {
+                currency: Some(String::new()),
+                ..outcome.clone()
+            })?;
+        } else {
+            csv_writer.serialize(outcome)?;
+        }
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::RawTransactionVariant;
+    use anyhow::Result;
+
+    #[test]
+    fn it_writes_outcomes_as_csv() -> Result<()> {
+        let outcomes = vec![
+            TransactionOutcome::new(
+                1,
+                1,
+                RawTransactionVariant::Deposit.into(),
+                Ok(vec![]),
+                BalanceSnapshot::new(1.5, 0.0, 1.5),
+            ),
+            TransactionOutcome::new(
+                2,
+                1,
+                RawTransactionVariant::Withdrawal.into(),
+                Err(Reason::InsufficientFunds),
+                BalanceSnapshot::new(1.5, 0.0, 1.5),
+            ),
+            TransactionOutcome::new(
+                3,
+                1,
+                RawTransactionVariant::Withdrawal.into(),
+                Err(Reason::ReversedByChargeback),
+                BalanceSnapshot::new(1.5, 0.0, 1.5),
+            ),
+            TransactionOutcome::new(
+                4,
+                1,
+                RawTransactionVariant::Withdrawal.into(),
+                Ok(vec![Note::FlaggedByRule, Note::AvailableBelowZero]),
+                BalanceSnapshot::new(-0.5, 1.0, 0.5),
+            ),
+        ];
+        let mut output = vec![];
+        write_outcomes(&outcomes, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+tx,client,type,outcome,reason,available_after,held_after,total_after,notes,rule
+1,1,deposit,accepted,,1.5,0.0,1.5,,
+2,1,withdrawal,failed,insufficient_funds,1.5,0.0,1.5,,
+3,1,withdrawal,reversed,reversed_by_chargeback,1.5,0.0,1.5,,
+4,1,withdrawal,accepted,,-0.5,1.0,0.5,flagged_by_rule;available_below_zero,
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_adds_a_currency_column_once_any_outcome_names_a_currency() -> Result<()> {
+        let deposit = TransactionOutcome::new(
+            1,
+            1,
+            RawTransactionVariant::Deposit.into(),
+            Ok(vec![]),
+            BalanceSnapshot::new(1.5, 0.0, 1.5),
+        );
+        let outcomes = vec![
+            TransactionOutcome {
+                currency: Some("EUR".to_owned()),
+                ..deposit.clone()
+            },
+            deposit,
+        ];
+        let mut output = vec![];
+        write_outcomes(&outcomes, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+tx,client,currency,type,outcome,reason,available_after,held_after,total_after,notes,rule
+1,1,EUR,deposit,accepted,,1.5,0.0,1.5,,
+1,1,,deposit,accepted,,1.5,0.0,1.5,,
+"
+        );
+        Ok(())
+    }
+}
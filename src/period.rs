@@ -0,0 +1,156 @@
+//! This module holds the `PeriodClose` snapshot that a `Client` freezes when a period is closed.
+//!
+//! A `close` row in the input closes the current period of every account that has seen a
+//! transaction, identified by the row's `tx`.  Each account freezes a snapshot of its balances,
+//! closes its statement with them, and opens the statement of the next period with the same
+//! balances.  Open disputes and authorizations carry over to the next period untouched.
+
+use crate::currency::Currency;
+use crate::report::format_amount;
+use crate::statement::BalanceSnapshot;
+use serde::Serialize;
+use std::io::Write;
+use thiserror::Error;
+
+/// An error type for the period module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum PeriodError {
+    /// Triggered if the period snapshots cannot be written
+    #[error("Failed to write period snapshots: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// The snapshot of a client's account frozen when a period was closed.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PeriodClose {
+    /// The ID of the period closed
+    pub period: u32,
+    /// When the period was closed, in seconds since the Unix epoch, if the close says
+    pub closed_at: Option<u64>,
+    /// The ID of the client
+    pub client: u16,
+    /// The currency of the account, if any
+    pub currency: Option<Currency>,
+    /// The balances of the account when the period was closed
+    pub balances: BalanceSnapshot,
+    /// Whether the account was locked when the period was closed
+    pub locked: bool,
+}
+
+impl PeriodClose {
+    /// Creates a new `PeriodClose`
+    #[inline]
+    #[must_use]
+    pub const fn new(
+        period: u32,
+        closed_at: Option<u64>,
+        client: u16,
+        currency: Option<Currency>,
+        balances: BalanceSnapshot,
+        locked: bool,
+    ) -> Self {
+        Self {
+            period,
+            closed_at,
+            client,
+            currency,
+            balances,
+            locked,
+        }
+    }
+}
+
+/// A row of the period snapshots, with the balances formatted like the client report.
+#[derive(Serialize)]
+struct PeriodCloseRow {
+    /// The ID of the period closed
+    period: u32,
+    /// When the period was closed
+    closed_at: Option<u64>,
+    /// The ID of the client
+    client: u16,
+    /// The currency of the account
+    currency: Option<Currency>,
+    /// The available balance
+    available: String,
+    /// The held balance
+    held: String,
+    /// The total balance
+    total: String,
+    /// Whether the account was locked
+    locked: bool,
+}
+
+impl From<&PeriodClose> for PeriodCloseRow {
+    fn from(close: &PeriodClose) -> Self {
+        let format = |value| {
+            close
+                .currency
+                .map_or_else(|| format_amount(value), |c| c.format(value))
+        };
+        Self {
+            period: close.period,
+            closed_at: close.closed_at,
+            client: close.client,
+            currency: close.currency,
+            available: format(close.balances.available),
+            held: format(close.balances.held),
+            total: format(close.balances.total),
+            locked: close.locked,
+        }
+    }
+}
+
+/// Writes the period snapshots as CSV to `writer`.
+/// # Errors
+/// Returns an error if the snapshots cannot be serialized or written.
+#[inline]
+pub fn write_period_closes<W: Write>(closes: &[PeriodClose], writer: W) -> Result<(), PeriodError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for close in closes {
+        csv_writer.serialize(PeriodCloseRow::from(close))?;
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_writes_the_snapshot_of_each_closed_period() -> Result<()> {
+        let closes = [
+            PeriodClose::new(
+                20_240_101,
+                Some(1_704_153_600),
+                1,
+                None,
+                BalanceSnapshot::new(7.5, 2.5, 10.0),
+                false,
+            ),
+            PeriodClose::new(
+                20_240_101,
+                Some(1_704_153_600),
+                2,
+                Some("JPY".parse()?),
+                BalanceSnapshot::new(1200.0, 0.0, 1200.0),
+                true,
+            ),
+        ];
+        let mut output = vec![];
+        write_period_closes(&closes, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+period,closed_at,client,currency,available,held,total,locked
+20240101,1704153600,1,,7.5,2.5,10.0,false
+20240101,1704153600,2,JPY,1200,0,1200,true
+"
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,15 @@
 //! Holds the `Processor`
 
-use crate::client::Client;
+use crate::client::{Client, SharedState};
+use crate::config::EngineConfig;
+use crate::currency::Currency;
 use crate::reader::RawTransactionStream;
-use crate::transaction::RawTransaction;
-use async_stream::stream;
+use crate::transaction::{RawTransaction, RawTransactionVariant};
 use futures_util::future::join_all;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use thiserror::Error;
 use tokio::sync::mpsc::error::SendError;
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 
@@ -30,11 +31,24 @@ pub enum ProcessorError {
 pub struct Processor {
     /// The `Sender` for each of the client streams.
     client_senders: HashMap<u16, Sender<RawTransaction>>,
-    /// The handle for the stream sender.
-    client_handles: HashMap<u16, JoinHandle<Client>>,
+    /// The handle for the stream sender, which yields the `Client` of each of the client's
+    /// accounts.
+    client_handles: HashMap<u16, JoinHandle<Vec<Client>>>,
+    /// The configuration given to each new `Client`.
+    config: EngineConfig,
 }
 
 impl Processor {
+    /// Creates a new `Processor` whose `Client`s follow the given configuration.
+    #[inline]
+    #[must_use]
+    pub fn new(config: EngineConfig) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
     /// Processes a stream of `RawTransaction`s and sends them to their respective `Client`s.
     /// # Errors
     /// Returns an error if the `Sender` for the `Client` fails to send the `RawTransaction`.
@@ -45,35 +59,14 @@ impl Processor {
         mut transactions: RawTransactionStream,
     ) -> Result<Vec<Client>, ProcessorError> {
         while let Some(transaction) = transactions.next().await {
-            if let std::collections::hash_map::Entry::Vacant(e) =
-                self.client_handles.entry(transaction.client_id)
-            {
-                let mut client = Client::new(transaction.client_id);
-                let (tx, mut rx) = tokio::sync::mpsc::channel(10);
-                self.client_senders.insert(transaction.client_id, tx);
-
-                let stream = RawTransactionStream::new(stream! {
-                    while let Some(t) = rx.recv().await {
-                        yield t;
-                    }
-                });
-
-                let handle = tokio::spawn(async move {
-                    client.process_activity(stream).await;
-                    client
-                });
-
-                e.insert(handle);
-                self.client_senders
-                    .get(&transaction.client_id)
-                    .ok_or(ProcessorError::ClientError)?
-                    .send(transaction)
-                    .await?;
-            } else if let Some(sender) = self.client_senders.get_mut(&transaction.client_id) {
-                sender.send(transaction).await?;
-            } else {
-                return Err(ProcessorError::ClientError);
+            if matches!(
+                transaction.variant,
+                RawTransactionVariant::Close | RawTransactionVariant::Accrue
+            ) {
+                self.broadcast(&transaction).await?;
+                continue;
             }
+            self.send(transaction).await?;
         }
 
         self.client_senders.clear();
@@ -81,6 +74,46 @@ impl Processor {
         Ok(self.join_clients().await)
     }
 
+    /// Sends a close or accrue to every client started so far, which applies it to each of its
+    /// accounts once it has processed the transactions sent before it.
+    async fn broadcast(&self, control: &RawTransaction) -> Result<(), ProcessorError> {
+        for (&client_id, sender) in &self.client_senders {
+            sender
+                .send(RawTransaction {
+                    client_id,
+                    ..control.clone()
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Sends a transaction to its client, and starts the client if this is its first transaction.
+    async fn send(&mut self, transaction: RawTransaction) -> Result<(), ProcessorError> {
+        let client_id = transaction.client_id;
+        if let std::collections::hash_map::Entry::Vacant(e) = self.client_handles.entry(client_id) {
+            let (tx, rx) = tokio::sync::mpsc::channel(10);
+            self.client_senders.insert(client_id, tx);
+            let task = ClientTask {
+                client_id,
+                config: self.config.clone(),
+                accounts: BTreeMap::new(),
+                shared: SharedState::default(),
+            };
+            e.insert(tokio::spawn(task.run(rx)));
+            self.client_senders
+                .get(&client_id)
+                .ok_or(ProcessorError::ClientError)?
+                .send(transaction)
+                .await?;
+        } else if let Some(sender) = self.client_senders.get_mut(&client_id) {
+            sender.send(transaction).await?;
+        } else {
+            return Err(ProcessorError::ClientError);
+        }
+        Ok(())
+    }
+
     /// Joins the `Client` handles into a vector of the finished `Client`s.
     #[inline]
     async fn join_clients(&mut self) -> Vec<Client> {
@@ -92,32 +125,129 @@ impl Processor {
         .await
         .into_iter()
         .flatten()
+        .flatten()
         .collect()
     }
 }
 
+/// The task that processes the transactions of a client.
+///
+/// The accounts of a client are processed in turn by a single task, which owns the state they
+/// share: the client's lock, and the activity its rules and risk score are evaluated on.
+#[derive(Debug)]
+struct ClientTask {
+    /// The id of the client.
+    client_id: u16,
+    /// The configuration given to each account the client opens.
+    config: EngineConfig,
+    /// The client's account in each currency it has used.
+    accounts: BTreeMap<Option<Currency>, Client>,
+    /// The state shared by the client's accounts.
+    shared: SharedState,
+}
+
+impl ClientTask {
+    /// Processes the transactions of the client, and returns the `Client` of each of its accounts.
+    /// The credit of a convert is applied to the account converted into before the next
+    /// transaction.
+    async fn run(mut self, mut transactions: Receiver<RawTransaction>) -> Vec<Client> {
+        while let Some(mut transaction) = transactions.recv().await {
+            if matches!(
+                transaction.variant,
+                RawTransactionVariant::Close | RawTransactionVariant::Accrue
+            ) {
+                for account in self.accounts.values_mut() {
+                    account.process(transaction.clone(), &mut self.shared);
+                }
+                continue;
+            }
+            if transaction.currency.is_none() {
+                transaction.currency = self.referred_currency(&transaction);
+            }
+            let account = account_in(
+                &mut self.accounts,
+                self.client_id,
+                transaction.currency,
+                &self.config,
+            );
+            account.process(transaction, &mut self.shared);
+            if let Some(conversion) = account.take_conversion_credit() {
+                account_in(
+                    &mut self.accounts,
+                    self.client_id,
+                    Some(conversion.to),
+                    &self.config,
+                )
+                .credit_conversion(&conversion, &mut self.shared);
+            }
+        }
+        let shared = self.shared;
+        self.accounts
+            .into_values()
+            .map(|mut account| {
+                account.end_activity(&shared);
+                account
+            })
+            .collect()
+    }
+
+    /// Finds the currency of the account that holds the transaction a transaction refers to, for
+    /// transactions that do not name a currency: the transaction a dispute, resolve, chargeback,
+    /// capture or void names, or the deposit a refund returns.
+    fn referred_currency(&self, transaction: &RawTransaction) -> Option<Currency> {
+        let referred = match transaction.variant {
+            RawTransactionVariant::Dispute
+            | RawTransactionVariant::Resolve
+            | RawTransactionVariant::Chargeback
+            | RawTransactionVariant::Capture
+            | RawTransactionVariant::Void => transaction.tx_id,
+            RawTransactionVariant::Refund => transaction.original_tx?,
+            RawTransactionVariant::Deposit
+            | RawTransactionVariant::Withdrawal
+            | RawTransactionVariant::Authorize
+            | RawTransactionVariant::Convert
+            | RawTransactionVariant::Close
+            | RawTransactionVariant::Accrue => return None,
+        };
+        let (&currency, _) = self
+            .accounts
+            .iter()
+            .find(|&(_, account)| account.holds_transaction(referred))?;
+        currency
+    }
+}
+
+/// Returns the client's account in a currency, opening it if this is its first transaction.
+fn account_in<'accounts>(
+    accounts: &'accounts mut BTreeMap<Option<Currency>, Client>,
+    client_id: u16,
+    currency: Option<Currency>,
+    config: &EngineConfig,
+) -> &'accounts mut Client {
+    accounts.entry(currency).or_insert_with(|| {
+        let mut client = Client::with_currency(client_id, currency, config);
+        client.begin_activity();
+        client
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::transaction::RawTransactionVariant;
-    use anyhow::Result;
+    use crate::fx::{Rate, RateTable};
+    use crate::invariant::verify;
+    use crate::ledger::LedgerAccount;
+    use crate::outcome::Outcome;
+    use anyhow::{anyhow, Result};
+    use async_stream::stream;
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn it_processes_transactions_for_a_single_client() -> Result<()> {
         let mut processor = Processor::default();
         let raw_transactions = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(1000.0_f64),
-                variant: RawTransactionVariant::Deposit
-            };
-            yield RawTransaction {
-                client_id: 1,
-                tx_id: 2,
-                amount: Some(500.0_f64),
-                variant: RawTransactionVariant::Withdrawal
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.0_f64));
         });
         let clients = processor.process_transactions(raw_transactions).await?;
         assert_eq!(clients.len(), 1);
@@ -135,24 +265,9 @@ mod tests {
         let mut processor = Processor::default();
 
         let raw_transactions = RawTransactionStream::new(stream! {
-            yield RawTransaction {
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(1000.0_f64),
-                variant: RawTransactionVariant::Deposit
-            };
-            yield RawTransaction {
-                client_id: 1,
-                tx_id: 2,
-                amount: Some(500.0_f64),
-                variant: RawTransactionVariant::Withdrawal
-            };
-            yield RawTransaction {
-                client_id: 2,
-                tx_id: 3,
-                amount: Some(500.0_f64),
-                variant: RawTransactionVariant::Deposit
-            };
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 2, 3, Some(500.0_f64));
         });
 
         let mut clients = processor.process_transactions(raw_transactions).await?;
@@ -174,4 +289,234 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn it_keeps_separate_balances_per_currency() -> Result<()> {
+        let mut processor = Processor::default();
+        let eur: Currency = "EUR".parse()?;
+        let usd: Currency = "USD".parse()?;
+        let raw_transactions = RawTransactionStream::new(stream! {
+            yield RawTransaction {
+                currency: Some(eur),
+                ..RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(100.0_f64))
+            };
+            yield RawTransaction {
+                currency: Some(usd),
+                ..RawTransaction::new(RawTransactionVariant::Deposit, 1, 2, Some(50.129_f64))
+            };
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction {
+                currency: Some(usd),
+                ..RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 3, Some(80.0_f64))
+            };
+        });
+        let mut clients = processor.process_transactions(raw_transactions).await?;
+        clients.sort_by_key(|c| c.currency);
+        assert_eq!(clients.len(), 2);
+        let (in_eur, in_usd) = (&clients[0], &clients[1]);
+        assert_eq!(in_eur.currency, Some(eur));
+        assert!(in_eur.available_balance.abs() < f64::EPSILON);
+        assert!((in_eur.held_balance - 100.0).abs() < f64::EPSILON);
+        assert_eq!(in_usd.currency, Some(usd));
+        assert!((in_usd.available_balance - 50.12).abs() < f64::EPSILON);
+        assert!(in_usd.held_balance.abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_shares_the_lock_across_the_currencies_of_a_client() -> Result<()> {
+        let mut processor = Processor::default();
+        let eur: Currency = "EUR".parse()?;
+        let usd: Currency = "USD".parse()?;
+        let raw_transactions = RawTransactionStream::new(stream! {
+            for (client_id, tx_id, currency) in [(1, 1, eur), (1, 2, usd), (2, 3, eur)] {
+                yield RawTransaction {
+                    currency: Some(currency),
+                    ..RawTransaction::new(RawTransactionVariant::Deposit, client_id, tx_id, Some(1.13_f64))
+                };
+            }
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            yield RawTransaction {
+                currency: Some(usd),
+                ..RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 4, Some(1.0_f64))
+            };
+        });
+        let mut clients = processor.process_transactions(raw_transactions).await?;
+        clients.sort_by_key(|c| (c.id, c.currency));
+        let accounts = clients
+            .iter()
+            .map(|c| (c.id, c.currency, c.available_balance, c.locked))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            accounts,
+            vec![
+                (1, Some(eur), 0.0_f64, true),
+                (1, Some(usd), 1.13_f64, true),
+                (2, Some(eur), 1.13_f64, false),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_converts_funds_between_the_currencies_of_a_client() -> Result<()> {
+        let eur: Currency = "EUR".parse()?;
+        let usd: Currency = "USD".parse()?;
+        let mut rates = RateTable::default();
+        rates.insert(Rate {
+            from: eur,
+            to: usd,
+            rate: 1.1,
+            spread: 0.01,
+            effective_at: 0,
+        })?;
+        let mut processor = Processor::new(EngineConfig {
+            rates: Arc::new(rates),
+            record_outcomes: true,
+            ..EngineConfig::default()
+        });
+        let raw_transactions = RawTransactionStream::new(stream! {
+            yield RawTransaction {
+                currency: Some(eur),
+                ..RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(100.0_f64))
+            };
+            yield RawTransaction {
+                currency: Some(eur),
+                to_currency: Some(usd),
+                ..RawTransaction::new(RawTransactionVariant::Convert, 1, 2, Some(50.0_f64))
+            };
+            yield RawTransaction {
+                currency: Some(eur),
+                to_currency: Some(usd),
+                ..RawTransaction::new(RawTransactionVariant::Convert, 1, 3, Some(500.0_f64))
+            };
+        });
+        let mut clients = processor.process_transactions(raw_transactions).await?;
+        clients.sort_by_key(|c| c.currency);
+        assert_eq!(clients.len(), 2);
+        assert!((clients[0].total_balance - 50.0).abs() < f64::EPSILON);
+        assert!((clients[1].total_balance - 54.45).abs() < f64::EPSILON);
+        let conversions = clients[0].take_conversions();
+        assert_eq!(conversions.len(), 1);
+        assert!((conversions[0].fee - 0.55).abs() < 1e-9_f64);
+        assert!(conversions[0].remainder.abs() < 1e-9_f64);
+        // The outcomes of the client's accounts are numbered in the order they were recorded.
+        let mut outcomes = clients
+            .iter_mut()
+            .flat_map(Client::take_outcomes)
+            .collect::<Vec<_>>();
+        outcomes.sort_by_key(|o| o.seq);
+        let recorded = outcomes
+            .iter()
+            .map(|o| (o.tx_id, o.currency.as_deref(), o.outcome))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recorded,
+            vec![
+                (1, Some("EUR"), Outcome::Accepted),
+                (2, Some("EUR"), Outcome::Accepted),
+                (2, Some("USD"), Outcome::Accepted),
+                (3, Some("EUR"), Outcome::Failed),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_keeps_accepting_transactions_for_a_locked_client() -> Result<()> {
+        let mut processor = Processor::default();
+        let raw_transactions = RawTransactionStream::new(stream! {
+            yield RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000.0_f64));
+            yield RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
+            yield RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
+            for tx_id in 2..100 {
+                yield RawTransaction::new(RawTransactionVariant::Deposit, 1, tx_id, Some(1.0_f64));
+            }
+        });
+        let clients = processor.process_transactions(raw_transactions).await?;
+        assert_eq!(clients.len(), 1);
+        let client = &clients[0];
+        assert!(client.locked);
+        assert!(client.total_balance.abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_closes_the_period_of_every_client_without_losing_open_disputes() -> Result<()> {
+        let mut processor = Processor::new(EngineConfig {
+            record_statements: true,
+            ..EngineConfig::default()
+        });
+        let raw_transactions = RawTransactionStream::new(stream! {
+            for (variant, client_id, tx_id, amount) in [
+                (RawTransactionVariant::Deposit, 1, 1, Some(10.0_f64)),
+                (RawTransactionVariant::Deposit, 2, 2, Some(5.0_f64)),
+                (RawTransactionVariant::Dispute, 1, 1, None),
+                (RawTransactionVariant::Close, 0, 1, None),
+                (RawTransactionVariant::Resolve, 1, 1, None),
+            ] {
+                yield RawTransaction::new(variant, client_id, tx_id, amount);
+            }
+        });
+        let mut clients = processor.process_transactions(raw_transactions).await?;
+        clients.sort_by_key(|c| c.id);
+        let snapshots = clients
+            .iter_mut()
+            .flat_map(Client::take_period_closes)
+            .map(|close| (close.client, close.balances.held, close.balances.total))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            snapshots,
+            vec![(1, 10.0_f64, 10.0_f64), (2, 0.0_f64, 5.0_f64)]
+        );
+        let statements = clients[0].take_closed_statements();
+        assert_eq!(statements.len(), 1);
+        assert_eq!(statements[0].period, Some(1));
+        assert_eq!(statements[0].entries.len(), 2);
+        let open = clients[0]
+            .take_statement()
+            .ok_or_else(|| anyhow!("no statement"))?;
+        assert_eq!(open.opening, statements[0].closing);
+        assert_eq!(open.entries.len(), 1);
+        assert!((clients[0].available_balance - 10.0).abs() < f64::EPSILON);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_accrues_tiered_interest_that_cannot_be_disputed() -> Result<()> {
+        let mut processor = Processor::new(EngineConfig {
+            interest: Arc::new("0:0.0001,1000:0.00012345".parse()?),
+            ..EngineConfig::default()
+        });
+        let raw_transactions = RawTransactionStream::new(stream! {
+            for (variant, client_id, tx_id, amount, timestamp) in [
+                (RawTransactionVariant::Deposit, 1, 1, Some(1000.0_f64), 0),
+                (RawTransactionVariant::Deposit, 2, 2, Some(10.0_f64), 0),
+                (RawTransactionVariant::Deposit, 1, 3, Some(1000.0_f64), 50),
+                (RawTransactionVariant::Accrue, 0, 4, None, 100),
+                (RawTransactionVariant::Dispute, 1, 4, None, 100),
+            ] {
+                yield RawTransaction {
+                    timestamp: Some(timestamp),
+                    ..RawTransaction::new(variant, client_id, tx_id, amount)
+                };
+            }
+        });
+        let mut clients = processor.process_transactions(raw_transactions).await?;
+        clients.sort_by_key(|c| c.id);
+        let balances = clients
+            .iter()
+            .map(|c| (c.available_balance, c.held_balance))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            balances,
+            vec![(2000.1617_f64, 0.0_f64), (10.001_f64, 0.0_f64)]
+        );
+        let ledger = clients[0].ledger();
+        assert!((ledger.balance(LedgerAccount::InterestExpense) - 0.161_725).abs() < 1e-9_f64);
+        assert!((ledger.balance(LedgerAccount::InterestResidue) + 0.000_025).abs() < 1e-9_f64);
+        assert!(verify(&clients).is_empty());
+        Ok(())
+    }
 }
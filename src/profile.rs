@@ -0,0 +1,112 @@
+//! This module reads client profiles, which set per-client limits on withdrawals: an overdraft
+//! limit, a maximum single withdrawal, and a cap on the total withdrawn within a window.
+//!
+//! Profiles are read from a CSV file keyed by `client`, with the columns `overdraft_limit`,
+//! `max_withdrawal`, `withdrawal_cap` and `cap_window`.  Every column but `client` may be left
+//! empty, and clients without a profile have no limits beyond the engine's funds availability
+//! policy.
+
+use crate::window::Window;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::path::Path;
+use thiserror::Error;
+
+/// An error type for the profile module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ProfileError {
+    /// Triggered if the profile file cannot be read or a row cannot be parsed
+    #[error("Failed to read client profiles: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// Deserializer for an optional window, treating an empty field as no window.
+fn deserialize_window<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Window>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) if !text.trim().is_empty() => {
+            text.parse().map(Some).map_err(serde::de::Error::custom)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The limits that apply to a single client.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub struct ClientProfile {
+    /// The ID of the client
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    /// How far the available balance may go below zero.  Replaces the engine's funds availability
+    /// policy for this client.
+    #[serde(default)]
+    pub overdraft_limit: Option<f64>,
+    /// The largest single withdrawal allowed
+    #[serde(default)]
+    pub max_withdrawal: Option<f64>,
+    /// The most that may be withdrawn within `cap_window`
+    #[serde(default)]
+    pub withdrawal_cap: Option<f64>,
+    /// The window `withdrawal_cap` applies to.  Defaults to a calendar day.
+    #[serde(default, deserialize_with = "deserialize_window")]
+    pub cap_window: Option<Window>,
+}
+
+impl ClientProfile {
+    /// The window the withdrawal cap applies to.
+    #[inline]
+    #[must_use]
+    pub fn cap_window(&self) -> Window {
+        self.cap_window.unwrap_or(Window::Day)
+    }
+}
+
+/// Reads client profiles from a CSV file with a header row, keyed by client id.
+/// # Errors
+/// Returns an error if the file cannot be read or a row cannot be parsed.
+#[inline]
+pub fn read_profiles(path: &Path) -> Result<HashMap<u16, ClientProfile>, ProfileError> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)?;
+    let mut profiles = HashMap::new();
+    for profile in rdr.deserialize::<ClientProfile>() {
+        let profile = profile?;
+        profiles.insert(profile.client_id, profile);
+    }
+    Ok(profiles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_reads_client_profiles() -> Result<()> {
+        let profiles = read_profiles(Path::new("test_data/test_profiles.csv"))?;
+        assert_eq!(profiles.len(), 3);
+        assert_eq!(
+            profiles.get(&1),
+            Some(&ClientProfile {
+                client_id: 1,
+                overdraft_limit: Some(100.0_f64),
+                max_withdrawal: Some(500.0_f64),
+                withdrawal_cap: Some(1_000.0_f64),
+                cap_window: Some(Window::Day),
+            })
+        );
+        assert_eq!(
+            profiles.get(&2).map(|p| (p.withdrawal_cap, p.cap_window())),
+            Some((Some(250.0_f64), Window::Transactions(10)))
+        );
+        assert_eq!(
+            profiles.get(&3).map(ClientProfile::cap_window),
+            Some(Window::Day)
+        );
+        Ok(())
+    }
+}
@@ -1,18 +1,85 @@
 //! The purpose of this module is to read chunks of data from a file (or any other source) and parse
 //! them into `Transaction`s.  The parsed `Transaction`s should be made available as a stream for other modules
-//! to consume.
+//! to consume.  CSV, newline delimited JSON (NDJSON) and MT940 sources are supported.
 
+use crate::mt940::Mt940Parser;
 use crate::transaction::RawTransaction;
 use async_stream::stream;
+use csv::StringRecord;
 use futures_core::stream::Stream;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Formatter};
 use std::io;
 use std::path::Path;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::task::{Context, Poll};
+use thiserror::Error;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio_stream::wrappers::LinesStream;
 
+/// An error type for the reader module.
+#[derive(Debug, Error, PartialEq)]
+#[non_exhaustive]
+pub enum ReaderError {
+    /// Triggered if an input format name is not recognized
+    #[error("Unknown input format: {0}")]
+    UnknownFormat(String),
+}
+
+/// The formats that transactions can be read from.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Comma separated values, with or without a header row.
+    Csv,
+    /// Newline delimited JSON, with one transaction object per line.
+    Ndjson,
+    /// SWIFT MT940 customer statements.
+    Mt940,
+}
+
+impl InputFormat {
+    /// Determines the format of a file from its extension.  Files with a `.ndjson`, `.jsonl` or
+    /// `.json` extension are read as NDJSON, files with a `.sta`, `.mt940` or `.940` extension are
+    /// read as MT940, and anything else is read as CSV.
+    #[inline]
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(std::ffi::OsStr::to_str) {
+            Some(ext)
+                if ext.eq_ignore_ascii_case("ndjson")
+                    || ext.eq_ignore_ascii_case("jsonl")
+                    || ext.eq_ignore_ascii_case("json") =>
+            {
+                Self::Ndjson
+            }
+            Some(ext)
+                if ext.eq_ignore_ascii_case("sta")
+                    || ext.eq_ignore_ascii_case("mt940")
+                    || ext == "940" =>
+            {
+                Self::Mt940
+            }
+            _ => Self::Csv,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = ReaderError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "ndjson" | "jsonl" | "json" => Ok(Self::Ndjson),
+            "mt940" => Ok(Self::Mt940),
+            _ => Err(ReaderError::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
 /// A `Send` struct for a stream of `String`s.
 pub struct StringStream(Pin<Box<dyn Stream<Item = String> + Send>>);
 
@@ -87,23 +154,83 @@ pub async fn read_from_file(path: &Path) -> Result<StringStream, io::Error> {
 }
 
 /// Reads a chunk of data from an input stream and parses it into a stream of `Transaction`s.
+///
+/// Without a header row, columns are read in the order `type, client, tx, amount, timestamp,
+/// currency, to_currency, partner, original_tx`.  A header row naming a `type` column sets the
+/// order of the columns that follow it, so that optional columns can be given in any order; columns
+/// with unknown names are ignored.
 #[inline]
 pub async fn process_raw_data(source: StringStream) -> RawTransactionStream {
     RawTransactionStream(Box::pin(stream! {
+        let mut headers: Option<StringRecord> = None;
         for await data in source {
             let mut rdr = csv::ReaderBuilder::new()
                 .has_headers(false)
                 .from_reader(data.as_bytes());
-            let mut iter = rdr.deserialize::<RawTransaction>();
-            if let Some(transaction) = iter.next() {
-                if let Ok(t) = transaction {
-                    yield t;
-                }
+            let mut record = StringRecord::new();
+            if !matches!(rdr.read_record(&mut record), Ok(true)) {
+                continue;
+            }
+            // A header row is one that names a `type` column.
+            if headers.is_none()
+                && record.iter().any(|field| field.trim().eq_ignore_ascii_case("type"))
+            {
+                record.trim();
+                headers = Some(record);
+                continue;
+            }
+            if let Ok(t) = record.deserialize::<RawTransaction>(headers.as_ref()) {
+                yield t;
+            }
+        }
+    }))
+}
+
+/// Reads lines of NDJSON from an input stream and parses them into a stream of `Transaction`s.
+/// Lines which are blank or cannot be parsed are skipped.
+#[inline]
+pub async fn process_ndjson_data(source: StringStream) -> RawTransactionStream {
+    RawTransactionStream(Box::pin(stream! {
+        for await data in source {
+            if data.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<RawTransaction>(&data) {
+                Ok(t) => yield t,
+                Err(e) => eprintln!("Failed to parse JSON transaction: {e}"),
             }
         }
     }))
 }
 
+/// Reads lines of MT940 statements from an input stream and parses their statement lines into a
+/// stream of `Transaction`s.
+///
+/// If `client_override` is given every transaction is assigned to that client, otherwise the
+/// client of each statement's account identification is looked up in `accounts`.
+#[inline]
+pub async fn process_mt940_data(
+    source: StringStream,
+    accounts: BTreeMap<String, u16>,
+    client_override: Option<u16>,
+) -> RawTransactionStream {
+    RawTransactionStream(Box::pin(stream! {
+        let mut parser = Mt940Parser::new(accounts, client_override);
+        for await data in source {
+            match parser.parse_line(&data) {
+                Ok(Some(t)) => yield t,
+                Ok(None) => {}
+                Err(e) => eprintln!("Failed to parse MT940 line: {e}"),
+            }
+        }
+        match parser.finish() {
+            Ok(Some(t)) => yield t,
+            Ok(None) => {}
+            Err(e) => eprintln!("Failed to parse MT940 line: {e}"),
+        }
+    }))
+}
+
 /// Reads a chunk of data from an input file and parses it into a stream of `Transaction`s.
 /// # Errors
 /// Returns an error if the file cannot be read
@@ -113,9 +240,52 @@ pub async fn read_transactions_from_file(path: &Path) -> Result<RawTransactionSt
     Ok(process_raw_data(raw_stream).await)
 }
 
+/// Reads an NDJSON input file and parses it into a stream of `Transaction`s.
+/// # Errors
+/// Returns an error if the file cannot be read
+#[inline]
+pub async fn read_ndjson_transactions_from_file(
+    path: &Path,
+) -> Result<RawTransactionStream, io::Error> {
+    let raw_stream = read_from_file(path).await?;
+    Ok(process_ndjson_data(raw_stream).await)
+}
+
+/// Reads an MT940 input file and parses it into a stream of `Transaction`s.
+/// # Errors
+/// Returns an error if the file cannot be read
+#[inline]
+pub async fn read_mt940_transactions_from_file(
+    path: &Path,
+    accounts: BTreeMap<String, u16>,
+    client_override: Option<u16>,
+) -> Result<RawTransactionStream, io::Error> {
+    let raw_stream = read_from_file(path).await?;
+    Ok(process_mt940_data(raw_stream, accounts, client_override).await)
+}
+
+/// Reads an input file in the given format and parses it into a stream of `Transaction`s.
+///
+/// MT940 files are read with no account mapped to a client, so use
+/// `read_mt940_transactions_from_file` to give the mapping.
+/// # Errors
+/// Returns an error if the file cannot be read
+#[inline]
+pub async fn read_transactions_from_file_as(
+    path: &Path,
+    format: InputFormat,
+) -> Result<RawTransactionStream, io::Error> {
+    match format {
+        InputFormat::Csv => read_transactions_from_file(path).await,
+        InputFormat::Ndjson => read_ndjson_transactions_from_file(path).await,
+        InputFormat::Mt940 => read_mt940_transactions_from_file(path, BTreeMap::new(), None).await,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mt940::read_accounts;
     use crate::transaction::RawTransactionVariant;
     use anyhow::Result;
     use tokio_stream::StreamExt;
@@ -154,67 +324,37 @@ mod tests {
                 0 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 1,
-                            variant: RawTransactionVariant::Deposit,
-                            amount: Some(1000_f64)
-                        }
+                        RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000_f64))
                     );
                 }
                 1 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 2,
-                            variant: RawTransactionVariant::Withdrawal,
-                            amount: Some(500_f64)
-                        }
+                        RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500_f64))
                     );
                 }
                 2 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 1,
-                            variant: RawTransactionVariant::Dispute,
-                            amount: None
-                        }
+                        RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None)
                     );
                 }
                 3 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 2,
-                            variant: RawTransactionVariant::Dispute,
-                            amount: None
-                        }
+                        RawTransaction::new(RawTransactionVariant::Dispute, 1, 2, None)
                     );
                 }
                 4 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 1,
-                            variant: RawTransactionVariant::Resolve,
-                            amount: None
-                        }
+                        RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None)
                     );
                 }
                 5 => {
                     assert_eq!(
                         transaction,
-                        RawTransaction {
-                            client_id: 1,
-                            tx_id: 2,
-                            variant: RawTransactionVariant::Resolve,
-                            amount: None
-                        }
+                        RawTransaction::new(RawTransactionVariant::Resolve, 1, 2, None)
                     );
                 }
                 _ => panic!("Too many transactions"),
@@ -224,4 +364,135 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn it_reads_csv_columns_in_the_order_given_by_the_header() -> Result<()> {
+        let source = StringStream::new(stream! {
+            yield "timestamp,tx,type,client,amount,note".to_owned();
+            yield "1650000000,1,deposit,1,10.5,first".to_owned();
+            yield ",2,withdrawal,1,2.0,".to_owned();
+        });
+        let transactions = process_raw_data(source).await.collect::<Vec<_>>().await;
+        assert_eq!(
+            transactions,
+            vec![
+                RawTransaction {
+                    variant: RawTransactionVariant::Deposit,
+                    client_id: 1,
+                    tx_id: 1,
+                    amount: Some(10.5_f64),
+                    timestamp: Some(1_650_000_000),
+                    currency: None,
+                    to_currency: None,
+                    partner: None,
+                    original_tx: None,
+                },
+                RawTransaction {
+                    variant: RawTransactionVariant::Withdrawal,
+                    client_id: 1,
+                    tx_id: 2,
+                    amount: Some(2.0_f64),
+                    timestamp: None,
+                    currency: None,
+                    to_currency: None,
+                    partner: None,
+                    original_tx: None,
+                },
+            ]
+        );
+
+        let source = StringStream::new(stream! {
+            yield "deposit,1,1,10.5,1650000000".to_owned();
+        });
+        let transactions = process_raw_data(source).await.collect::<Vec<_>>().await;
+        assert_eq!(
+            transactions.first().and_then(|t| t.timestamp),
+            Some(1_650_000_000)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_ndjson_from_file() -> Result<()> {
+        let path = Path::new("test_data/test_data.ndjson");
+        let transactions = read_ndjson_transactions_from_file(path)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            transactions,
+            vec![
+                RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1000_f64)),
+                RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 2, Some(500.25_f64)),
+                RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None),
+                RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_reads_mt940_from_file() -> Result<()> {
+        let path = Path::new("test_data/test_data.sta");
+        let accounts = read_accounts(Path::new("test_data/test_mt940_accounts.csv"))?;
+        let transactions = read_mt940_transactions_from_file(path, accounts, None)
+            .await?
+            .collect::<Vec<_>>()
+            .await;
+        let eur = Some("EUR".parse()?);
+        assert_eq!(
+            transactions,
+            vec![
+                RawTransaction {
+                    currency: eur,
+                    ..RawTransaction::new(RawTransactionVariant::Deposit, 1, 1001, Some(1000_f64))
+                },
+                RawTransaction {
+                    currency: eur,
+                    ..RawTransaction::new(
+                        RawTransactionVariant::Withdrawal,
+                        1,
+                        1002,
+                        Some(250.5_f64)
+                    )
+                },
+                RawTransaction {
+                    currency: eur,
+                    ..RawTransaction::new(RawTransactionVariant::Deposit, 2, 2001, Some(75_f64))
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_determines_the_input_format_from_the_file_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("data.ndjson")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("data.JSONL")),
+            InputFormat::Ndjson
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("data.csv")),
+            InputFormat::Csv
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("statement.sta")),
+            InputFormat::Mt940
+        );
+        assert_eq!(InputFormat::from_path(Path::new("data")), InputFormat::Csv);
+    }
+
+    #[test]
+    fn it_parses_input_format_names() {
+        assert_eq!("NDJSON".parse(), Ok(InputFormat::Ndjson));
+        assert_eq!("csv".parse(), Ok(InputFormat::Csv));
+        assert_eq!(
+            "xml".parse::<InputFormat>(),
+            Err(ReaderError::UnknownFormat("xml".to_owned()))
+        );
+    }
 }
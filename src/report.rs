@@ -0,0 +1,368 @@
+//! This module writes the final report of client states.
+//!
+//! The report can be written as CSV, as a single JSON array, or as newline delimited JSON
+//! (NDJSON).  Balances are always written as strings truncated to 4 decimal places, so that every
+//! format carries exactly the same values.
+//!
+//! Clients with balances in more than one currency get one row per currency.  Once any transaction
+//! names a currency the report gains a `currency` column, and the balances of each row are written
+//! with exactly the decimal places of the minor unit of its currency.
+//!
+//! The balances of a client's sub-accounts roll up into its held balance.  When sub-accounts are
+//! listed the report gains a `sub_account` column, and each client row is followed by a row per
+//! sub-account, with its balance held.
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::transaction::truncate_to_decimal_places;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the report module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum ReportError {
+    /// Triggered if an output format name is not recognized
+    #[error("Unknown output format: {0}")]
+    UnknownFormat(String),
+    /// Triggered if a CSV record cannot be written
+    #[error("Failed to write CSV report: {0}")]
+    Csv(#[from] csv::Error),
+    /// Triggered if a JSON record cannot be written
+    #[error("Failed to write JSON report: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Triggered if the output cannot be written to
+    #[error("Failed to write report: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The formats that the client report can be written in.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// A single JSON array of client objects.
+    Json,
+    /// Newline delimited JSON, with one client object per line.
+    Ndjson,
+}
+
+impl OutputFormat {
+    /// Determines the output format from the extension of a file, defaulting to CSV.
+    #[inline]
+    #[must_use]
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(std::ffi::OsStr::to_str) {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            Some(ext)
+                if ext.eq_ignore_ascii_case("ndjson") || ext.eq_ignore_ascii_case("jsonl") =>
+            {
+                Self::Ndjson
+            }
+            _ => Self::Csv,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ReportError;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            _ => Err(ReportError::UnknownFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Formats a balance the same way the CSV report does: truncated to 4 decimal places and written
+/// in its shortest round-trip representation.
+#[inline]
+#[must_use]
+pub fn format_amount(value: f64) -> String {
+    ryu::Buffer::new()
+        .format(truncate_to_decimal_places(value, 4))
+        .to_owned()
+}
+
+/// Formats a balance in a currency with exactly the decimal places of its minor unit, or as
+/// `format_amount` does if it has no currency.
+#[inline]
+#[must_use]
+pub fn format_in(currency: Option<Currency>, value: f64) -> String {
+    currency.map_or_else(|| format_amount(value), |c| c.format(value))
+}
+
+/// A single row of the client report.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[non_exhaustive]
+pub struct ClientReport {
+    /// The id of the client.
+    pub client: u16,
+    /// The currency of the balances, if any transaction named a currency.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// The sub-account the row is for, if sub-accounts are listed, or empty for the client itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub_account: Option<String>,
+    /// The available balance of the client.
+    pub available: String,
+    /// The held balance of the client.
+    pub held: String,
+    /// The total balance of the client.
+    pub total: String,
+    /// Whether or not the client is locked.
+    pub locked: bool,
+    /// The debt the client still owes, if the shortfall policy carries debt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt: Option<String>,
+    /// The debt the client has repaid, if the shortfall policy carries debt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debt_repaid: Option<String>,
+    /// Whether a velocity rule has flagged the client, if there are velocity rules.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flagged: Option<bool>,
+    /// The risk score of the client, if it is reported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub risk_score: Option<String>,
+}
+
+impl From<&Client> for ClientReport {
+    #[inline]
+    fn from(client: &Client) -> Self {
+        let format = |value| format_in(client.currency, value);
+        Self {
+            client: client.id,
+            currency: client.currency.map(|c| c.to_string()),
+            sub_account: None,
+            available: format(client.available_balance),
+            held: format(client.held_balance),
+            total: format(client.total_balance),
+            locked: client.locked,
+            debt: client.debt().map(|d| format(d.outstanding())),
+            debt_repaid: client.debt().map(|d| format(d.repaid())),
+            flagged: client.flagged(),
+            risk_score: client.risk_score().map(format_amount),
+        }
+    }
+}
+
+impl ClientReport {
+    /// The rows of a client's sub-accounts, if the report lists them, to follow this row of the
+    /// client's own.
+    fn sub_account_rows(&self, client: &Client) -> Vec<Self> {
+        let format = |value| format_in(client.currency, value);
+        let blank = |column: &Option<String>| column.as_ref().map(|_| String::new());
+        client
+            .listed_sub_accounts()
+            .into_iter()
+            .flatten()
+            .map(|(name, &balance)| Self {
+                sub_account: Some(name.clone()),
+                available: format(0.0_f64),
+                held: format(balance),
+                total: format(balance),
+                debt: blank(&self.debt),
+                debt_repaid: blank(&self.debt_repaid),
+                risk_score: blank(&self.risk_score),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+/// Writes the report of the given `Client`s to `writer` in the given format.
+/// # Errors
+/// Returns an error if the report cannot be serialized or written.
+#[inline]
+pub fn write_report<W: Write>(
+    clients: &[Client],
+    format: OutputFormat,
+    mut writer: W,
+) -> Result<(), ReportError> {
+    let with_currency = clients.iter().any(|c| c.currency.is_some());
+    let with_sub_accounts = clients.iter().any(|c| c.listed_sub_accounts().is_some());
+    let rows = clients.iter().flat_map(|client| {
+        let mut row = ClientReport::from(client);
+        if with_currency {
+            row.currency.get_or_insert_with(String::new);
+        }
+        if with_sub_accounts {
+            row.sub_account.get_or_insert_with(String::new);
+        }
+        let sub_accounts = row.sub_account_rows(client);
+        std::iter::once(row).chain(sub_accounts)
+    });
+    match format {
+        OutputFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                csv_writer.serialize(row)?;
+            }
+            csv_writer.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &rows.collect::<Vec<_>>())?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        OutputFormat::Ndjson => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::EngineConfig;
+    use crate::risk::RiskWeights;
+    use crate::shortfall::AllowNegative;
+    use anyhow::Result;
+    use std::sync::Arc;
+
+    fn clients() -> Vec<Client> {
+        let mut client = Client::new(1);
+        client.available_balance = 1.234_56_f64;
+        client.held_balance = 2.0_f64;
+        client.total_balance = 3.234_56_f64;
+        let mut locked = Client::new(2);
+        locked.locked = true;
+        vec![client, locked]
+    }
+
+    #[test]
+    fn it_writes_the_report_as_csv() -> Result<()> {
+        let mut output = vec![];
+        write_report(&clients(), OutputFormat::Csv, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,available,held,total,locked
+1,1.2345,2.0,3.2345,false
+2,0.0,0.0,0.0,true
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_debt_columns_when_debt_is_carried() -> Result<()> {
+        let config = EngineConfig {
+            shortfall_policy: Arc::new(AllowNegative),
+            ..EngineConfig::default()
+        };
+        let mut client = Client::with_config(1, &config);
+        client.total_balance = -5.0_f64;
+        let mut output = vec![];
+        write_report(&[client], OutputFormat::Csv, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,available,held,total,locked,debt,debt_repaid
+1,0.0,0.0,-5.0,false,0.0,0.0
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_risk_score_column_when_weights_are_given() -> Result<()> {
+        let config = EngineConfig {
+            risk_weights: Some(RiskWeights::default()),
+            ..EngineConfig::default()
+        };
+        let mut output = vec![];
+        write_report(
+            &[Client::with_config(1, &config)],
+            OutputFormat::Csv,
+            &mut output,
+        )?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,available,held,total,locked,risk_score
+1,0.0,0.0,0.0,false,0.0
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_a_row_per_currency_with_its_minor_units() -> Result<()> {
+        let config = EngineConfig::default();
+        let mut eur = Client::with_currency(1, Some("EUR".parse()?), &config);
+        eur.available_balance = 10.5_f64;
+        eur.total_balance = 10.5_f64;
+        let mut jpy = Client::with_currency(1, Some("JPY".parse()?), &config);
+        jpy.available_balance = 1200.0_f64;
+        jpy.total_balance = 1200.0_f64;
+        let mut output = vec![];
+        write_report(&[Client::new(2), eur, jpy], OutputFormat::Csv, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+client,currency,available,held,total,locked
+2,,0.0,0.0,0.0,false
+1,EUR,10.50,0.00,10.50,false
+1,JPY,1200,0,1200,false
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_report_as_ndjson_with_string_amounts() -> Result<()> {
+        let mut output = vec![];
+        write_report(&clients(), OutputFormat::Ndjson, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            r#"{"client":1,"available":"1.2345","held":"2.0","total":"3.2345","locked":false}
+{"client":2,"available":"0.0","held":"0.0","total":"0.0","locked":true}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_the_report_as_a_json_array() -> Result<()> {
+        let mut output = vec![];
+        write_report(&clients(), OutputFormat::Json, &mut output)?;
+        let parsed: serde_json::Value = serde_json::from_slice(&output)?;
+        assert_eq!(
+            parsed,
+            serde_json::json!([
+                {"client": 1_u16, "available": "1.2345", "held": "2.0", "total": "3.2345", "locked": false},
+                {"client": 2_u16, "available": "0.0", "held": "0.0", "total": "0.0", "locked": true}
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn it_formats_amounts_like_the_client_csv_serializer() -> Result<()> {
+        let mut clients = clients();
+        clients[1].available_balance = 1_234_907_120_394_879_870_134.1_f64;
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for client in &clients {
+            writer.serialize(client)?;
+        }
+        let expected = String::from_utf8(writer.into_inner()?)?;
+        let mut output = vec![];
+        write_report(&clients, OutputFormat::Csv, &mut output)?;
+        assert_eq!(String::from_utf8(output)?, expected);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+//! This module holds the `ReservePolicy` of the rolling reserve, a sub-account of each client that
+//! keeps back a share of every deposit for a while.
+//!
+//! A `Client` may keep named sub-accounts alongside its main balances.  The funds in a sub-account
+//! are part of the client's total balance but are not available, so they roll up into the held
+//! balance of the client's report.  With a reserve policy, every deposit credited to the available
+//! balance has a percentage of its amount moved into the `reserve` sub-account, and each of those
+//! amounts is released back to the available balance once its deposit leaves the policy's window.
+//! Releases happen as later transactions move the client's clock, and are recorded as
+//! `reserve_release` transactions.  A time window needs transaction timestamps to pass, so without
+//! them only a window counted in transactions ever releases the reserve.
+//!
+//! Disputing a deposit draws its share out of the reserve and into the dispute's hold.  A chargeback
+//! takes the share with the rest of the deposit, while a resolve returns it to the reserve if its
+//! deposit is still inside the window.
+
+use crate::window::Window;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The name of the rolling reserve sub-account.
+pub const RESERVE: &str = "reserve";
+
+/// An error type for the reserve module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReserveError {
+    /// Triggered if a reserve policy cannot be parsed
+    #[error("Invalid reserve policy: {0}")]
+    InvalidPolicy(String),
+}
+
+/// How much of each deposit the rolling reserve keeps back, and for how long.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReservePolicy {
+    /// The percentage of each deposit kept back
+    pub percent: f64,
+    /// How long each amount is kept back, counted from its deposit
+    pub hold_for: Window,
+}
+
+impl ReservePolicy {
+    /// Creates a new `ReservePolicy`
+    #[inline]
+    #[must_use]
+    pub const fn new(percent: f64, hold_for: Window) -> Self {
+        Self { percent, hold_for }
+    }
+
+    /// The amount kept back from a deposit, before rounding.
+    #[inline]
+    #[must_use]
+    pub fn reserved(self, deposit: f64) -> f64 {
+        deposit * self.percent / 100.0_f64
+    }
+}
+
+impl FromStr for ReservePolicy {
+    type Err = ReserveError;
+
+    /// Parses `<percent>:<window>`, e.g. `10:90d` to keep back 10% of each deposit for 90 days.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ReserveError::InvalidPolicy(s.to_owned());
+        let (percent, window) = s.split_once(':').ok_or_else(invalid)?;
+        let percent = match percent.trim().parse::<f64>() {
+            Ok(p) if (0.0_f64..=100.0_f64).contains(&p) => p,
+            _ => return Err(invalid()),
+        };
+        let hold_for = window.parse().ok().ok_or_else(invalid)?;
+        Ok(Self::new(percent, hold_for))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_the_share_kept_back_and_for_how_long() {
+        assert_eq!(
+            "10:90d".parse(),
+            Ok(ReservePolicy::new(10.0, Window::Seconds(7_776_000)))
+        );
+        assert!((ReservePolicy::new(2.5, Window::Day).reserved(200.0) - 5.0).abs() < f64::EPSILON);
+        assert_eq!(
+            "120:1d".parse::<ReservePolicy>(),
+            Err(ReserveError::InvalidPolicy("120:1d".to_owned()))
+        );
+        assert!("10".parse::<ReservePolicy>().is_err());
+    }
+}
@@ -0,0 +1,399 @@
+//! This module computes a risk score for each client from its dispute ratio, chargebacks,
+//! withdrawals reversed by a chargeback and rule hits, weighted by configurable `RiskWeights`.
+//!
+//! The score is a rolling one: each signal is recorded in a `RiskLog` with the transaction it came
+//! from, and only the signals inside the weights' `Window` count, so old activity drops out of the
+//! score.
+
+use crate::outcome::{Note, Reason};
+use crate::transaction::{RawTransactionVariant, TransactionKind};
+use crate::window::Window;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The number of transactions the risk score looks back over by default.
+const DEFAULT_WINDOW: u64 = 1000;
+
+/// An error type for the risk module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RiskError {
+    /// Triggered if the risk weights cannot be parsed
+    #[error("Invalid risk weights: {0}")]
+    InvalidWeights(String),
+}
+
+/// The weight of each signal in the risk score.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RiskWeights {
+    /// The weight of the share of deposits and withdrawals that were disputed
+    pub dispute_ratio: f64,
+    /// The weight of each chargeback
+    pub chargeback: f64,
+    /// The weight of each withdrawal reversed by a chargeback
+    pub reversed_withdrawal: f64,
+    /// The weight of each transaction a rule flagged, held or rejected
+    pub rule_hit: f64,
+    /// The window of recent activity the score is computed over
+    pub window: Window,
+}
+
+impl Default for RiskWeights {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            dispute_ratio: 50.0,
+            chargeback: 25.0,
+            reversed_withdrawal: 10.0,
+            rule_hit: 5.0,
+            window: Window::Transactions(DEFAULT_WINDOW),
+        }
+    }
+}
+
+impl FromStr for RiskWeights {
+    type Err = RiskError;
+
+    /// Parses `default`, or a comma separated list of `<signal>=<weight>` overriding the default
+    /// weights, where the signals are `dispute_ratio`, `chargeback`, `reversed_withdrawal` and
+    /// `rule_hit`.  The list may also give `window=<window>` to override the default window of the
+    /// last 1000 transactions.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || RiskError::InvalidWeights(s.to_owned());
+        let mut weights = Self::default();
+        let lower = s.trim().to_ascii_lowercase();
+        if lower == "default" {
+            return Ok(weights);
+        }
+        for pair in lower.split(',') {
+            let (signal, weight) = pair.split_once('=').ok_or_else(invalid)?;
+            if signal.trim() == "window" {
+                weights.window = weight.parse().ok().ok_or_else(invalid)?;
+                continue;
+            }
+            let weight = match weight.trim().parse::<f64>() {
+                Ok(w) if w.is_finite() && w >= 0_f64 => w,
+                _ => return Err(invalid()),
+            };
+            match signal.trim() {
+                "dispute_ratio" => weights.dispute_ratio = weight,
+                "chargeback" => weights.chargeback = weight,
+                "reversed_withdrawal" => weights.reversed_withdrawal = weight,
+                "rule_hit" => weights.rule_hit = weight,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(weights)
+    }
+}
+
+/// The counts of a client's activity that the risk score is computed from.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RiskCounters {
+    /// The number of deposits and withdrawals applied
+    pub transactions: u32,
+    /// The number of disputes opened
+    pub disputes: u32,
+    /// The number of chargebacks
+    pub chargebacks: u32,
+    /// The number of withdrawals reversed by a chargeback
+    pub reversed_withdrawals: u32,
+    /// The number of transactions a rule flagged, held or rejected
+    pub rule_hits: u32,
+}
+
+/// A signal counted towards the risk score.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Signal {
+    /// An applied deposit or withdrawal
+    Transaction,
+    /// An opened dispute
+    Dispute,
+    /// A chargeback
+    Chargeback,
+    /// A withdrawal reversed by a chargeback
+    ReversedWithdrawal,
+    /// A transaction a rule flagged, held or rejected
+    RuleHit,
+}
+
+/// The signals in the result of processing a transaction: a rule hit, and what was counted.
+fn signals(kind: TransactionKind, result: &Result<Vec<Note>, Reason>) -> [Option<Signal>; 2] {
+    let rule_hit = match result {
+        Ok(notes) => notes
+            .iter()
+            .any(|note| matches!(note, Note::FlaggedByRule | Note::HeldByRule)),
+        Err(Reason::ReversedByChargeback) => return [None, Some(Signal::ReversedWithdrawal)],
+        Err(reason) => matches!(*reason, Reason::HeldByRule | Reason::RejectedByRule),
+    }
+    .then_some(Signal::RuleHit);
+    if result.is_err() {
+        return [rule_hit, None];
+    }
+    let counted = match kind {
+        TransactionKind::Raw(
+            RawTransactionVariant::Deposit
+            | RawTransactionVariant::Withdrawal
+            | RawTransactionVariant::Capture,
+        ) => Signal::Transaction,
+        TransactionKind::Raw(RawTransactionVariant::Dispute) => Signal::Dispute,
+        TransactionKind::Raw(RawTransactionVariant::Chargeback) => Signal::Chargeback,
+        TransactionKind::Raw(
+            RawTransactionVariant::Resolve
+            | RawTransactionVariant::Authorize
+            | RawTransactionVariant::Void
+            | RawTransactionVariant::Convert
+            | RawTransactionVariant::Close
+            | RawTransactionVariant::Accrue
+            | RawTransactionVariant::Refund,
+        )
+        | TransactionKind::Internal(_) => return [rule_hit, None],
+    };
+    [rule_hit, Some(counted)]
+}
+
+impl RiskCounters {
+    /// Counts the result of processing a transaction.
+    #[inline]
+    pub fn record(&mut self, kind: TransactionKind, result: &Result<Vec<Note>, Reason>) {
+        for signal in signals(kind, result).into_iter().flatten() {
+            self.add(signal);
+        }
+    }
+
+    /// Counts a single signal.
+    const fn add(&mut self, signal: Signal) {
+        let counter = match signal {
+            Signal::Transaction => &mut self.transactions,
+            Signal::Dispute => &mut self.disputes,
+            Signal::Chargeback => &mut self.chargebacks,
+            Signal::ReversedWithdrawal => &mut self.reversed_withdrawals,
+            Signal::RuleHit => &mut self.rule_hits,
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    /// The share of deposits and withdrawals that were disputed.
+    #[inline]
+    #[must_use]
+    pub fn dispute_ratio(&self) -> f64 {
+        if self.transactions == 0 {
+            return 0.0;
+        }
+        f64::from(self.disputes) / f64::from(self.transactions)
+    }
+
+    /// The risk score of the counted activity under the given weights.
+    #[inline]
+    #[must_use]
+    pub fn score(&self, weights: &RiskWeights) -> f64 {
+        let disputes = weights.dispute_ratio * self.dispute_ratio();
+        let chargebacks = weights
+            .chargeback
+            .mul_add(f64::from(self.chargebacks), disputes);
+        let reversals = weights
+            .reversed_withdrawal
+            .mul_add(f64::from(self.reversed_withdrawals), chargebacks);
+        weights
+            .rule_hit
+            .mul_add(f64::from(self.rule_hits), reversals)
+    }
+}
+
+/// A signal, with the transaction it came from.
+#[derive(Debug, Clone, Copy)]
+struct RiskEvent {
+    /// The number of the transaction among those processed for the client
+    seq: u64,
+    /// When the transaction happened, if known
+    at: Option<u64>,
+    /// The signal
+    signal: Signal,
+}
+
+/// The recent risk signals of a client, bounded to a number of entries like the `ActivityLog`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct RiskLog {
+    /// The most recent signals, oldest first
+    events: VecDeque<RiskEvent>,
+    /// The number of signals kept
+    capacity: usize,
+}
+
+impl RiskLog {
+    /// Creates a new `RiskLog` that keeps the last `capacity` signals.
+    #[inline]
+    #[must_use]
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Records the signals in the result of processing the transaction numbered `seq` at time
+    /// `at`, forgetting the oldest signals if the log is full.
+    #[inline]
+    pub fn record(
+        &mut self,
+        kind: TransactionKind,
+        result: &Result<Vec<Note>, Reason>,
+        seq: u64,
+        at: Option<u64>,
+    ) {
+        for signal in signals(kind, result).into_iter().flatten() {
+            if self.events.len() >= self.capacity {
+                self.events.pop_front();
+            }
+            self.events.push_back(RiskEvent { seq, at, signal });
+        }
+    }
+
+    /// Counts the signals inside `window`, for the transaction numbered `now_seq` at time `now_at`.
+    #[inline]
+    #[must_use]
+    pub fn counters(&self, window: Window, now_seq: u64, now_at: Option<u64>) -> RiskCounters {
+        let mut counters = RiskCounters::default();
+        for event in &self.events {
+            if window.contains(now_seq, now_at, event.seq, event.at) {
+                counters.add(event.signal);
+            }
+        }
+        counters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_scores_the_counted_activity() {
+        let mut counters = RiskCounters::default();
+        for (variant, result) in [
+            (RawTransactionVariant::Deposit, Ok(vec![])),
+            (RawTransactionVariant::Deposit, Ok(vec![Note::HeldByRule])),
+            (RawTransactionVariant::Withdrawal, Ok(vec![])),
+            (
+                RawTransactionVariant::Withdrawal,
+                Err(Reason::InsufficientFunds),
+            ),
+            (
+                RawTransactionVariant::Withdrawal,
+                Err(Reason::RejectedByRule),
+            ),
+            (RawTransactionVariant::Dispute, Ok(vec![])),
+            (
+                RawTransactionVariant::Dispute,
+                Err(Reason::UnknownTransaction),
+            ),
+            (RawTransactionVariant::Chargeback, Ok(vec![])),
+            (
+                RawTransactionVariant::Withdrawal,
+                Err(Reason::ReversedByChargeback),
+            ),
+        ] {
+            counters.record(variant.into(), &result);
+        }
+        assert_eq!(
+            counters,
+            RiskCounters {
+                transactions: 3,
+                disputes: 1,
+                chargebacks: 1,
+                reversed_withdrawals: 1,
+                rule_hits: 2,
+            }
+        );
+        // 50 / 3 + 25 + 10 + 2 * 5
+        let score = counters.score(&RiskWeights::default());
+        assert!((score - (50.0 / 3.0 + 45.0)).abs() < 1e-9_f64);
+        assert!(RiskCounters::default().score(&RiskWeights::default()).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn it_drops_old_signals_out_of_the_score() {
+        let mut log = RiskLog::new(100);
+        log.record(
+            RawTransactionVariant::Deposit.into(),
+            &Ok(vec![]),
+            1,
+            Some(0),
+        );
+        log.record(
+            RawTransactionVariant::Dispute.into(),
+            &Ok(vec![]),
+            2,
+            Some(10),
+        );
+        log.record(
+            RawTransactionVariant::Chargeback.into(),
+            &Ok(vec![]),
+            3,
+            Some(20),
+        );
+        for seq in 4..=6 {
+            log.record(
+                RawTransactionVariant::Deposit.into(),
+                &Ok(vec![]),
+                seq,
+                Some(seq * 100),
+            );
+        }
+        let all = log.counters(Window::Transactions(6), 6, Some(600));
+        assert_eq!((all.transactions, all.disputes, all.chargebacks), (4, 1, 1));
+        let recent = log.counters(Window::Transactions(3), 6, Some(600));
+        assert_eq!(
+            recent,
+            RiskCounters {
+                transactions: 3,
+                ..RiskCounters::default()
+            }
+        );
+        assert!(recent.score(&RiskWeights::default()).abs() < f64::EPSILON);
+        let timed = log.counters(Window::Seconds(590), 6, Some(600));
+        assert_eq!((timed.transactions, timed.chargebacks), (3, 1));
+
+        let mut full = RiskLog::new(2);
+        for seq in 1..=3 {
+            full.record(
+                RawTransactionVariant::Chargeback.into(),
+                &Ok(vec![]),
+                seq,
+                None,
+            );
+        }
+        assert_eq!(full.counters(Window::Dispute, 3, None).chargebacks, 2);
+    }
+
+    #[test]
+    fn it_parses_risk_weights() {
+        assert_eq!("default".parse(), Ok(RiskWeights::default()));
+        assert_eq!(
+            "chargeback=40, rule_hit=0".parse(),
+            Ok(RiskWeights {
+                chargeback: 40.0,
+                rule_hit: 0.0,
+                ..RiskWeights::default()
+            })
+        );
+        assert_eq!(
+            "velocity=1".parse::<RiskWeights>(),
+            Err(RiskError::InvalidWeights("velocity=1".to_owned()))
+        );
+        assert!("chargeback=-1".parse::<RiskWeights>().is_err());
+        assert_eq!(
+            "window=day".parse(),
+            Ok(RiskWeights {
+                window: Window::Day,
+                ..RiskWeights::default()
+            })
+        );
+        assert!("window=week".parse::<RiskWeights>().is_err());
+    }
+}
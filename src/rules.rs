@@ -0,0 +1,290 @@
+//! This module holds the `Rule` trait, which the `Client` evaluates before applying each deposit
+//! and withdrawal, along with the built-in rules and the loader for rule configuration files.
+//!
+//! A rule gets a read-only `ClientView` of the client's pending balances, its processed
+//! transactions and its recent activity, and returns a `Verdict`.  When several rules match, the
+//! strictest verdict wins.
+//!
+//! Rules are configured as a JSON array of objects tagged by `rule`, for example
+//! `{"rule": "large_amount", "type": "withdrawal", "min_amount": 5000, "verdict": "hold"}`, so that
+//! they can be changed without a new release of the engine.
+
+use crate::duplicate::{AmountIndex, DuplicateDeposit};
+use crate::statement::BalanceSnapshot;
+use crate::transaction::{RawTransactionVariant, Transaction};
+use crate::velocity::VelocityRule;
+use crate::window::ActivityLog;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use thiserror::Error;
+
+/// An error type for the rules module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum RuleError {
+    /// Triggered if the rule file cannot be opened
+    #[error("Failed to open rule file: {0}")]
+    Io(#[from] io::Error),
+    /// Triggered if the rule file is not a valid list of rules
+    #[error("Failed to read rules: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What happens to a transaction after the rules have been evaluated, from the most to the least
+/// permissive.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// The transaction is applied as usual.
+    Allow,
+    /// The transaction is applied, and the client is flagged for review.
+    Flag,
+    /// The transaction is held for review: a deposit is credited to the held balance until it is
+    /// resolved or disputed, and a withdrawal is not made.  The client is flagged.
+    Hold,
+    /// The transaction is rejected, and the client is flagged.
+    Reject,
+}
+
+/// A read-only view of a client, as seen by the rules.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientView<'client> {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The client's balances before the transaction
+    pub balances: BalanceSnapshot,
+    /// The client's transactions still in the dispute window
+    pub processed_transactions: &'client HashMap<u32, Transaction>,
+    /// The client's recent deposits and withdrawals
+    pub activity: &'client ActivityLog,
+    /// The client's deposits still in the dispute window, indexed by amount
+    pub deposits_by_amount: &'client AmountIndex,
+    /// The number of the transaction among those processed for the client
+    pub sequence: u64,
+    /// The time of the transaction, or of the latest timestamped transaction before it
+    pub clock: Option<u64>,
+}
+
+/// The deposit or withdrawal the rules are evaluated for.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PendingTransaction {
+    /// The ID of the transaction
+    pub tx_id: u32,
+    /// The type of the transaction
+    pub variant: RawTransactionVariant,
+    /// The amount of the transaction
+    pub amount: f64,
+}
+
+impl PendingTransaction {
+    /// Creates a new `PendingTransaction`.
+    #[inline]
+    #[must_use]
+    pub const fn new(tx_id: u32, variant: RawTransactionVariant, amount: f64) -> Self {
+        Self {
+            tx_id,
+            variant,
+            amount,
+        }
+    }
+}
+
+/// A check made on every deposit and withdrawal before it is applied.
+pub trait Rule: Debug + Send + Sync {
+    /// A short name for the rule, used when reporting that it matched.
+    fn name(&self) -> &'static str;
+
+    /// Decides what happens to `transaction`, given the state of the client.
+    fn evaluate(&self, client: &ClientView<'_>, transaction: &PendingTransaction) -> Verdict;
+}
+
+/// Matches single transactions of a type at or above an amount.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct LargeAmount {
+    /// The type of transaction the rule applies to
+    #[serde(rename = "type")]
+    pub variant: RawTransactionVariant,
+    /// The smallest amount that matches
+    pub min_amount: f64,
+    /// The verdict for a matching transaction
+    pub verdict: Verdict,
+}
+
+impl Rule for LargeAmount {
+    #[inline]
+    fn name(&self) -> &'static str {
+        "large_amount"
+    }
+
+    #[inline]
+    fn evaluate(&self, _client: &ClientView<'_>, transaction: &PendingTransaction) -> Verdict {
+        if transaction.variant != self.variant {
+            return Verdict::Allow;
+        }
+        if transaction.amount >= self.min_amount {
+            self.verdict
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// Matches withdrawals made while more than a number of the client's transactions are under an
+/// open dispute.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct OpenDisputes {
+    /// The most open disputes a client may have and still withdraw freely
+    pub max_open: usize,
+    /// The verdict for a matching withdrawal
+    pub verdict: Verdict,
+}
+
+impl Rule for OpenDisputes {
+    #[inline]
+    fn name(&self) -> &'static str {
+        "open_disputes"
+    }
+
+    #[inline]
+    fn evaluate(&self, client: &ClientView<'_>, transaction: &PendingTransaction) -> Verdict {
+        if transaction.variant != RawTransactionVariant::Withdrawal {
+            return Verdict::Allow;
+        }
+        let open = client
+            .processed_transactions
+            .values()
+            .filter(|tx| match *tx {
+                Transaction::Deposit(d) => d.disputed && !d.resolved,
+                Transaction::Withdrawal(w) => w.disputed && !w.resolved,
+                Transaction::Dispute(_)
+                | Transaction::Resolve(_)
+                | Transaction::Chargeback(_)
+                | Transaction::Authorization(_)
+                | Transaction::Capture(_)
+                | Transaction::Void(_)
+                | Transaction::Refund(_) => false,
+            })
+            .count();
+        if open > self.max_open {
+            self.verdict
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// The configuration of a built-in rule, tagged by the name of the rule.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum RuleSpec {
+    /// A `VelocityRule`
+    Velocity(VelocityRule),
+    /// A `LargeAmount` rule
+    LargeAmount(LargeAmount),
+    /// An `OpenDisputes` rule
+    OpenDisputes(OpenDisputes),
+    /// A `DuplicateDeposit` rule
+    DuplicateDeposit(DuplicateDeposit),
+}
+
+impl RuleSpec {
+    /// Builds the configured rule.
+    #[inline]
+    #[must_use]
+    pub fn build(self) -> Box<dyn Rule> {
+        match self {
+            Self::Velocity(rule) => Box::new(rule),
+            Self::LargeAmount(rule) => Box::new(rule),
+            Self::OpenDisputes(rule) => Box::new(rule),
+            Self::DuplicateDeposit(rule) => Box::new(rule),
+        }
+    }
+}
+
+/// Reads a JSON array of rule configurations from a file and builds the rules.
+/// # Errors
+/// Returns an error if the file cannot be opened or is not a valid list of rules.
+#[inline]
+pub fn read_rules(path: &Path) -> Result<Vec<Box<dyn Rule>>, RuleError> {
+    let specs: Vec<RuleSpec> = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    Ok(specs.into_iter().map(RuleSpec::build).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Deposit;
+    use anyhow::Result;
+
+    #[test]
+    fn it_reads_rules_from_a_configuration_file() -> Result<()> {
+        let rules = read_rules(Path::new("test_data/test_rules.json"))?;
+        let names: Vec<_> = rules.iter().map(|r| r.name()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "velocity",
+                "large_amount",
+                "open_disputes",
+                "duplicate_deposit"
+            ]
+        );
+        assert!(read_rules(Path::new("test_data/test_profiles.csv")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn it_evaluates_the_built_in_rules() {
+        let mut processed_transactions = HashMap::new();
+        processed_transactions.insert(
+            1,
+            Transaction::Deposit(Deposit {
+                client_id: 1,
+                tx_id: 1,
+                amount: 100.0,
+                disputed: true,
+                resolved: false,
+                refunded: 0.0,
+                held_by_rule: false,
+            }),
+        );
+        let activity = ActivityLog::new(10);
+        let deposits_by_amount = AmountIndex::default();
+        let client = ClientView {
+            client_id: 1,
+            balances: BalanceSnapshot::new(0.0, 100.0, 100.0),
+            processed_transactions: &processed_transactions,
+            activity: &activity,
+            deposits_by_amount: &deposits_by_amount,
+            sequence: 2,
+            clock: None,
+        };
+        let withdrawal = PendingTransaction::new(2, RawTransactionVariant::Withdrawal, 50.0);
+        let deposit = PendingTransaction::new(2, RawTransactionVariant::Deposit, 50.0);
+
+        let large = LargeAmount {
+            variant: RawTransactionVariant::Withdrawal,
+            min_amount: 50.0,
+            verdict: Verdict::Hold,
+        };
+        assert_eq!(large.evaluate(&client, &withdrawal), Verdict::Hold);
+        assert_eq!(large.evaluate(&client, &deposit), Verdict::Allow);
+
+        let disputes = OpenDisputes {
+            max_open: 0,
+            verdict: Verdict::Reject,
+        };
+        assert_eq!(disputes.evaluate(&client, &withdrawal), Verdict::Reject);
+        assert_eq!(disputes.evaluate(&client, &deposit), Verdict::Allow);
+    }
+}
@@ -0,0 +1,336 @@
+//! This module nets the money moved through each settlement partner into `SettlementBatch`es, one
+//! per partner, period and currency.
+//!
+//! Transactions may name the `partner` that moved their funds, and disputes, chargebacks, captures
+//! and withdrawals reversed by a chargeback belong to the partner of the transaction they refer
+//! to, as do refunds that do not name a partner of their own.  A batch nets the deposits a partner
+//! collected against the withdrawals and refunds it paid out and the chargebacks it was debited,
+//! giving the amount owed by or to the partner.  Fees the platform
+//! earns, conversions between currencies and interest credited do not move money through a
+//! partner, but are carried in the batch so that the batches reconcile with the clients' balances.
+
+use crate::client::Client;
+use crate::currency::Currency;
+use crate::report::format_amount;
+use crate::transaction::{InternalVariant, RawTransactionVariant, TransactionKind};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use thiserror::Error;
+
+/// How far apart the batches and the client balances may drift through floating point error.
+const TOLERANCE: f64 = 1e-6;
+
+/// An error type for the settlement module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum SettlementError {
+    /// Triggered if the settlement batches do not add up to the clients' balances
+    #[error("Settlement batches in {} do not reconcile: batches {batches}, client balances {balances}", .currency.map(|c| c.to_string()).unwrap_or_default())]
+    Unreconciled {
+        /// The currency that does not reconcile, if any
+        currency: Option<Currency>,
+        /// The change in the clients' balances that the batches account for
+        batches: f64,
+        /// The sum of the clients' total balances
+        balances: f64,
+    },
+    /// Triggered if the settlement report cannot be written
+    #[error("Failed to write settlement report: {0}")]
+    Csv(#[from] csv::Error),
+}
+
+/// The money moved through a settlement partner in a period, in one currency.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettlementBatch {
+    /// The partner, or `None` for transactions that did not name one
+    pub partner: Option<String>,
+    /// The ID of the period, or `None` for the period still open
+    pub period: Option<u32>,
+    /// The currency, if any
+    pub currency: Option<Currency>,
+    /// The deposits the partner collected
+    pub deposits: f64,
+    /// The withdrawals the partner paid out, less those reversed by a chargeback
+    pub withdrawals: f64,
+    /// The chargebacks the partner was debited, less chargebacks of withdrawals
+    pub chargebacks: f64,
+    /// The refunds the partner paid back to payers
+    pub refunds: f64,
+    /// The fees the platform earned on the partner's transactions
+    pub fees: f64,
+    /// The funds converted into the currency, less those converted out of it, before fees
+    pub conversions: f64,
+    /// The interest credited to clients
+    pub interest: f64,
+}
+
+impl SettlementBatch {
+    /// Creates an empty batch for a partner, in a currency.
+    #[inline]
+    #[must_use]
+    pub const fn new(partner: Option<String>, currency: Option<Currency>) -> Self {
+        Self {
+            partner,
+            period: None,
+            currency,
+            deposits: 0.0,
+            withdrawals: 0.0,
+            chargebacks: 0.0,
+            refunds: 0.0,
+            fees: 0.0,
+            conversions: 0.0,
+            interest: 0.0,
+        }
+    }
+
+    /// Adds the money a transaction moved through settlement, and the fee earned on it.
+    #[inline]
+    pub fn record(&mut self, kind: TransactionKind, settled: f64, fee: f64) {
+        match kind {
+            TransactionKind::Raw(RawTransactionVariant::Deposit) => self.deposits += settled,
+            TransactionKind::Raw(
+                RawTransactionVariant::Withdrawal | RawTransactionVariant::Capture,
+            ) => {
+                self.withdrawals -= settled;
+            }
+            TransactionKind::Raw(RawTransactionVariant::Chargeback) => self.chargebacks -= settled,
+            TransactionKind::Raw(RawTransactionVariant::Refund) => self.refunds -= settled,
+            TransactionKind::Raw(RawTransactionVariant::Convert)
+            | TransactionKind::Internal(InternalVariant::ConversionCredit) => {
+                self.conversions += settled;
+            }
+            TransactionKind::Internal(InternalVariant::Interest) => self.interest += settled,
+            TransactionKind::Raw(
+                RawTransactionVariant::Dispute
+                | RawTransactionVariant::Resolve
+                | RawTransactionVariant::Authorize
+                | RawTransactionVariant::Void
+                | RawTransactionVariant::Close
+                | RawTransactionVariant::Accrue,
+            )
+            | TransactionKind::Internal(
+                InternalVariant::Opening
+                | InternalVariant::DisputeFee
+                | InternalVariant::ReserveRelease,
+            ) => {}
+        }
+        self.fees += fee;
+    }
+
+    /// Adds another batch of the same partner, period and currency to this one.
+    fn merge(&mut self, other: &Self) {
+        self.deposits += other.deposits;
+        self.withdrawals += other.withdrawals;
+        self.chargebacks += other.chargebacks;
+        self.refunds += other.refunds;
+        self.fees += other.fees;
+        self.conversions += other.conversions;
+        self.interest += other.interest;
+    }
+
+    /// The net amount moved through the partner: positive if the partner owes the platform.
+    #[inline]
+    #[must_use]
+    pub fn net(&self) -> f64 {
+        self.deposits - self.withdrawals - self.chargebacks - self.refunds
+    }
+
+    /// The change in the clients' balances the batch accounts for.
+    #[inline]
+    #[must_use]
+    pub fn client_balance(&self) -> f64 {
+        self.net() + self.conversions + self.interest - self.fees
+    }
+}
+
+/// A row of the settlement report, with the amounts formatted like the client report.
+#[derive(Serialize)]
+struct SettlementRow<'batch> {
+    /// The partner
+    partner: Option<&'batch str>,
+    /// The ID of the period
+    period: Option<u32>,
+    /// The currency
+    currency: Option<Currency>,
+    /// The deposits collected
+    deposits: String,
+    /// The withdrawals paid out
+    withdrawals: String,
+    /// The chargebacks debited
+    chargebacks: String,
+    /// The refunds paid back
+    refunds: String,
+    /// The fees earned
+    fees: String,
+    /// The net conversions
+    conversions: String,
+    /// The interest credited
+    interest: String,
+    /// The net amount moved through the partner
+    net: String,
+    /// The amount the partner owes the platform
+    owed_by_partner: String,
+    /// The amount the platform owes the partner
+    owed_to_partner: String,
+}
+
+impl<'batch> From<&'batch SettlementBatch> for SettlementRow<'batch> {
+    fn from(batch: &'batch SettlementBatch) -> Self {
+        let format = |value| {
+            batch
+                .currency
+                .map_or_else(|| format_amount(value), |c| c.format(value))
+        };
+        let net = batch.net();
+        Self {
+            partner: batch.partner.as_deref(),
+            period: batch.period,
+            currency: batch.currency,
+            deposits: format(batch.deposits),
+            withdrawals: format(batch.withdrawals),
+            chargebacks: format(batch.chargebacks),
+            refunds: format(batch.refunds),
+            fees: format(batch.fees),
+            conversions: format(batch.conversions),
+            interest: format(batch.interest),
+            net: format(net),
+            owed_by_partner: format(net.max(0.0_f64)),
+            owed_to_partner: format((0.0_f64 - net).max(0.0_f64)),
+        }
+    }
+}
+
+/// Nets the batches of every client into one batch per period, partner and currency, with the
+/// closed periods first and the open period last.
+#[inline]
+#[must_use]
+pub fn net_batches(batches: Vec<SettlementBatch>) -> Vec<SettlementBatch> {
+    let mut netted = BTreeMap::new();
+    for batch in batches {
+        let key = (
+            batch.period.is_none(),
+            batch.period,
+            batch.partner.clone(),
+            batch.currency,
+        );
+        netted
+            .entry(key)
+            .and_modify(|netted: &mut SettlementBatch| netted.merge(&batch))
+            .or_insert(batch);
+    }
+    netted.into_values().collect()
+}
+
+/// Proves that the batches account for the total balances of the clients in every currency.
+/// # Errors
+/// Returns an error naming the first currency that does not reconcile.
+#[inline]
+pub fn reconcile(batches: &[SettlementBatch], clients: &[Client]) -> Result<(), SettlementError> {
+    let mut sums: BTreeMap<Option<Currency>, (f64, f64)> = BTreeMap::new();
+    for batch in batches {
+        sums.entry(batch.currency).or_default().0 += batch.client_balance();
+    }
+    for client in clients {
+        sums.entry(client.currency).or_default().1 += client.total_balance;
+    }
+    for (currency, (batches, balances)) in sums {
+        if (batches - balances).abs() > TOLERANCE * balances.abs().max(1.0_f64) {
+            return Err(SettlementError::Unreconciled {
+                currency,
+                batches,
+                balances,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Writes the settlement batches as CSV to `writer`.
+/// # Errors
+/// Returns an error if the batches cannot be serialized or written.
+#[inline]
+pub fn write_settlement<W: Write>(
+    batches: &[SettlementBatch],
+    writer: W,
+) -> Result<(), SettlementError> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for batch in batches {
+        csv_writer.serialize(SettlementRow::from(batch))?;
+    }
+    csv_writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::RawTransactionStream;
+    use crate::transaction::RawTransaction;
+    use anyhow::Result;
+    use async_stream::stream;
+
+    #[test]
+    fn it_nets_the_batches_of_each_partner_and_writes_the_amount_owed() -> Result<()> {
+        let mut acquirer = SettlementBatch::new(Some("acquirer".to_owned()), None);
+        acquirer.record(RawTransactionVariant::Deposit.into(), 100.0, 0.0);
+        acquirer.record(RawTransactionVariant::Withdrawal.into(), -30.0, 0.0);
+        let mut other_client = SettlementBatch::new(Some("acquirer".to_owned()), None);
+        other_client.record(RawTransactionVariant::Chargeback.into(), -20.0, 0.0);
+        let mut bank = SettlementBatch::new(Some("bank".to_owned()), None);
+        bank.record(RawTransactionVariant::Withdrawal.into(), -40.0, 0.0);
+        bank.record(RawTransactionVariant::Withdrawal.into(), 10.0, 0.0);
+        let mut closed = SettlementBatch::new(Some("bank".to_owned()), None);
+        closed.period = Some(1);
+        closed.record(RawTransactionVariant::Deposit.into(), 5.0, 0.0);
+
+        let batches = net_batches(vec![acquirer, bank, other_client, closed]);
+        let mut output = vec![];
+        write_settlement(&batches, &mut output)?;
+        assert_eq!(
+            String::from_utf8(output)?,
+            "\
+partner,period,currency,deposits,withdrawals,chargebacks,refunds,fees,conversions,interest,net,owed_by_partner,owed_to_partner
+bank,1,,5.0,0.0,0.0,0.0,0.0,0.0,0.0,5.0,5.0,0.0
+acquirer,,,100.0,30.0,20.0,0.0,0.0,0.0,0.0,50.0,50.0,0.0
+bank,,,0.0,30.0,0.0,0.0,0.0,0.0,0.0,-30.0,0.0,30.0
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn it_books_chargebacks_to_the_partner_of_the_deposit_and_reconciles() -> Result<()> {
+        let mut client = Client::new(1);
+        let stream = RawTransactionStream::new(stream! {
+            for (variant, tx_id, amount, partner) in [
+                (RawTransactionVariant::Deposit, 1, Some(100.0_f64), Some("acquirer")),
+                (RawTransactionVariant::Deposit, 2, Some(20.0_f64), Some("bank")),
+                (RawTransactionVariant::Dispute, 1, None, None),
+                (RawTransactionVariant::Chargeback, 1, None, None),
+            ] {
+                yield RawTransaction {
+                    partner: partner.map(str::to_owned),
+                    ..RawTransaction::new(variant, 1, tx_id, amount)
+                };
+            }
+        });
+        client.process_activity(stream).await;
+
+        let batches = net_batches(client.take_settlement());
+        let nets = batches
+            .iter()
+            .map(|b| (b.partner.as_deref(), b.chargebacks, b.net()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            nets,
+            vec![
+                (Some("acquirer"), 100.0_f64, 0.0_f64),
+                (Some("bank"), 0.0_f64, 20.0_f64)
+            ]
+        );
+        assert!(reconcile(&batches, &[client]).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! This module holds the `ShortfallPolicy` trait, which decides how the shortfall left by a
+//! chargeback is covered, along with the built-in policies.
+//!
+//! When a chargeback takes a client's total balance below zero, the `Client` offers the policy
+//! every withdrawal made since the charged back transaction that can still be reversed, and
+//! reverses the withdrawals the policy selects.  Whatever is not covered stays on the account as a
+//! negative balance and is reported on the `ChargebackEvent`.
+
+use crate::chargeback::ReversedWithdrawal;
+use std::fmt::Debug;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// An error type for the shortfall module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ShortfallError {
+    /// Triggered if a policy name is not recognised
+    #[error("Unknown shortfall policy: {0}")]
+    UnknownPolicy(String),
+}
+
+/// Decides which withdrawals are reversed to cover the shortfall left by a chargeback.
+pub trait ShortfallPolicy: Debug + Send + Sync {
+    /// Selects the withdrawals to reverse, in the order they should be reversed.  `shortfall` is
+    /// how far the total balance is below zero, and `candidates` are the withdrawals that can be
+    /// reversed, oldest first.
+    fn select(&self, shortfall: f64, candidates: &[ReversedWithdrawal]) -> Vec<u32>;
+
+    /// Whether an uncovered shortfall is carried as debt, which later deposits repay first.
+    /// Clients that owe debt keep accepting deposits after their account is locked.
+    #[inline]
+    fn carries_debt(&self) -> bool {
+        false
+    }
+}
+
+/// Takes withdrawals from `candidates`, in order, until the shortfall is covered.
+fn take_until_covered<'candidates, I>(shortfall: f64, candidates: I) -> Vec<u32>
+where
+    I: Iterator<Item = &'candidates ReversedWithdrawal>,
+{
+    let mut remaining = shortfall;
+    let mut selected = vec![];
+    for candidate in candidates {
+        if remaining <= 0_f64 {
+            break;
+        }
+        remaining -= candidate.amount;
+        selected.push(candidate.tx_id);
+    }
+    selected
+}
+
+/// Reverses the most recent withdrawals first.  This is the default policy.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NewestFirst;
+
+impl ShortfallPolicy for NewestFirst {
+    #[inline]
+    fn select(&self, shortfall: f64, candidates: &[ReversedWithdrawal]) -> Vec<u32> {
+        take_until_covered(shortfall, candidates.iter().rev())
+    }
+}
+
+/// Reverses the earliest withdrawals since the charged back transaction first.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OldestFirst;
+
+impl ShortfallPolicy for OldestFirst {
+    #[inline]
+    fn select(&self, shortfall: f64, candidates: &[ReversedWithdrawal]) -> Vec<u32> {
+        take_until_covered(shortfall, candidates.iter())
+    }
+}
+
+/// Reverses nothing, leaving the account with a negative balance.  The shortfall is carried as
+/// debt, which later deposits repay first.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowNegative;
+
+impl ShortfallPolicy for AllowNegative {
+    #[inline]
+    fn select(&self, _shortfall: f64, _candidates: &[ReversedWithdrawal]) -> Vec<u32> {
+        vec![]
+    }
+
+    #[inline]
+    fn carries_debt(&self) -> bool {
+        true
+    }
+}
+
+/// Reverses the smallest set of withdrawals that covers the shortfall.
+///
+/// As few withdrawals as possible are reversed, preferring smaller withdrawals where they still
+/// cover the shortfall, so that as little as possible is reversed beyond it.  If the shortfall
+/// cannot be covered, every withdrawal is reversed.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmallestCover;
+
+impl ShortfallPolicy for SmallestCover {
+    #[inline]
+    fn select(&self, shortfall: f64, candidates: &[ReversedWithdrawal]) -> Vec<u32> {
+        let mut pool = candidates.to_vec();
+        pool.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+        // The fewest withdrawals that can cover the shortfall are the largest ones.
+        let mut slots = 0;
+        let mut covered = 0_f64;
+        while covered < shortfall && slots < pool.len() {
+            covered += pool.get(slots).map_or(0_f64, |w| w.amount);
+            slots = slots.saturating_add(1);
+        }
+        let mut remaining = shortfall;
+        let mut selected = vec![];
+        while slots > 0 {
+            // Pick the smallest withdrawal that, together with the largest of the others, still
+            // fills the remaining slots enough to cover what is left.
+            let largest_others = |index: usize| -> f64 {
+                pool.iter()
+                    .enumerate()
+                    .filter(|&(i, _)| i != index)
+                    .take(slots.saturating_sub(1))
+                    .map(|(_, w)| w.amount)
+                    .sum()
+            };
+            let index = (0..pool.len())
+                .rev()
+                .find(|&i| pool.get(i).map_or(0_f64, |w| w.amount) + largest_others(i) >= remaining)
+                .unwrap_or(0);
+            if index >= pool.len() {
+                break;
+            }
+            let withdrawal = pool.remove(index);
+            remaining -= withdrawal.amount;
+            selected.push(withdrawal.tx_id);
+            slots = slots.saturating_sub(1);
+        }
+        selected
+    }
+}
+
+/// Returns the built-in policy with the given name: `newest-first`, `oldest-first`,
+/// `allow-negative` or `smallest-cover`.
+/// # Errors
+/// Returns an error if there is no built-in policy with that name.
+#[inline]
+pub fn builtin_policy(name: &str) -> Result<Arc<dyn ShortfallPolicy>, ShortfallError> {
+    match name.to_ascii_lowercase().replace('_', "-").as_str() {
+        "newest-first" => Ok(Arc::new(NewestFirst)),
+        "oldest-first" => Ok(Arc::new(OldestFirst)),
+        "allow-negative" => Ok(Arc::new(AllowNegative)),
+        "smallest-cover" => Ok(Arc::new(SmallestCover)),
+        _ => Err(ShortfallError::UnknownPolicy(name.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<ReversedWithdrawal> {
+        vec![
+            ReversedWithdrawal::new(1, 100.0),
+            ReversedWithdrawal::new(2, 500.0),
+            ReversedWithdrawal::new(3, 50.0),
+            ReversedWithdrawal::new(4, 300.0),
+            ReversedWithdrawal::new(5, 250.0),
+        ]
+    }
+
+    #[test]
+    fn it_reverses_newest_or_oldest_first() {
+        assert_eq!(NewestFirst.select(400.0, &candidates()), vec![5, 4]);
+        assert_eq!(OldestFirst.select(400.0, &candidates()), vec![1, 2]);
+        assert_eq!(
+            NewestFirst.select(5_000.0, &candidates()),
+            vec![5, 4, 3, 2, 1]
+        );
+        assert!(AllowNegative.select(400.0, &candidates()).is_empty());
+        assert!(AllowNegative.carries_debt());
+        assert!(!NewestFirst.carries_debt());
+    }
+
+    #[test]
+    fn it_reverses_the_smallest_set_that_covers_the_shortfall() {
+        // A single withdrawal of 500 covers 400; no smaller single withdrawal does.
+        assert_eq!(SmallestCover.select(400.0, &candidates()), vec![2]);
+        // Two withdrawals are needed for 600, and 300 + 300 is not possible, so 500 + 100.
+        assert_eq!(SmallestCover.select(600.0, &candidates()), vec![1, 2]);
+        assert_eq!(SmallestCover.select(250.0, &candidates()), vec![5]);
+        assert_eq!(SmallestCover.select(5_000.0, &candidates()).len(), 5);
+        assert!(SmallestCover.select(0.0, &candidates()).is_empty());
+    }
+
+    #[test]
+    fn it_looks_up_builtin_policies_by_name() {
+        assert_eq!(
+            builtin_policy("Smallest_Cover")
+                .map(|p| p.select(250.0, &candidates()))
+                .ok(),
+            Some(vec![5])
+        );
+        assert_eq!(
+            builtin_policy("random").err(),
+            Some(ShortfallError::UnknownPolicy("random".to_owned()))
+        );
+    }
+}
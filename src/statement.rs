@@ -0,0 +1,589 @@
+//! This module holds the statements of `Client` activity, and exports them as ISO 20022 camt.053
+//! (bank to customer statement) XML documents.
+//!
+//! The booked balance of a statement is the client's total balance.  Accepted deposits and
+//! withdrawals are booked entries.  Disputes only move funds between available and held, so they
+//! are shown as pending reversal entries, and resolving a dispute is shown as an informational
+//! entry.  Chargebacks, and withdrawals reversed because of a chargeback, are booked reversal
+//! entries.  The closing booked balance therefore always equals the opening booked balance plus
+//! the booked credits minus the booked debits.
+//!
+//! A client has one statement per period closed, each identified by the period and dated the day
+//! it was closed, and one for the period still open when processing ends.
+
+use crate::currency::Currency;
+use std::io::{self, Write};
+use thiserror::Error;
+
+/// The namespace of the camt.053 documents produced by this module.
+const CAMT_053_NAMESPACE: &str = "urn:iso:std:iso:20022:tech:xsd:camt.053.001.02";
+
+/// The number of seconds in a day.
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// An error type for the statement module.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum StatementError {
+    /// Triggered if the statement cannot be written
+    #[error("Failed to write statement: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// The direction of a statement entry.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Funds moved into the account.
+    Credit,
+    /// Funds moved out of the account.
+    Debit,
+}
+
+impl Direction {
+    /// The camt credit/debit indicator for this direction.
+    const fn code(self) -> &'static str {
+        match self {
+            Self::Credit => "CRDT",
+            Self::Debit => "DBIT",
+        }
+    }
+}
+
+/// The kind of activity that produced a statement entry.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// An accepted deposit.
+    Deposit,
+    /// An accepted withdrawal.
+    Withdrawal,
+    /// A dispute of an earlier deposit or withdrawal.
+    Dispute,
+    /// The resolution of a dispute, either explicit or because the dispute window expired.
+    Resolve,
+    /// A chargeback of an earlier deposit or withdrawal.
+    Chargeback,
+    /// A withdrawal reversed to cover the shortfall left by a chargeback.
+    ReversedWithdrawal,
+    /// An authorization, which holds funds until it is captured, voided or expires.
+    Authorization,
+    /// The release of the funds held by an authorization that was voided, expired, or captured
+    /// for less than the amount authorized.
+    Release,
+    /// Funds converted out of, or into, the account's currency.
+    Conversion,
+    /// Interest credited on the available balance.
+    Interest,
+    /// The fee charged for a chargeback.
+    DisputeFee,
+    /// A refund of part or all of an earlier deposit.
+    Refund,
+    /// The share of a deposit kept back in the rolling reserve.
+    Reserve,
+    /// The release of a deposit's share of the rolling reserve.
+    ReserveRelease,
+    /// A deposit held by a rule for review.
+    Hold,
+    /// The release of a deposit held by a rule.
+    HoldRelease,
+}
+
+impl EntryKind {
+    /// The proprietary bank transaction code used for this kind of entry.
+    const fn code(self) -> &'static str {
+        match self {
+            Self::Deposit => "DEPOSIT",
+            Self::Withdrawal => "WITHDRAWAL",
+            Self::Dispute => "DISPUTE",
+            Self::Resolve => "RESOLVE",
+            Self::Chargeback => "CHARGEBACK",
+            Self::ReversedWithdrawal => "REVERSAL",
+            Self::Authorization => "AUTHORIZATION",
+            Self::Release => "RELEASE",
+            Self::Conversion => "CONVERSION",
+            Self::Interest => "INTEREST",
+            Self::DisputeFee => "DISPUTE_FEE",
+            Self::Refund => "REFUND",
+            Self::Reserve => "RESERVE",
+            Self::ReserveRelease => "RESERVE_RELEASE",
+            Self::Hold => "HOLD",
+            Self::HoldRelease => "HOLD_RELEASE",
+        }
+    }
+
+    /// The camt entry status for this kind of entry.
+    const fn status(self) -> &'static str {
+        match self {
+            Self::Dispute | Self::Authorization | Self::Reserve | Self::Hold => "PDNG",
+            Self::Resolve | Self::Release | Self::ReserveRelease | Self::HoldRelease => "INFO",
+            Self::Deposit
+            | Self::Withdrawal
+            | Self::Chargeback
+            | Self::ReversedWithdrawal
+            | Self::Conversion
+            | Self::Interest
+            | Self::DisputeFee
+            | Self::Refund => "BOOK",
+        }
+    }
+
+    /// Whether entries of this kind reverse an earlier entry.
+    const fn is_reversal(self) -> bool {
+        matches!(
+            self,
+            Self::Dispute | Self::Chargeback | Self::ReversedWithdrawal | Self::Hold
+        )
+    }
+
+    /// Whether entries of this kind change the booked balance.
+    #[must_use]
+    #[inline]
+    pub const fn is_booked(self) -> bool {
+        matches!(
+            self,
+            Self::Deposit
+                | Self::Withdrawal
+                | Self::Chargeback
+                | Self::ReversedWithdrawal
+                | Self::Conversion
+                | Self::Interest
+                | Self::DisputeFee
+                | Self::Refund
+        )
+    }
+}
+
+/// A single movement on a client's statement.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatementEntry {
+    /// The ID of the transaction the entry refers to.
+    pub tx_id: u32,
+    /// The kind of activity that produced the entry.
+    pub kind: EntryKind,
+    /// The direction of the entry.
+    pub direction: Direction,
+    /// The amount of the entry.
+    pub amount: f64,
+}
+
+impl StatementEntry {
+    /// Creates a new `StatementEntry`
+    #[inline]
+    #[must_use]
+    pub const fn new(tx_id: u32, kind: EntryKind, direction: Direction, amount: f64) -> Self {
+        Self {
+            tx_id,
+            kind,
+            direction,
+            amount,
+        }
+    }
+
+    /// The amount of the entry, negative for debits.
+    #[inline]
+    #[must_use]
+    pub fn signed_amount(&self) -> f64 {
+        match self.direction {
+            Direction::Credit => self.amount,
+            Direction::Debit => -self.amount,
+        }
+    }
+
+    /// Writes the entry element of the entry to a camt.053 statement.
+    fn write_camt<W: Write>(
+        &self,
+        writer: &mut W,
+        currency: Currency,
+        date: &str,
+    ) -> io::Result<()> {
+        writeln!(writer, "      <Ntry>")?;
+        writeln!(writer, "        <NtryRef>{}</NtryRef>", self.tx_id)?;
+        writeln!(
+            writer,
+            "        <Amt Ccy=\"{}\">{}</Amt>",
+            currency,
+            format_camt_amount(self.amount, currency)
+        )?;
+        writeln!(
+            writer,
+            "        <CdtDbtInd>{}</CdtDbtInd>",
+            self.direction.code()
+        )?;
+        if self.kind.is_reversal() {
+            writeln!(writer, "        <RvslInd>true</RvslInd>")?;
+        }
+        writeln!(writer, "        <Sts>{}</Sts>", self.kind.status())?;
+        if self.kind.is_booked() {
+            writeln!(writer, "        <BookgDt><Dt>{date}</Dt></BookgDt>")?;
+        }
+        writeln!(writer, "        <ValDt><Dt>{date}</Dt></ValDt>")?;
+        writeln!(
+            writer,
+            "        <BkTxCd><Prtry><Cd>{}</Cd></Prtry></BkTxCd>",
+            self.kind.code()
+        )?;
+        writeln!(
+            writer,
+            "        <NtryDtls><TxDtls><Refs><TxId>{}</TxId></Refs></TxDtls></NtryDtls>",
+            self.tx_id
+        )?;
+        writeln!(writer, "      </Ntry>")
+    }
+}
+
+/// A snapshot of the balances of a client.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BalanceSnapshot {
+    /// The available balance.
+    pub available: f64,
+    /// The held balance.
+    pub held: f64,
+    /// The total balance.
+    pub total: f64,
+}
+
+impl BalanceSnapshot {
+    /// Creates a new `BalanceSnapshot`
+    #[inline]
+    #[must_use]
+    pub const fn new(available: f64, held: f64, total: f64) -> Self {
+        Self {
+            available,
+            held,
+            total,
+        }
+    }
+}
+
+/// The statement of a single client's activity.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientStatement {
+    /// The id of the client.
+    pub client_id: u16,
+    /// The balances of the client when the statement was opened.
+    pub opening: BalanceSnapshot,
+    /// The balances of the client when the statement was closed.
+    pub closing: BalanceSnapshot,
+    /// The movements on the client's account, in the order they happened.
+    pub entries: Vec<StatementEntry>,
+    /// The currency of the account, if it has one.  Otherwise the statement is written in the
+    /// currency the document falls back to.
+    pub currency: Option<Currency>,
+    /// The ID of the period the statement covers, if the period was closed.
+    pub period: Option<u32>,
+    /// When the period was closed, in seconds since the Unix epoch, if the close says.
+    pub closed_at: Option<u64>,
+}
+
+impl ClientStatement {
+    /// Opens a new statement with the given opening balances.
+    #[inline]
+    #[must_use]
+    pub const fn new(client_id: u16, opening: BalanceSnapshot) -> Self {
+        Self {
+            client_id,
+            opening,
+            closing: opening,
+            entries: Vec::new(),
+            currency: None,
+            period: None,
+            closed_at: None,
+        }
+    }
+}
+
+/// Options for the camt.053 document.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Camt053Options {
+    /// The message id of the document.  Each statement's id is derived from it.
+    pub message_id: String,
+    /// The creation time of the document, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// The currency of the statements of accounts that have none of their own.
+    pub currency: Currency,
+}
+
+impl Camt053Options {
+    /// Creates a new `Camt053Options`
+    #[inline]
+    #[must_use]
+    pub fn new(message_id: &str, created_at: u64, currency: Currency) -> Self {
+        Self {
+            message_id: message_id.to_owned(),
+            created_at,
+            currency,
+        }
+    }
+}
+
+/// Formats a Unix timestamp as an ISO 8601 date, e.g. `2022-06-01`.
+#[inline]
+#[must_use]
+pub fn format_date(unix_seconds: u64) -> String {
+    let days = unix_seconds.div_euclid(SECONDS_PER_DAY);
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days.saturating_add(719_468);
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = doe
+        .saturating_sub(doe.div_euclid(1460))
+        .saturating_add(doe.div_euclid(36_524))
+        .saturating_sub(doe.div_euclid(146_096))
+        .div_euclid(365);
+    let doy = doe.saturating_sub(
+        yoe.saturating_mul(365)
+            .saturating_add(yoe.div_euclid(4))
+            .saturating_sub(yoe.div_euclid(100)),
+    );
+    let mp = doy.saturating_mul(5).saturating_add(2).div_euclid(153);
+    let day = doy
+        .saturating_sub(mp.saturating_mul(153).saturating_add(2).div_euclid(5))
+        .saturating_add(1);
+    let month = if mp < 10 {
+        mp.saturating_add(3)
+    } else {
+        mp.saturating_sub(9)
+    };
+    let year = yoe
+        .saturating_add(era.saturating_mul(400))
+        .saturating_add(u64::from(month <= 2));
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Formats a Unix timestamp as an ISO 8601 date and time, e.g. `2022-06-01T13:45:00`.
+#[inline]
+#[must_use]
+pub fn format_datetime(unix_seconds: u64) -> String {
+    let seconds = unix_seconds.rem_euclid(SECONDS_PER_DAY);
+    format!(
+        "{}T{:02}:{:02}:{:02}",
+        format_date(unix_seconds),
+        seconds.div_euclid(3600),
+        seconds.div_euclid(60).rem_euclid(60),
+        seconds.rem_euclid(60)
+    )
+}
+
+/// Formats an amount for camt, which requires a non-negative decimal without an exponent, with the
+/// decimal places of the minor unit of its currency.
+fn format_camt_amount(amount: f64, currency: Currency) -> String {
+    currency.format(amount.abs())
+}
+
+/// Writes a balance element of the given type.
+fn write_balance<W: Write>(
+    writer: &mut W,
+    code: &str,
+    amount: f64,
+    currency: Currency,
+    date: &str,
+) -> io::Result<()> {
+    let direction = if amount < 0.0_f64 {
+        Direction::Debit
+    } else {
+        Direction::Credit
+    };
+    writeln!(writer, "      <Bal>")?;
+    writeln!(
+        writer,
+        "        <Tp><CdOrPrtry><Cd>{code}</Cd></CdOrPrtry></Tp>"
+    )?;
+    writeln!(
+        writer,
+        "        <Amt Ccy=\"{}\">{}</Amt>",
+        currency,
+        format_camt_amount(amount, currency)
+    )?;
+    writeln!(
+        writer,
+        "        <CdtDbtInd>{}</CdtDbtInd>",
+        direction.code()
+    )?;
+    writeln!(writer, "        <Dt><Dt>{date}</Dt></Dt>")?;
+    writeln!(writer, "      </Bal>")
+}
+
+/// Writes the statements to `writer` as a single camt.053 document with one `Stmt` per client.
+/// # Errors
+/// Returns an error if the document cannot be written.
+#[inline]
+pub fn write_camt053<W: Write>(
+    statements: &[ClientStatement],
+    options: &Camt053Options,
+    mut writer: W,
+) -> Result<(), StatementError> {
+    // Escape the characters of the message ID that are not allowed in XML text or attributes.
+    let mut message_id = String::with_capacity(options.message_id.len());
+    for c in options.message_id.chars() {
+        match c {
+            '&' => message_id.push_str("&amp;"),
+            '<' => message_id.push_str("&lt;"),
+            '>' => message_id.push_str("&gt;"),
+            '"' => message_id.push_str("&quot;"),
+            '\'' => message_id.push_str("&apos;"),
+            _ => message_id.push(c),
+        }
+    }
+    let created_at = format_datetime(options.created_at);
+    let date = format_date(options.created_at);
+    writeln!(writer, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(writer, "<Document xmlns=\"{CAMT_053_NAMESPACE}\">")?;
+    writeln!(writer, "  <BkToCstmrStmt>")?;
+    writeln!(writer, "    <GrpHdr>")?;
+    writeln!(writer, "      <MsgId>{message_id}</MsgId>")?;
+    writeln!(writer, "      <CreDtTm>{created_at}</CreDtTm>")?;
+    writeln!(writer, "    </GrpHdr>")?;
+    for statement in statements {
+        let currency = statement.currency.unwrap_or(options.currency);
+        let date = statement
+            .closed_at
+            .map_or_else(|| date.clone(), format_date);
+        let currency_suffix = statement
+            .currency
+            .map(|c| format!("-{c}"))
+            .unwrap_or_default();
+        let period_suffix = statement
+            .period
+            .map(|period| format!("-{period}"))
+            .unwrap_or_default();
+        writeln!(writer, "    <Stmt>")?;
+        writeln!(
+            writer,
+            "      <Id>{}-{}{}{}</Id>",
+            message_id, statement.client_id, currency_suffix, period_suffix
+        )?;
+        writeln!(writer, "      <CreDtTm>{created_at}</CreDtTm>")?;
+        writeln!(
+            writer,
+            "      <Acct><Id><Othr><Id>{}</Id></Othr></Id><Ccy>{}</Ccy></Acct>",
+            statement.client_id, currency
+        )?;
+        write_balance(
+            &mut writer,
+            "OPBD",
+            statement.opening.total,
+            currency,
+            &date,
+        )?;
+        write_balance(
+            &mut writer,
+            "CLBD",
+            statement.closing.total,
+            currency,
+            &date,
+        )?;
+        write_balance(
+            &mut writer,
+            "CLAV",
+            statement.closing.available,
+            currency,
+            &date,
+        )?;
+        for entry in &statement.entries {
+            entry.write_camt(&mut writer, currency, &date)?;
+        }
+        writeln!(writer, "    </Stmt>")?;
+    }
+    writeln!(writer, "  </BkToCstmrStmt>")?;
+    writeln!(writer, "</Document>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[test]
+    fn it_formats_unix_timestamps_as_iso_dates() {
+        assert_eq!(format_datetime(0), "1970-01-01T00:00:00");
+        assert_eq!(format_datetime(951_782_400), "2000-02-29T00:00:00");
+        assert_eq!(format_datetime(1_654_091_100), "2022-06-01T13:45:00");
+    }
+
+    #[test]
+    fn it_escapes_xml_text() -> Result<()> {
+        let options = Camt053Options::new("a<b>&\"c'", 0, "EUR".parse()?);
+
+        let mut output = vec![];
+        write_camt053(&[], &options, &mut output)?;
+        let xml = String::from_utf8(output)?;
+
+        assert!(xml.contains("<MsgId>a&lt;b&gt;&amp;&quot;c&apos;</MsgId>"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_a_camt053_statement() -> Result<()> {
+        let mut statement = ClientStatement::new(7, BalanceSnapshot::default());
+        statement.entries = vec![
+            StatementEntry::new(1, EntryKind::Deposit, Direction::Credit, 1000.0),
+            StatementEntry::new(1, EntryKind::Dispute, Direction::Debit, 1000.0),
+            StatementEntry::new(1, EntryKind::Chargeback, Direction::Debit, 1000.0),
+        ];
+        statement.closing = BalanceSnapshot::new(0.0, 0.0, 0.0);
+        let options = Camt053Options::new("RUN<1>", 1_654_091_100, "EUR".parse()?);
+
+        let mut output = vec![];
+        write_camt053(&[statement], &options, &mut output)?;
+        let xml = String::from_utf8(output)?;
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(xml.contains("<MsgId>RUN&lt;1&gt;</MsgId>"));
+        assert!(xml.contains("<Id>RUN&lt;1&gt;-7</Id>"));
+        assert!(xml.contains("<CreDtTm>2022-06-01T13:45:00</CreDtTm>"));
+        assert!(xml.contains("<Cd>OPBD</Cd>"));
+        assert!(xml.contains("<Cd>CLBD</Cd>"));
+        assert_eq!(xml.matches("<Ntry>").count(), 3);
+        assert_eq!(xml.matches("<RvslInd>true</RvslInd>").count(), 2);
+        assert!(xml.contains("<Amt Ccy=\"EUR\">1000.00</Amt>"));
+        assert!(xml.contains("<Sts>PDNG</Sts>"));
+        assert!(xml.contains("<BkTxCd><Prtry><Cd>CHARGEBACK</Cd></Prtry></BkTxCd>"));
+        assert!(xml.ends_with("</Document>\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_dates_the_statement_of_a_closed_period_by_its_close() -> Result<()> {
+        let mut statement = ClientStatement::new(7, BalanceSnapshot::default());
+        statement.period = Some(20_220_531);
+        statement.closed_at = Some(1_654_041_600);
+        let options = Camt053Options::new("RUN", 1_654_091_100, "EUR".parse()?);
+
+        let mut output = vec![];
+        write_camt053(&[statement], &options, &mut output)?;
+        let xml = String::from_utf8(output)?;
+
+        assert!(xml.contains("<Id>RUN-7-20220531</Id>"));
+        assert!(xml.contains("<Dt><Dt>2022-06-01</Dt></Dt>"));
+        Ok(())
+    }
+
+    #[test]
+    fn it_writes_amounts_in_the_currency_of_the_account() -> Result<()> {
+        let mut statement = ClientStatement::new(7, BalanceSnapshot::new(1500.0, 0.0, 1500.0));
+        statement.currency = Some("JPY".parse()?);
+        statement.entries = vec![StatementEntry::new(
+            1,
+            EntryKind::Deposit,
+            Direction::Credit,
+            1500.0,
+        )];
+        let options = Camt053Options::new("RUN", 1_654_091_100, "EUR".parse()?);
+
+        let mut output = vec![];
+        write_camt053(&[statement], &options, &mut output)?;
+        let xml = String::from_utf8(output)?;
+
+        assert!(xml.contains("<Ccy>JPY</Ccy>"));
+        assert!(xml.contains("<Amt Ccy=\"JPY\">1500</Amt>"));
+        assert!(!xml.contains("EUR"));
+        Ok(())
+    }
+}
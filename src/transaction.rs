@@ -2,7 +2,9 @@
 //! and to convert it into a well-formed variant of the transactions that can be used later in the
 //! application.
 
-use serde::{Deserialize, Serialize};
+use crate::currency::{deserialize_currency, Currency};
+use serde::de::Error as DeError;
+use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 
 /// Truncates a floating point number to the specified number of decimal places.
@@ -18,6 +20,45 @@ pub fn truncate_to_decimal_places(num: f64, places: i32) -> f64 {
     (num * ten).floor() / ten
 }
 
+/// Truncates the amount of a transaction to the minor unit of its currency, or to 4 decimal places
+/// if it does not name one.
+fn truncate_amount(value: &RawTransaction, amount: f64) -> f64 {
+    value.currency.map_or_else(
+        || truncate_to_decimal_places(amount, 4),
+        |currency| currency.truncate(amount),
+    )
+}
+
+/// A transaction amount as it appears in the input, which may be either a number or a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawAmount {
+    /// An amount given as a number
+    Number(f64),
+    /// An amount given as a string
+    Text(String),
+}
+
+/// Deserializer for transaction amounts.  Accepts numbers as well as strings so that sources which
+/// quote their amounts (as many JSON producers do to preserve precision) can be read.
+fn deserialize_amount<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    match Option::<RawAmount>::deserialize(deserializer)? {
+        Some(RawAmount::Number(amount)) => Ok(Some(amount)),
+        Some(RawAmount::Text(text)) if text.trim().is_empty() => Ok(None),
+        Some(RawAmount::Text(text)) => text.trim().parse().map(Some).map_err(D::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// Deserializer for the optional partner of a transaction, which may be left empty.
+fn deserialize_partner<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?
+        .map(|partner| partner.trim().to_owned())
+        .filter(|partner| !partner.is_empty()))
+}
+
 /// An error type for the transaction module.
 #[derive(Debug, Error, PartialEq)]
 #[non_exhaustive]
@@ -37,16 +78,42 @@ pub enum TransactionError {
     /// An error occurred while attempting to convert a `Transaction` to a `ChargeBack`.
     #[error("Invalid Chargeback")]
     InvalidChargeback,
+    /// An error occurred while attempting to convert a `Transaction` to an `Authorization`.
+    #[error("Invalid Authorization")]
+    InvalidAuthorization,
+    /// An error occurred while attempting to convert a `Transaction` to a `Capture`.
+    #[error("Invalid Capture")]
+    InvalidCapture,
+    /// An error occurred while attempting to convert a `Transaction` to a `Void`.
+    #[error("Invalid Void")]
+    InvalidVoid,
+    /// An error occurred while attempting to convert a `Transaction` to a `Convert`.
+    #[error("Invalid Convert")]
+    InvalidConvert,
+    /// An error occurred while attempting to convert a `Transaction` to a `Refund`.
+    #[error("Invalid Refund")]
+    InvalidRefund,
 }
 
-/// Transactions have five variants:
+/// Transactions have twelve variants:
 /// * Deposit
 /// * Withdrawal
 /// * Dispute
 /// * Resolve
 /// * Chargeback
+/// * Authorize
+/// * Capture
+/// * Void
+/// * Convert
+/// * Refund
+/// * Close
+/// * Accrue
+///
+/// A close is not a transaction of any one client: it closes the current period of every account.
+/// Neither is an accrue, which credits every account the interest it earned.  The entries a client
+/// posts on its own are not read from the input, and are an `InternalVariant` instead.
 #[non_exhaustive]
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RawTransactionVariant {
     /// A deposit is a credit to the client's asset account, meaning it should increase the available and
     /// total funds of the client account
@@ -75,6 +142,90 @@ pub enum RawTransactionVariant {
     /// client's account should be immediately frozen.
     #[serde(rename = "chargeback")]
     Chargeback,
+    /// An authorization places a hold on the client's funds, identified by the ID of the
+    /// transaction.  The client's available funds should decrease by the amount authorized and
+    /// their held funds should increase by it, while their total funds should remain the same.
+    #[serde(rename = "authorize")]
+    Authorize,
+    /// A capture turns an authorization into a final withdrawal of the amount captured, which may
+    /// be less than the amount authorized.  The client's held funds should decrease by the amount
+    /// authorized, their total funds by the amount captured, and any remainder is released to
+    /// their available funds.
+    #[serde(rename = "capture")]
+    Capture,
+    /// A void releases an authorization.  The client's held funds should decrease by the amount
+    /// authorized and their available funds should increase by it.
+    #[serde(rename = "void")]
+    Void,
+    /// A convert moves funds from one currency of the client to another.  The client's available
+    /// and total funds in the currency of the transaction should decrease by the amount converted,
+    /// and the amount it converts to is credited to the currency named by `to_currency`.
+    #[serde(rename = "convert")]
+    Convert,
+    /// A close ends the current period of every client's account, identified by the ID of the
+    /// transaction, and freezes a snapshot of each account's balances.  The client of a close is
+    /// ignored, and the balances do not change.
+    #[serde(rename = "close")]
+    Close,
+    /// An accrue credits every client's account the interest earned on its average available
+    /// balance since the previous accrue, identified by the ID of the transaction.  The client of
+    /// an accrue is ignored.
+    #[serde(rename = "accrue")]
+    Accrue,
+    /// A refund returns part or all of an earlier deposit, named by its `original_tx`, to the
+    /// payer.  The client's available and total funds should decrease by the amount refunded, and
+    /// the refunded part of the deposit can no longer be disputed.  A refund cannot be disputed
+    /// itself.
+    #[serde(rename = "refund")]
+    Refund,
+}
+
+/// The entries a client posts to its own account rather than reads from the input, identified by
+/// the ID of the transaction that caused them.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InternalVariant {
+    /// The balances an account was opened with, posted against settlement.
+    Opening,
+    /// The credit of a convert to the currency the funds were converted into, which should increase
+    /// the client's available and total funds in that currency.
+    ConversionCredit,
+    /// The interest an accrue credits to a client's available and total funds, which cannot be
+    /// disputed.
+    Interest,
+    /// The fee charged to a client's available and total funds for a chargeback, identified by the
+    /// ID of the transaction charged back.
+    DisputeFee,
+    /// The release of the share of a deposit kept in the rolling reserve back to the client's
+    /// available funds, identified by the ID of the deposit.
+    ReserveRelease,
+}
+
+/// The type of anything posted to a client's account: a transaction read from the input, or an
+/// entry the client posts on its own.  Both serialize as the name of their variant.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum TransactionKind {
+    /// A transaction read from the input
+    Raw(RawTransactionVariant),
+    /// An entry the client posts on its own
+    Internal(InternalVariant),
+}
+
+impl From<RawTransactionVariant> for TransactionKind {
+    #[inline]
+    fn from(variant: RawTransactionVariant) -> Self {
+        Self::Raw(variant)
+    }
+}
+
+impl From<InternalVariant> for TransactionKind {
+    #[inline]
+    fn from(variant: InternalVariant) -> Self {
+        Self::Internal(variant)
+    }
 }
 
 /// Wrapper for collections of parsed transactions.
@@ -91,6 +242,14 @@ pub enum Transaction {
     Resolve(Resolve),
     /// A chargeback transaction
     Chargeback(Chargeback),
+    /// An authorization transaction
+    Authorization(Authorization),
+    /// A capture transaction
+    Capture(Capture),
+    /// A void transaction
+    Void(Void),
+    /// A refund transaction
+    Refund(Refund),
 }
 /// A deposit is a credit to the client's asset account, meaning it should increase the available and
 /// total funds of the client account
@@ -107,6 +266,19 @@ pub struct Deposit {
     pub disputed: bool,
     /// Whether or not the dispute was resolved
     pub resolved: bool,
+    /// The amount refunded so far
+    pub refunded: f64,
+    /// Whether or not a rule holds the deposit for review
+    pub held_by_rule: bool,
+}
+
+impl Deposit {
+    /// The amount that can still be disputed: what has not been refunded.
+    #[inline]
+    #[must_use]
+    pub fn disputable(&self) -> f64 {
+        self.amount - self.refunded
+    }
 }
 
 impl TryFrom<RawTransaction> for Deposit {
@@ -122,9 +294,11 @@ impl TryFrom<RawTransaction> for Deposit {
                 return Ok(Self {
                     client_id: value.client_id,
                     tx_id: value.tx_id,
-                    amount: truncate_to_decimal_places(amount, 4),
+                    amount: truncate_amount(&value, amount),
                     disputed: false,
                     resolved: false,
+                    refunded: 0.0,
+                    held_by_rule: false,
                 });
             }
         }
@@ -167,7 +341,7 @@ impl TryFrom<RawTransaction> for Withdrawal {
             return Ok(Self {
                 client_id: value.client_id,
                 tx_id: value.tx_id,
-                amount: truncate_to_decimal_places(amount, 4),
+                amount: truncate_amount(&value, amount),
                 disputed: false,
                 resolved: false,
                 failed: false,
@@ -270,6 +444,167 @@ impl TryFrom<RawTransaction> for Chargeback {
     }
 }
 
+/// An authorization places a hold on the client's funds until it is captured, voided or expires.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Authorization {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the transaction, which identifies the hold
+    pub tx_id: u32,
+    /// The amount held
+    pub amount: f64,
+    /// Whether the hold was voided or expired
+    pub released: bool,
+}
+
+impl TryFrom<RawTransaction> for Authorization {
+    type Error = TransactionError;
+
+    #[inline]
+    fn try_from(value: RawTransaction) -> Result<Self, Self::Error> {
+        if value.variant != RawTransactionVariant::Authorize {
+            return Err(TransactionError::InvalidAuthorization);
+        }
+        match value.amount {
+            Some(amount) if amount >= 0.0_f64 => Ok(Self {
+                client_id: value.client_id,
+                tx_id: value.tx_id,
+                amount: truncate_amount(&value, amount),
+                released: false,
+            }),
+            _ => Err(TransactionError::InvalidAuthorization),
+        }
+    }
+}
+
+/// A capture turns the authorization with the same ID into a final withdrawal.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the authorization
+    pub tx_id: u32,
+    /// The amount captured, or `None` to capture the whole authorization
+    pub amount: Option<f64>,
+}
+
+impl TryFrom<RawTransaction> for Capture {
+    type Error = TransactionError;
+
+    #[inline]
+    fn try_from(value: RawTransaction) -> Result<Self, Self::Error> {
+        if value.variant != RawTransactionVariant::Capture {
+            return Err(TransactionError::InvalidCapture);
+        }
+        if value.amount.is_some_and(|amount| amount < 0.0_f64) {
+            return Err(TransactionError::InvalidCapture);
+        }
+        Ok(Self {
+            client_id: value.client_id,
+            tx_id: value.tx_id,
+            amount: value.amount.map(|amount| truncate_amount(&value, amount)),
+        })
+    }
+}
+
+/// A convert moves the amount from the currency of the client's account to another currency.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Convert {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the transaction
+    pub tx_id: u32,
+    /// The amount converted, in the currency of the account
+    pub amount: f64,
+    /// The currency the amount is converted into
+    pub to_currency: Currency,
+}
+
+impl TryFrom<RawTransaction> for Convert {
+    type Error = TransactionError;
+
+    #[inline]
+    fn try_from(value: RawTransaction) -> Result<Self, Self::Error> {
+        if value.variant != RawTransactionVariant::Convert {
+            return Err(TransactionError::InvalidConvert);
+        }
+        match (value.amount, value.to_currency) {
+            (Some(amount), Some(to_currency)) if amount > 0.0_f64 => Ok(Self {
+                client_id: value.client_id,
+                tx_id: value.tx_id,
+                amount: truncate_amount(&value, amount),
+                to_currency,
+            }),
+            _ => Err(TransactionError::InvalidConvert),
+        }
+    }
+}
+
+/// A void releases the authorization with the same ID.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Void {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the authorization
+    pub tx_id: u32,
+}
+
+impl TryFrom<RawTransaction> for Void {
+    type Error = TransactionError;
+
+    #[inline]
+    fn try_from(value: RawTransaction) -> Result<Self, Self::Error> {
+        if value.variant != RawTransactionVariant::Void {
+            return Err(TransactionError::InvalidVoid);
+        }
+        if value.amount.is_some() {
+            return Err(TransactionError::InvalidVoid);
+        }
+        Ok(Self {
+            client_id: value.client_id,
+            tx_id: value.tx_id,
+        })
+    }
+}
+
+/// A refund returns part or all of the deposit named by `original_tx`.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Refund {
+    /// The ID of the client
+    pub client_id: u16,
+    /// The ID of the transaction
+    pub tx_id: u32,
+    /// The ID of the deposit refunded
+    pub original_tx: u32,
+    /// The amount refunded
+    pub amount: f64,
+}
+
+impl TryFrom<RawTransaction> for Refund {
+    type Error = TransactionError;
+
+    #[inline]
+    fn try_from(value: RawTransaction) -> Result<Self, Self::Error> {
+        if value.variant != RawTransactionVariant::Refund {
+            return Err(TransactionError::InvalidRefund);
+        }
+        match (value.amount, value.original_tx) {
+            (Some(amount), Some(original_tx)) if amount > 0.0_f64 => Ok(Self {
+                client_id: value.client_id,
+                tx_id: value.tx_id,
+                original_tx,
+                amount: truncate_amount(&value, amount),
+            }),
+            _ => Err(TransactionError::InvalidRefund),
+        }
+    }
+}
+
 /// A wrapper type around the possible transaction variants.
 #[non_exhaustive]
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -284,7 +619,47 @@ pub struct RawTransaction {
     #[serde(rename = "tx")]
     pub tx_id: u32,
     /// The amount of the transaction
+    #[serde(default, deserialize_with = "deserialize_amount")]
     pub amount: Option<f64>,
+    /// When the transaction happened, in seconds since the Unix epoch, if the input says
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// The currency of the transaction, if the input names one
+    #[serde(default, deserialize_with = "deserialize_currency")]
+    pub currency: Option<Currency>,
+    /// The currency a convert moves funds into
+    #[serde(default, deserialize_with = "deserialize_currency")]
+    pub to_currency: Option<Currency>,
+    /// The settlement partner that moved the funds, if the input names one
+    #[serde(default, deserialize_with = "deserialize_partner")]
+    pub partner: Option<String>,
+    /// The ID of the deposit a refund returns
+    #[serde(default)]
+    pub original_tx: Option<u32>,
+}
+
+impl RawTransaction {
+    /// Creates a new `RawTransaction` with none of the optional columns set.
+    #[inline]
+    #[must_use]
+    pub const fn new(
+        variant: RawTransactionVariant,
+        client_id: u16,
+        tx_id: u32,
+        amount: Option<f64>,
+    ) -> Self {
+        Self {
+            variant,
+            client_id,
+            tx_id,
+            amount,
+            timestamp: None,
+            currency: None,
+            to_currency: None,
+            partner: None,
+            original_tx: None,
+        }
+    }
 }
 
 unsafe impl Send for RawTransaction {}
@@ -313,6 +688,19 @@ mod tests {
         assert!((truncated_amount - 0.1234_f64).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn it_truncates_converts_to_the_minor_unit_of_their_currency() -> Result<()> {
+        for amount in [1.13_f64, 0.29_f64] {
+            let convert = Convert::try_from(RawTransaction {
+                currency: Some("EUR".parse()?),
+                to_currency: Some("USD".parse()?),
+                ..RawTransaction::new(RawTransactionVariant::Convert, 1, 1, Some(amount))
+            })?;
+            assert!((convert.amount - amount).abs() < f64::EPSILON);
+        }
+        Ok(())
+    }
+
     #[test]
     fn it_returns_large_numbers_without_modification() {
         let amount = f64::MAX / 2.0_f64;
@@ -340,52 +728,27 @@ mod tests {
         let deposit = iter.next().unwrap()?;
         assert_eq!(
             deposit,
-            RawTransaction {
-                variant: RawTransactionVariant::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(1.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.00_f64))
         );
         let withdrawal = iter.next().unwrap()?;
         assert_eq!(
             withdrawal,
-            RawTransaction {
-                variant: RawTransactionVariant::Withdrawal,
-                client_id: 2,
-                tx_id: 2,
-                amount: Some(2.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 2, 2, Some(2.00_f64))
         );
         let dispute = iter.next().unwrap()?;
         assert_eq!(
             dispute,
-            RawTransaction {
-                variant: RawTransactionVariant::Dispute,
-                client_id: 3,
-                tx_id: 3,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Dispute, 3, 3, None)
         );
         let resolve = iter.next().unwrap()?;
         assert_eq!(
             resolve,
-            RawTransaction {
-                variant: RawTransactionVariant::Resolve,
-                client_id: 4,
-                tx_id: 4,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Resolve, 4, 4, None)
         );
         let chargeback = iter.next().unwrap()?;
         assert_eq!(
             chargeback,
-            RawTransaction {
-                variant: RawTransactionVariant::Chargeback,
-                client_id: 5,
-                tx_id: 5,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Chargeback, 5, 5, None)
         );
         Ok(())
     }
@@ -409,22 +772,12 @@ mod tests {
         let deposit = iter.next().unwrap()?;
         assert_eq!(
             deposit,
-            RawTransaction {
-                variant: RawTransactionVariant::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(1.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.00_f64))
         );
         let withdrawal = iter.next().unwrap()?;
         assert_eq!(
             withdrawal,
-            RawTransaction {
-                variant: RawTransactionVariant::Withdrawal,
-                client_id: 2,
-                tx_id: 2,
-                amount: Some(2.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 2, 2, Some(2.00_f64))
         );
         let dispute = iter.next().unwrap();
         assert!(dispute.is_err());
@@ -433,12 +786,7 @@ mod tests {
         let chargeback_transaction = iter.next().unwrap()?;
         assert_eq!(
             chargeback_transaction,
-            RawTransaction {
-                variant: RawTransactionVariant::Chargeback,
-                client_id: 5,
-                tx_id: 5,
-                amount: Some(3.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Chargeback, 5, 5, Some(3.00_f64))
         );
         let chargeback: Result<Chargeback, TransactionError> = chargeback_transaction.try_into();
         assert_eq!(chargeback.unwrap_err(), TransactionError::InvalidChargeback);
@@ -463,63 +811,54 @@ mod tests {
         let deposit = iter.next().unwrap()?;
         assert_eq!(
             deposit,
-            RawTransaction {
-                variant: RawTransactionVariant::Deposit,
-                client_id: 1,
-                tx_id: 1,
-                amount: Some(1.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.00_f64))
         );
         let withdrawal = iter.next().unwrap()?;
         assert_eq!(
             withdrawal,
-            RawTransaction {
-                variant: RawTransactionVariant::Withdrawal,
-                client_id: 2,
-                tx_id: 2,
-                amount: Some(2.00_f64)
-            }
+            RawTransaction::new(RawTransactionVariant::Withdrawal, 2, 2, Some(2.00_f64))
         );
         let dispute = iter.next().unwrap()?;
         assert_eq!(
             dispute,
-            RawTransaction {
-                variant: RawTransactionVariant::Dispute,
-                client_id: 3,
-                tx_id: 3,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Dispute, 3, 3, None)
         );
         let resolve = iter.next().unwrap()?;
         assert_eq!(
             resolve,
-            RawTransaction {
-                variant: RawTransactionVariant::Resolve,
-                client_id: 4,
-                tx_id: 4,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Resolve, 4, 4, None)
         );
         let chargeback = iter.next().unwrap()?;
         assert_eq!(
             chargeback,
-            RawTransaction {
-                variant: RawTransactionVariant::Chargeback,
-                client_id: 5,
-                tx_id: 5,
-                amount: None
-            }
+            RawTransaction::new(RawTransactionVariant::Chargeback, 5, 5, None)
         );
         Ok(())
     }
+    #[test]
+    fn it_deserializes_json_transactions_with_string_and_numeric_amounts() -> Result<()> {
+        let numeric: RawTransaction =
+            serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}"#)?;
+        assert_eq!(numeric.amount, Some(1.5_f64));
+        let integer: RawTransaction =
+            serde_json::from_str(r#"{"type":"deposit","client":1,"tx":1,"amount":2}"#)?;
+        assert_eq!(integer.amount, Some(2.0_f64));
+        let string: RawTransaction =
+            serde_json::from_str(r#"{"type":"withdrawal","client":1,"tx":2,"amount":"0.1234"}"#)?;
+        assert_eq!(string.amount, Some(0.1234_f64));
+        let missing: RawTransaction =
+            serde_json::from_str(r#"{"type":"dispute","client":1,"tx":1}"#)?;
+        assert_eq!(missing.amount, None);
+        let invalid = serde_json::from_str::<RawTransaction>(
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"abc"}"#,
+        );
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
     #[test]
     fn it_converts_a_transaction_to_a_deposit() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.00_f64));
         let deposit: Deposit = tx.try_into()?;
         assert_eq!(
             deposit,
@@ -529,6 +868,8 @@ mod tests {
                 amount: 1.00_f64,
                 disputed: false,
                 resolved: false,
+                refunded: 0.0,
+                held_by_rule: false,
             }
         );
         Ok(())
@@ -536,12 +877,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_transaction_to_a_deposit_with_negative_amount() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(-1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(-1.00_f64));
         let deposit: Result<Deposit, TransactionError> = tx.try_into();
         if deposit == Err(TransactionError::InvalidDeposit) {
             Ok(())
@@ -554,12 +890,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_withdrawal_into_a_deposit() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.00_f64));
         let deposit: Result<Deposit, TransactionError> = tx.try_into();
         if deposit == Err(TransactionError::InvalidDeposit) {
             Ok(())
@@ -572,12 +903,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_an_invalid_transaction_into_a_deposit() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, None);
         let deposit: Result<Deposit, TransactionError> = tx.try_into();
         if deposit == Err(TransactionError::InvalidDeposit) {
             Ok(())
@@ -590,12 +916,7 @@ mod tests {
 
     #[test]
     fn it_converts_a_transaction_to_a_withdrawal() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.00_f64));
         let withdrawal: Withdrawal = tx.try_into()?;
         assert_eq!(
             withdrawal,
@@ -613,12 +934,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_transaction_with_negative_amount_to_a_withdrawal() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(-1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(-1.00_f64));
         let withdrawal: Result<Withdrawal, TransactionError> = tx.try_into();
         if withdrawal == Err(TransactionError::InvalidWithdrawal) {
             Ok(())
@@ -631,12 +947,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_deposit_into_a_withdrawal() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Deposit,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Deposit, 1, 1, Some(1.00_f64));
         let withdrawal: Result<Withdrawal, TransactionError> = tx.try_into();
         if withdrawal == Err(TransactionError::InvalidWithdrawal) {
             Ok(())
@@ -649,12 +960,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_an_invalid_transaction_into_a_withdrawal() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, None);
         let withdrawal: Result<Withdrawal, TransactionError> = tx.try_into();
         if withdrawal == Err(TransactionError::InvalidWithdrawal) {
             Ok(())
@@ -667,12 +973,7 @@ mod tests {
 
     #[test]
     fn it_converts_a_transaction_to_a_dispute() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Dispute,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
         let dispute: Dispute = tx.try_into()?;
         assert_eq!(
             dispute,
@@ -686,12 +987,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_withdrawal_into_a_dispute() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Withdrawal,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Withdrawal, 1, 1, Some(1.00_f64));
         let dispute: Result<Dispute, TransactionError> = tx.try_into();
         if dispute == Err(TransactionError::InvalidDispute) {
             Ok(())
@@ -704,12 +1000,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_an_invalid_transaction_into_a_dispute() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Dispute,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, Some(1.00_f64));
         let dispute: Result<Dispute, TransactionError> = tx.try_into();
         if dispute == Err(TransactionError::InvalidDispute) {
             Ok(())
@@ -722,12 +1013,7 @@ mod tests {
 
     #[test]
     fn it_converts_a_transaction_to_a_resolve() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Resolve,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None);
         let resolve: Resolve = tx.try_into()?;
         assert_eq!(
             resolve,
@@ -741,12 +1027,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_dispute_into_a_resolve() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Dispute,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Dispute, 1, 1, None);
         let resolve: Result<Resolve, TransactionError> = tx.try_into();
         if resolve == Err(TransactionError::InvalidResolve) {
             Ok(())
@@ -759,12 +1040,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_an_invalid_transaction_into_a_resolve() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Resolve,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, Some(1.00_f64));
         let resolve: Result<Resolve, TransactionError> = tx.try_into();
         if resolve == Err(TransactionError::InvalidResolve) {
             Ok(())
@@ -777,12 +1053,7 @@ mod tests {
 
     #[test]
     fn it_converts_a_transaction_to_a_chargeback() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Chargeback,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, None);
         let chargeback: Chargeback = tx.try_into()?;
         assert_eq!(
             chargeback,
@@ -796,12 +1067,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_a_resolve_into_a_chargeback() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Resolve,
-            client_id: 1,
-            tx_id: 1,
-            amount: None,
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Resolve, 1, 1, None);
         let chargeback: Result<Chargeback, TransactionError> = tx.try_into();
         if chargeback == Err(TransactionError::InvalidChargeback) {
             Ok(())
@@ -814,12 +1080,7 @@ mod tests {
 
     #[test]
     fn it_fails_to_convert_an_invalid_transaction_into_a_chargeback() -> Result<()> {
-        let tx = RawTransaction {
-            variant: RawTransactionVariant::Chargeback,
-            client_id: 1,
-            tx_id: 1,
-            amount: Some(1.00_f64),
-        };
+        let tx = RawTransaction::new(RawTransactionVariant::Chargeback, 1, 1, Some(1.00_f64));
         let chargeback: Result<Chargeback, TransactionError> = tx.try_into();
         if chargeback == Err(TransactionError::InvalidChargeback) {
             Ok(())
@@ -0,0 +1,222 @@
+//! This module holds the `VelocityRule`s checked against each client's recent activity, such as
+//! "more than 3 withdrawals in 10 transactions" or "deposits totalling over 10000 in a day".
+//!
+//! Rules are given as `<deposit|withdrawal>:<count|total>:<threshold>:<window>:<verdict>`, where
+//! the window is parsed as a `Window` and the verdict is `flag`, `hold` or `reject`.  A rule
+//! matches a deposit or withdrawal when, counting the transaction itself, the number or the total
+//! of the transactions of its type inside the window goes over the threshold.
+
+use crate::rules::{ClientView, PendingTransaction, Rule, Verdict};
+use crate::transaction::RawTransactionVariant;
+use crate::window::{ActivityLog, Window};
+use serde::Deserialize;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An error type for the velocity module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum VelocityError {
+    /// Triggered if a rule cannot be parsed
+    #[error("Invalid velocity rule: {0}")]
+    InvalidRule(String),
+}
+
+/// What a velocity rule measures over its window.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Measure {
+    /// The number of transactions, matching when it goes over the given count.
+    Count(u64),
+    /// The sum of the amounts, matching when it goes over the given total.
+    Total(f64),
+}
+
+/// A rule on the velocity of a client's deposits or withdrawals.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct VelocityRule {
+    /// The type of transaction the rule applies to
+    #[serde(rename = "type")]
+    pub variant: RawTransactionVariant,
+    /// What the rule measures, and its threshold
+    pub measure: Measure,
+    /// The window the rule is checked over
+    pub window: Window,
+    /// The verdict for a matching transaction
+    pub verdict: Verdict,
+}
+
+impl VelocityRule {
+    /// Creates a new `VelocityRule`.
+    #[inline]
+    #[must_use]
+    pub const fn new(
+        variant: RawTransactionVariant,
+        measure: Measure,
+        window: Window,
+        verdict: Verdict,
+    ) -> Self {
+        Self {
+            variant,
+            measure,
+            window,
+            verdict,
+        }
+    }
+
+    /// Whether a transaction of the given type and amount, numbered `now_seq` at time `now_at`,
+    /// matches the rule given the client's earlier activity.
+    #[inline]
+    #[must_use]
+    pub fn matches(
+        &self,
+        activity: &ActivityLog,
+        now_seq: u64,
+        now_at: Option<u64>,
+        variant: RawTransactionVariant,
+        amount: f64,
+    ) -> bool {
+        if variant != self.variant {
+            return false;
+        }
+        match self.measure {
+            Measure::Count(count) => {
+                let earlier = activity
+                    .in_window(self.window, now_seq, now_at, variant)
+                    .count();
+                u64::try_from(earlier).map_or(true, |n| n.saturating_add(1) > count)
+            }
+            Measure::Total(total) => {
+                activity.total_in_window(self.window, now_seq, now_at, variant) + amount > total
+            }
+        }
+    }
+}
+
+impl Rule for VelocityRule {
+    #[inline]
+    fn name(&self) -> &'static str {
+        "velocity"
+    }
+
+    #[inline]
+    fn evaluate(&self, client: &ClientView<'_>, transaction: &PendingTransaction) -> Verdict {
+        if self.matches(
+            client.activity,
+            client.sequence,
+            client.clock,
+            transaction.variant,
+            transaction.amount,
+        ) {
+            self.verdict
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+impl FromStr for VelocityRule {
+    type Err = VelocityError;
+
+    /// Parses `<deposit|withdrawal>:<count|total>:<threshold>:<window>:<flag|hold|reject>`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || VelocityError::InvalidRule(s.to_owned());
+        let lower = s.trim().to_ascii_lowercase();
+        let parts: Vec<&str> = lower.split(':').map(str::trim).collect();
+        let [variant, measure, threshold, window, verdict] = parts.as_slice() else {
+            return Err(invalid());
+        };
+        let variant = match *variant {
+            "deposit" => RawTransactionVariant::Deposit,
+            "withdrawal" => RawTransactionVariant::Withdrawal,
+            _ => return Err(invalid()),
+        };
+        let measure = match *measure {
+            "count" => Measure::Count(threshold.parse().ok().ok_or_else(invalid)?),
+            "total" => match threshold.parse::<f64>() {
+                Ok(t) if t.is_finite() && t >= 0_f64 => Measure::Total(t),
+                _ => return Err(invalid()),
+            },
+            _ => return Err(invalid()),
+        };
+        let window = window.parse().ok().ok_or_else(invalid)?;
+        let verdict = match *verdict {
+            "flag" => Verdict::Flag,
+            "hold" => Verdict::Hold,
+            "reject" => Verdict::Reject,
+            _ => return Err(invalid()),
+        };
+        Ok(Self::new(variant, measure, window, verdict))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::Activity;
+
+    #[test]
+    fn it_parses_velocity_rules() {
+        assert_eq!(
+            "withdrawal:count:3:10:flag".parse(),
+            Ok(VelocityRule::new(
+                RawTransactionVariant::Withdrawal,
+                Measure::Count(3),
+                Window::Transactions(10),
+                Verdict::Flag,
+            ))
+        );
+        assert_eq!(
+            "Deposit:total:10000:day:hold".parse(),
+            Ok(VelocityRule::new(
+                RawTransactionVariant::Deposit,
+                Measure::Total(10_000.0),
+                Window::Day,
+                Verdict::Hold,
+            ))
+        );
+        assert_eq!(
+            "dispute:count:3:10:flag".parse::<VelocityRule>(),
+            Err(VelocityError::InvalidRule(
+                "dispute:count:3:10:flag".to_owned()
+            ))
+        );
+        assert!("withdrawal:count:3:10".parse::<VelocityRule>().is_err());
+    }
+
+    #[test]
+    fn it_matches_when_the_window_goes_over_the_threshold() {
+        let mut log = ActivityLog::new(10);
+        for seq in 1..=3 {
+            log.push(Activity {
+                seq,
+                at: None,
+                tx_id: 0,
+                variant: RawTransactionVariant::Withdrawal,
+                amount: 100.0,
+            });
+        }
+        let withdrawal = RawTransactionVariant::Withdrawal;
+        let count = VelocityRule::new(
+            withdrawal,
+            Measure::Count(3),
+            Window::Transactions(10),
+            Verdict::Flag,
+        );
+        assert!(count.matches(&log, 4, None, withdrawal, 1.0));
+        assert!(!count.matches(&log, 4, None, RawTransactionVariant::Deposit, 1.0));
+        assert!(!count.matches(&log, 13, None, withdrawal, 1.0));
+
+        let total = VelocityRule::new(
+            withdrawal,
+            Measure::Total(350.0),
+            Window::Dispute,
+            Verdict::Hold,
+        );
+        assert!(!total.matches(&log, 4, None, withdrawal, 50.0));
+        assert!(total.matches(&log, 4, None, withdrawal, 50.5));
+    }
+}
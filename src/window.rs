@@ -0,0 +1,261 @@
+//! This module holds the `Window` over a client's recent activity that limits and rules are
+//! evaluated against, and the `ActivityLog` of accepted deposits and withdrawals they look at.
+//!
+//! A window is either a number of transactions, counted like the dispute window, the dispute window
+//! itself, or a span of time.  Time windows use the optional `timestamp` of each transaction.  A
+//! transaction without a timestamp is taken to happen at the time of the latest timestamped
+//! transaction before it.
+//!
+//! If no transaction so far had a timestamp, a time window cannot be measured and every entry is
+//! taken to be inside it, so it covers the same activity as the dispute window: on input without
+//! timestamps, a daily withdrawal cap limits what is withdrawn over the client's recent activity
+//! kept in the `ActivityLog`, not over a day.
+
+use crate::transaction::RawTransactionVariant;
+use serde::{Deserialize, Deserializer};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// The number of seconds in a day.
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// An error type for the window module.
+#[derive(Debug, Error, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WindowError {
+    /// Triggered if a window cannot be parsed
+    #[error("Invalid window: {0}")]
+    InvalidWindow(String),
+}
+
+/// A window over a client's recent activity.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// The last `n` transactions processed for the client, including the current one.
+    Transactions(u64),
+    /// The last `n` seconds, up to and including the current transaction.
+    Seconds(u64),
+    /// The calendar day (UTC) of the current transaction.
+    Day,
+    /// The deposits and withdrawals still in the dispute window.
+    Dispute,
+}
+
+impl Window {
+    /// Whether an entry processed as transaction number `seq` at time `at` is inside the window of
+    /// the transaction numbered `now_seq` at time `now_at`.  Without both times, an entry is always
+    /// inside a time window.
+    #[inline]
+    #[must_use]
+    pub const fn contains(
+        self,
+        now_seq: u64,
+        now_at: Option<u64>,
+        seq: u64,
+        at: Option<u64>,
+    ) -> bool {
+        match (self, now_at, at) {
+            (Self::Transactions(n), _, _) => now_seq.saturating_sub(seq) < n,
+            (Self::Seconds(n), Some(now), Some(then)) => now.saturating_sub(then) < n,
+            (Self::Day, Some(now), Some(then)) => {
+                now.div_euclid(SECONDS_PER_DAY) == then.div_euclid(SECONDS_PER_DAY)
+            }
+            (Self::Seconds(_) | Self::Day | Self::Dispute, _, _) => true,
+        }
+    }
+}
+
+impl FromStr for Window {
+    type Err = WindowError;
+
+    /// Parses `day` (or `daily`), `dispute` for the dispute window, `<n>s` for a number of seconds,
+    /// `<n>d` for a number of days, or `<n>` (or `<n>tx`) for a number of transactions.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WindowError::InvalidWindow(s.to_owned());
+        let lower = s.trim().to_ascii_lowercase();
+        if lower == "day" || lower == "daily" {
+            return Ok(Self::Day);
+        }
+        if lower == "dispute" {
+            return Ok(Self::Dispute);
+        }
+        if let Some(seconds) = lower.strip_suffix('s') {
+            return seconds.parse().map(Self::Seconds).ok().ok_or_else(invalid);
+        }
+        if let Some(days) = lower.strip_suffix('d') {
+            return days
+                .parse::<u64>()
+                .ok()
+                .and_then(|d| d.checked_mul(SECONDS_PER_DAY))
+                .map(Self::Seconds)
+                .ok_or_else(invalid);
+        }
+        lower
+            .strip_suffix("tx")
+            .unwrap_or(&lower)
+            .parse()
+            .map(Self::Transactions)
+            .ok()
+            .ok_or_else(invalid)
+    }
+}
+
+impl<'de> Deserialize<'de> for Window {
+    /// Deserializes a window from the same text `from_str` parses.
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// An accepted deposit or withdrawal, as recorded in the `ActivityLog`.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Activity {
+    /// The number of the transaction among those processed for the client
+    pub seq: u64,
+    /// When the transaction happened, if known
+    pub at: Option<u64>,
+    /// The ID of the transaction
+    pub tx_id: u32,
+    /// The type of the transaction
+    pub variant: RawTransactionVariant,
+    /// The amount of the transaction
+    pub amount: f64,
+}
+
+/// The recent deposits and withdrawals of a client, bounded to the size of the dispute window.
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct ActivityLog {
+    /// The most recent activity, oldest first
+    entries: VecDeque<Activity>,
+    /// The number of entries kept
+    capacity: usize,
+}
+
+impl ActivityLog {
+    /// Creates a new `ActivityLog` that keeps the last `capacity` entries.
+    #[inline]
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Records an accepted deposit or withdrawal, forgetting the oldest entry if the log is full.
+    #[inline]
+    pub fn push(&mut self, activity: Activity) {
+        if self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(activity);
+    }
+
+    /// Iterates over the entries of the given type inside `window`, for the transaction numbered
+    /// `now_seq` at time `now_at`.
+    #[inline]
+    pub fn in_window(
+        &self,
+        window: Window,
+        now_seq: u64,
+        now_at: Option<u64>,
+        variant: RawTransactionVariant,
+    ) -> impl Iterator<Item = &Activity> {
+        self.entries
+            .iter()
+            .filter(move |a| a.variant == variant && window.contains(now_seq, now_at, a.seq, a.at))
+    }
+
+    /// The sum of the amounts of the entries of the given type inside `window`.
+    #[inline]
+    #[must_use]
+    pub fn total_in_window(
+        &self,
+        window: Window,
+        now_seq: u64,
+        now_at: Option<u64>,
+        variant: RawTransactionVariant,
+    ) -> f64 {
+        self.in_window(window, now_seq, now_at, variant)
+            .fold(0_f64, |sum, a| sum + a.amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_parses_windows() {
+        assert_eq!("day".parse(), Ok(Window::Day));
+        assert_eq!("Daily".parse(), Ok(Window::Day));
+        assert_eq!("3600s".parse(), Ok(Window::Seconds(3600)));
+        assert_eq!("90d".parse(), Ok(Window::Seconds(7_776_000)));
+        assert_eq!("10".parse(), Ok(Window::Transactions(10)));
+        assert_eq!("10tx".parse(), Ok(Window::Transactions(10)));
+        assert_eq!("dispute".parse(), Ok(Window::Dispute));
+        assert_eq!(
+            "week".parse::<Window>(),
+            Err(WindowError::InvalidWindow("week".to_owned()))
+        );
+    }
+
+    #[test]
+    fn it_sums_the_activity_inside_a_window() {
+        let mut log = ActivityLog::new(3);
+        for (seq, at, amount) in [
+            (1, 86_000, 1.0_f64),
+            (2, 86_500, 2.0_f64),
+            (4, 87_000, 4.0_f64),
+            (5, 87_100, 8.0_f64),
+        ] {
+            log.push(Activity {
+                seq,
+                at: Some(at),
+                tx_id: 0,
+                variant: RawTransactionVariant::Withdrawal,
+                amount,
+            });
+        }
+        let withdrawal = RawTransactionVariant::Withdrawal;
+        // The first entry has been forgotten.
+        assert!(
+            (log.total_in_window(Window::Transactions(100), 6, None, withdrawal) - 14.0).abs()
+                < f64::EPSILON
+        );
+        assert!(
+            (log.total_in_window(Window::Transactions(3), 6, None, withdrawal) - 12.0).abs()
+                < f64::EPSILON
+        );
+        assert!(
+            (log.total_in_window(Window::Seconds(200), 6, Some(87_150), withdrawal) - 12.0).abs()
+                < f64::EPSILON
+        );
+        assert!(
+            (log.total_in_window(Window::Day, 6, Some(87_150), withdrawal) - 14.0).abs()
+                < f64::EPSILON
+        );
+        // Without a time, a time window covers everything still in the log.
+        assert!(
+            (log.total_in_window(Window::Day, 6, None, withdrawal) - 14.0).abs() < f64::EPSILON
+        );
+        assert!(
+            log.total_in_window(Window::Day, 6, Some(86_399), withdrawal)
+                .abs()
+                < f64::EPSILON
+        );
+        assert!(
+            log.total_in_window(Window::Day, 6, None, RawTransactionVariant::Deposit)
+                .abs()
+                < f64::EPSILON
+        );
+    }
+}